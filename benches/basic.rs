@@ -1,5 +1,9 @@
-use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
-use reddb::MemDb;
+use criterion::measurement::WallTime;
+use criterion::{
+    black_box, criterion_group, criterion_main, BenchmarkGroup, BenchmarkId, Criterion, Throughput,
+};
+use reddb::serializer::Serializer;
+use reddb::{BinDb, DbConfig, MemDb, MemStorage, RedDb, SyncMode};
 use serde::{Deserialize, Serialize};
 use tokio::runtime::Runtime;
 
@@ -44,6 +48,29 @@ fn bench_insert_batch(c: &mut Criterion) {
     group.finish();
 }
 
+/// Inserts into a file-backed database, which encodes each record into the
+/// log as well as into memory. Syncing is left to the OS so the time is
+/// spent encoding and writing rather than in fsync.
+fn bench_insert_file(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let mut group = c.benchmark_group("insert_file");
+    for size in [1u32, 100] {
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            let config = DbConfig::new(format!("insert_{size}"))
+                .dir(dir.path())
+                .sync_mode(SyncMode::Never);
+            let db = rt.block_on(BinDb::open::<Record>(config)).unwrap();
+            b.to_async(&rt).iter(|| async {
+                let records: Vec<Record> = (0..size).map(record).collect();
+                db.insert(records).await.unwrap();
+            });
+        });
+    }
+    group.finish();
+}
+
 // ── query ─────────────────────────────────────────────────────────────────────
 
 async fn seeded(n: u32) -> MemDb {
@@ -139,14 +166,115 @@ fn bench_index_lookup(c: &mut Criterion) {
     group.finish();
 }
 
+// ── serializers ───────────────────────────────────────────────────────────────
+
+const CODEC_RECORDS: u32 = 100;
+
+/// Encode or decode a batch of records. The parameter is the encoded size, so
+/// the report compares payload sizes as well as speed. Encoding is timed
+/// both into a fresh `Vec` and, as `<name>_into`, into a reused buffer the
+/// way the storages write their log.
+fn bench_codec<SE: Serializer>(group: &mut BenchmarkGroup<WallTime>, name: &str, decode: bool) {
+    let serializer = SE::default();
+    let records: Vec<Record> = (0..CODEC_RECORDS).map(record).collect();
+    let bytes = serializer.serialize(&records).unwrap();
+    let size = format!("{}B", bytes.len());
+    let id = BenchmarkId::new(name, &size);
+    if decode {
+        group.bench_function(id, |b| {
+            b.iter(|| {
+                serializer
+                    .deserialize_owned::<Vec<Record>>(black_box(&bytes))
+                    .unwrap()
+            })
+        });
+    } else {
+        group.bench_function(id, |b| {
+            b.iter(|| serializer.serialize(black_box(&records)).unwrap())
+        });
+        let mut buf = Vec::with_capacity(bytes.len());
+        group.bench_function(BenchmarkId::new(format!("{name}_into"), &size), |b| {
+            b.iter(|| {
+                buf.clear();
+                serializer
+                    .serialize_into(black_box(&records), &mut buf)
+                    .unwrap()
+            })
+        });
+    }
+}
+
+fn bench_serializers(c: &mut Criterion) {
+    for (op, decode) in [("encode", false), ("decode", true)] {
+        let mut group = c.benchmark_group(op);
+        group.throughput(Throughput::Elements(CODEC_RECORDS as u64));
+        #[cfg(feature = "bin_ser")]
+        bench_codec::<reddb::serializer::Bin>(&mut group, "bin", decode);
+        #[cfg(feature = "json_ser")]
+        bench_codec::<reddb::serializer::Json>(&mut group, "json", decode);
+        #[cfg(feature = "ron_ser")]
+        bench_codec::<reddb::serializer::Ron>(&mut group, "ron", decode);
+        #[cfg(feature = "yaml_ser")]
+        bench_codec::<reddb::serializer::Yaml>(&mut group, "yaml", decode);
+        #[cfg(feature = "msgpack_ser")]
+        bench_codec::<reddb::serializer::MsgPack>(&mut group, "msgpack", decode);
+        #[cfg(feature = "cbor_ser")]
+        bench_codec::<reddb::serializer::Cbor>(&mut group, "cbor", decode);
+        #[cfg(feature = "postcard_ser")]
+        bench_codec::<reddb::serializer::Postcard>(&mut group, "postcard", decode);
+        group.finish();
+    }
+}
+
+/// `find_all` decodes every document, so it shows what the format costs a
+/// whole database.
+fn bench_find_all_in<SE>(group: &mut BenchmarkGroup<WallTime>, rt: &Runtime, name: &str)
+where
+    SE: Serializer + std::fmt::Debug + Send + Sync + 'static,
+{
+    let db = rt.block_on(async {
+        let db = RedDb::<SE, MemStorage>::new::<Record>("_").await.unwrap();
+        db.insert((0..1000).map(record).collect()).await.unwrap();
+        db
+    });
+    group.bench_function(name, |b| {
+        b.to_async(rt)
+            .iter(|| async { db.find_all::<Record>().await.unwrap() });
+    });
+}
+
+fn bench_find_all_by_format(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("find_all_by_format");
+    group.throughput(Throughput::Elements(1000));
+    #[cfg(feature = "bin_ser")]
+    bench_find_all_in::<reddb::serializer::Bin>(&mut group, &rt, "bin");
+    #[cfg(feature = "json_ser")]
+    bench_find_all_in::<reddb::serializer::Json>(&mut group, &rt, "json");
+    #[cfg(feature = "ron_ser")]
+    bench_find_all_in::<reddb::serializer::Ron>(&mut group, &rt, "ron");
+    #[cfg(feature = "yaml_ser")]
+    bench_find_all_in::<reddb::serializer::Yaml>(&mut group, &rt, "yaml");
+    #[cfg(feature = "msgpack_ser")]
+    bench_find_all_in::<reddb::serializer::MsgPack>(&mut group, &rt, "msgpack");
+    #[cfg(feature = "cbor_ser")]
+    bench_find_all_in::<reddb::serializer::Cbor>(&mut group, &rt, "cbor");
+    #[cfg(feature = "postcard_ser")]
+    bench_find_all_in::<reddb::serializer::Postcard>(&mut group, &rt, "postcard");
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_insert_one,
     bench_insert_batch,
+    bench_insert_file,
     bench_find_all,
     bench_query_filter,
     bench_update_one,
     bench_delete_one,
     bench_index_lookup,
+    bench_serializers,
+    bench_find_all_by_format,
 );
 criterion_main!(benches);
@@ -0,0 +1,474 @@
+//! `reddb`: inspect and maintain database files from the command line
+//! (`cli` feature).
+//!
+//! Works on single-file databases, whatever their format: the serializer is
+//! read from the file header. Payloads are shown untyped, so only the
+//! self-describing formats can be decoded.
+
+use std::fmt::Debug;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use reddb::serializer::{Bin, Cbor, FormatId, Json, MsgPack, Postcard, Ron, Serializer, Yaml};
+use reddb::storage::inspect::{self, FileInfo, RawRecord};
+use reddb::storage::Storage;
+use reddb::{DbConfig, FileStorage, ProblemKind, RedDb, WalOp};
+
+#[derive(Parser)]
+#[command(
+    name = "reddb",
+    version,
+    about = "Inspect and maintain reddb database files"
+)]
+struct Cli {
+    /// File holding the 32-byte encryption key, raw or as 64 hex digits.
+    #[arg(long, global = true, value_name = "FILE")]
+    key_file: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show the header, record counts and live vs dead bytes.
+    Info { path: PathBuf },
+    /// Print every record as a line of JSON.
+    Dump {
+        path: PathBuf,
+        /// Show every payload as hex, even when it is text.
+        #[arg(long)]
+        hex: bool,
+    },
+    /// Read every record, past any damage, and report each problem found.
+    Verify { path: PathBuf },
+    /// Rewrite the file with one record per live document.
+    Compact { path: PathBuf },
+    /// Rebuild a damaged file from every record that can still be read,
+    /// moving the rest to `<file>.quarantine`.
+    Salvage { path: PathBuf },
+    /// Write the live documents to a new database in another format.
+    Convert {
+        path: PathBuf,
+        /// Format of the new database.
+        #[arg(long, value_enum)]
+        to: Format,
+        /// Name of the new database, in the same directory as the source.
+        /// Defaults to the name of the source.
+        #[arg(long)]
+        name: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Json,
+    Ron,
+    Yaml,
+    Bin,
+    #[value(name = "msgpack")]
+    MsgPack,
+    Cbor,
+    Postcard,
+}
+
+impl From<Format> for FormatId {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Json => FormatId::Json,
+            Format::Ron => FormatId::Ron,
+            Format::Yaml => FormatId::Yaml,
+            Format::Bin => FormatId::Bin,
+            Format::MsgPack => FormatId::MsgPack,
+            Format::Cbor => FormatId::Cbor,
+            Format::Postcard => FormatId::Postcard,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("reddb: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<ExitCode> {
+    let key = cli.key_file.as_deref().map(read_key).transpose()?;
+    match cli.command {
+        Command::Info { path } => {
+            let info = inspect::info(&path, &config(&path, key)?).await?;
+            print_info(&path, &info);
+        }
+        Command::Dump { path, hex } => dump(&path, &config(&path, key)?, hex).await?,
+        Command::Verify { path } => return verify(&path, &config(&path, key)?).await,
+        Command::Compact { path } => compact(&path, config(&path, key)?).await?,
+        Command::Salvage { path } => salvage(&path, config(&path, key)?).await?,
+        Command::Convert { path, to, name } => {
+            convert(&path, config(&path, key)?, to.into(), name).await?
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Config naming the database stored at `path`.
+fn config(path: &Path, key: Option<[u8; 32]>) -> Result<DbConfig> {
+    let name = path
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("{} is not a database file", path.display()))?;
+    let mut config = DbConfig::new(name).dir(path.parent().unwrap_or(Path::new("")));
+    if let Some(key) = key {
+        config = config.encryption_key(key);
+    }
+    Ok(config)
+}
+
+/// Refuse to open the file at `path` as a database unless its extension
+/// matches the format in its header, since that decides which file opens.
+/// Custom formats choose their own extension, so theirs is not checked.
+fn check_extension(path: &Path, format: FormatId) -> Result<()> {
+    if let FormatId::Custom(_) = format {
+        return Ok(());
+    }
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    if extension != &format.extension()[1..] {
+        bail!(
+            "{} holds a {} database; its name must end in {}",
+            path.display(),
+            format_name(format),
+            format.extension()
+        );
+    }
+    Ok(())
+}
+
+fn read_key(path: &Path) -> Result<[u8; 32]> {
+    let bytes =
+        std::fs::read(path).map_err(|e| anyhow::anyhow!("cannot read {}: {e}", path.display()))?;
+    if let Ok(key) = <[u8; 32]>::try_from(bytes.as_slice()) {
+        return Ok(key);
+    }
+    let hex = std::str::from_utf8(&bytes)
+        .ok()
+        .map(str::trim)
+        .filter(|hex| hex.len() == 64 && hex.is_ascii())
+        .context("key file must hold 32 bytes, raw or as 64 hex digits")?;
+    let mut key = [0; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+            .context("key file must hold 32 bytes, raw or as 64 hex digits")?;
+    }
+    Ok(key)
+}
+
+fn format_name(format: FormatId) -> String {
+    match format {
+        FormatId::Custom(tag) => format!("custom format {tag}"),
+        format => format.extension()[1..].to_string(),
+    }
+}
+
+/// Error for a command that needs the serializer of a custom format.
+fn needs_serializer(path: &Path, tag: u8) -> anyhow::Error {
+    anyhow::anyhow!(
+        "{} is in custom format {tag}; only a program with its serializer can rewrite it",
+        path.display()
+    )
+}
+
+fn op_name(op: WalOp) -> &'static str {
+    match op {
+        WalOp::Insert => "insert",
+        WalOp::Update => "update",
+        WalOp::Delete => "delete",
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn print_info(path: &Path, info: &FileInfo) {
+    let yes_no = |flag| if flag { "yes" } else { "no" };
+    println!("file:        {}", path.display());
+    println!("format:      {} (version 3)", format_name(info.format));
+    println!("checksums:   {}", yes_no(info.checksummed));
+    println!("compression: {:?}", info.compression);
+    println!("encrypted:   {}", yes_no(info.encrypted));
+    println!("lsn:         {} to {}", info.base_lsn, info.last_lsn);
+    println!("records:     {}", info.records);
+    println!("documents:   {}", info.documents);
+    println!("file bytes:  {}", info.file_bytes);
+    println!("live bytes:  {}", info.live_bytes);
+    println!("dead bytes:  {}", info.dead_bytes);
+    if info.truncated_tail_bytes > 0 {
+        println!(
+            "torn tail:   {} bytes, discarded on next open",
+            info.truncated_tail_bytes
+        );
+    }
+}
+
+/// The payload of `record` as a JSON value if the file is JSON, MessagePack
+/// or CBOR, as text for the other text formats and as hex otherwise.
+fn payload_field(
+    format: FormatId,
+    record: &RawRecord,
+    hex: bool,
+) -> (&'static str, serde_json::Value) {
+    if !hex && format == FormatId::Json {
+        if let Ok(value) = serde_json::from_slice(&record.payload) {
+            return ("payload", value);
+        }
+    }
+    if !hex && matches!(format, FormatId::MsgPack | FormatId::Cbor) {
+        let value = match format {
+            FormatId::MsgPack => MsgPack.deserialize_owned(&record.payload),
+            _ => Cbor.deserialize_owned(&record.payload),
+        };
+        if let Ok(value) = value {
+            return ("payload", value);
+        }
+    }
+    let binary = matches!(
+        format,
+        FormatId::Bin | FormatId::MsgPack | FormatId::Cbor | FormatId::Postcard
+    );
+    if !hex && !binary {
+        if let Ok(text) = std::str::from_utf8(&record.payload) {
+            return ("payload_text", text.into());
+        }
+    }
+    ("payload_hex", to_hex(&record.payload).into())
+}
+
+async fn dump(path: &Path, config: &DbConfig, hex: bool) -> Result<()> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    let format = inspect::format(path).await?;
+    inspect::scan(path, config, |record| {
+        let mut line = serde_json::json!({
+            "offset": record.offset,
+            "lsn": record.lsn,
+            "timestamp": record.timestamp,
+            "op": op_name(record.op),
+            "id": record.id.to_string(),
+        });
+        if record.op != WalOp::Delete {
+            let (key, value) = payload_field(format, &record, hex);
+            line[key] = value;
+        }
+        writeln!(out, "{line}")?;
+        Ok(())
+    })
+    .await?;
+    out.flush()?;
+    Ok(())
+}
+
+async fn verify(path: &Path, config: &DbConfig) -> Result<ExitCode> {
+    let verified = match inspect::format(path).await {
+        Ok(format) => {
+            let check = |payload: &[u8]| decode_untyped(format, payload).map_err(|e| e.to_string());
+            inspect::verify_file_with(path, config, &check)
+                .await
+                .map(|report| (format, report))
+        }
+        Err(e) => Err(e),
+    };
+    let (format, report) = match verified {
+        Ok(verified) => verified,
+        Err(e) => {
+            println!("{}: {e}", path.display());
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    for problem in &report.problems {
+        let id = problem
+            .id
+            .map_or_else(String::new, |id| format!("document {id}: "));
+        println!(
+            "offset {}: {}{} ({} bytes)",
+            problem.offset,
+            id,
+            describe(&problem.kind),
+            problem.len
+        );
+    }
+    match format {
+        FormatId::Bin | FormatId::Postcard => println!(
+            "{} payloads are not self-describing and were not decoded",
+            format_name(format)
+        ),
+        FormatId::Custom(_) => println!("payloads in a custom format were not decoded"),
+        _ => {}
+    }
+    println!(
+        "{}: {} records, {} documents, {} problems",
+        path.display(),
+        report.records,
+        report.documents,
+        report.problems.len()
+    );
+    Ok(if report.is_clean() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn describe(kind: &ProblemKind) -> String {
+    match kind {
+        ProblemKind::UnknownOp(op) => format!("unknown op byte {op:#04x}"),
+        ProblemKind::ChecksumMismatch => "checksum mismatch".into(),
+        ProblemKind::TruncatedFrame => "record runs past the end of the file".into(),
+        ProblemKind::UnreadablePayload => "payload cannot be decrypted or decompressed".into(),
+        ProblemKind::Undeserializable(e) => format!("payload does not decode: {e}"),
+        ProblemKind::UnknownDocument(op) => {
+            format!("{} of a document that does not exist", op_name(*op))
+        }
+    }
+}
+
+/// Check that `payload` is well-formed in `format`, without knowing the
+/// document type. Bin, Postcard and custom payloads cannot be checked and
+/// always pass.
+fn decode_untyped(format: FormatId, payload: &[u8]) -> anyhow::Result<()> {
+    match format {
+        FormatId::Json => Json.deserialize::<serde_json::Value>(payload).map(drop),
+        FormatId::Ron => Ron.deserialize::<ron::Value>(payload).map(drop),
+        FormatId::Yaml => Yaml.deserialize::<serde_yaml::Value>(payload).map(drop),
+        // Any value will do: MessagePack maps may have keys JSON cannot.
+        FormatId::MsgPack => Ok(rmp_serde::from_slice::<serde::de::IgnoredAny>(payload).map(drop)?),
+        FormatId::Cbor => Ok(ciborium::from_reader::<serde::de::IgnoredAny, _>(payload).map(drop)?),
+        FormatId::Bin | FormatId::Postcard | FormatId::Custom(_) => Ok(()),
+    }
+}
+
+async fn compact(path: &Path, config: DbConfig) -> Result<()> {
+    let before = inspect::info(path, &config).await?;
+    check_extension(path, before.format)?;
+    let config = config.compression(before.compression);
+    match before.format {
+        FormatId::Json => compact_as::<Json>(config).await?,
+        FormatId::Ron => compact_as::<Ron>(config).await?,
+        FormatId::Yaml => compact_as::<Yaml>(config).await?,
+        FormatId::Bin => compact_as::<Bin>(config).await?,
+        FormatId::MsgPack => compact_as::<MsgPack>(config).await?,
+        FormatId::Cbor => compact_as::<Cbor>(config).await?,
+        FormatId::Postcard => compact_as::<Postcard>(config).await?,
+        FormatId::Custom(tag) => return Err(needs_serializer(path, tag)),
+    }
+    let after = std::fs::metadata(path)?.len();
+    println!(
+        "{}: {} -> {} bytes",
+        path.display(),
+        before.file_bytes,
+        after
+    );
+    Ok(())
+}
+
+async fn compact_as<SE>(config: DbConfig) -> Result<()>
+where
+    SE: Serializer + Debug + Send + Sync + 'static,
+{
+    // Compaction copies payloads as stored; the document type is not used.
+    let db = RedDb::<SE, FileStorage<SE>>::open::<serde_json::Value>(config).await?;
+    db.compact().await?;
+    db.close().await?;
+    Ok(())
+}
+
+async fn salvage(path: &Path, config: DbConfig) -> Result<()> {
+    let format = inspect::format(path).await?;
+    check_extension(path, format)?;
+    let check = |payload: &[u8]| decode_untyped(format, payload).map_err(|e| e.to_string());
+    let report = match format {
+        FormatId::Json => FileStorage::<Json>::salvage(&config, &check).await?,
+        FormatId::Ron => FileStorage::<Ron>::salvage(&config, &check).await?,
+        FormatId::Yaml => FileStorage::<Yaml>::salvage(&config, &check).await?,
+        FormatId::Bin => FileStorage::<Bin>::salvage(&config, &check).await?,
+        FormatId::MsgPack => FileStorage::<MsgPack>::salvage(&config, &check).await?,
+        FormatId::Cbor => FileStorage::<Cbor>::salvage(&config, &check).await?,
+        FormatId::Postcard => FileStorage::<Postcard>::salvage(&config, &check).await?,
+        FormatId::Custom(tag) => return Err(needs_serializer(path, tag)),
+    };
+    for problem in &report.quarantined {
+        println!(
+            "offset {}: quarantined {} bytes: {}",
+            problem.offset,
+            problem.len,
+            describe(&problem.kind)
+        );
+    }
+    if let Some(quarantine) = &report.quarantine_file {
+        println!("quarantined bytes appended to {}", quarantine.display());
+    }
+    println!(
+        "{}: {} records recovered, {} documents",
+        path.display(),
+        report.records,
+        report.documents
+    );
+    Ok(())
+}
+
+async fn convert(path: &Path, src: DbConfig, to: FormatId, name: Option<String>) -> Result<()> {
+    let info = inspect::info(path, &src).await?;
+    check_extension(path, info.format)?;
+    let mut dst = src.clone().compression(info.compression);
+    if let Some(name) = name {
+        dst.name = name;
+    }
+    // Documents go through an untyped value, which only carries their shape
+    // over faithfully between JSON, YAML, MessagePack and CBOR. RON, Bin and
+    // Postcard encode structs differently from maps, which takes the document
+    // type to tell apart.
+    let count = match info.format {
+        from if from == to => bail!("{} is already {}", path.display(), format_name(to)),
+        FormatId::Json => convert_untyped::<Json>(&src, &dst, to).await?,
+        FormatId::Yaml => convert_untyped::<Yaml>(&src, &dst, to).await?,
+        FormatId::MsgPack => convert_untyped::<MsgPack>(&src, &dst, to).await?,
+        FormatId::Cbor => convert_untyped::<Cbor>(&src, &dst, to).await?,
+        from => return Err(needs_type(from, to)),
+    };
+    println!(
+        "{} documents written to {}{}",
+        count,
+        dst.file_stem().display(),
+        to.extension()
+    );
+    Ok(())
+}
+
+/// Convert a database written with `From` to `to` through untyped values.
+async fn convert_untyped<From>(src: &DbConfig, dst: &DbConfig, to: FormatId) -> Result<usize>
+where
+    From: Serializer + Debug + Send + Sync + 'static,
+{
+    use reddb::migrate::convert;
+    type Value = serde_json::Value;
+    Ok(match to {
+        FormatId::Json => convert::<Value, From, Json>(src, dst).await?,
+        FormatId::Yaml => convert::<Value, From, Yaml>(src, dst).await?,
+        FormatId::MsgPack => convert::<Value, From, MsgPack>(src, dst).await?,
+        FormatId::Cbor => convert::<Value, From, Cbor>(src, dst).await?,
+        to => return Err(needs_type(From::default().format_id(), to)),
+    })
+}
+
+fn needs_type(from: FormatId, to: FormatId) -> anyhow::Error {
+    anyhow::anyhow!(
+        "cannot convert {} to {} without the document type; \
+         call reddb::migrate::convert with it instead",
+        format_name(from),
+        format_name(to)
+    )
+}
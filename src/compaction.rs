@@ -0,0 +1,95 @@
+//! Background compaction task started by [`RedDb::open`](crate::RedDb::open)
+//! when [`DbConfig::auto_compaction`](crate::DbConfig::auto_compaction) is set.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use tokio::sync::{oneshot, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant, MissedTickBehavior};
+
+use crate::config::AutoCompaction;
+use crate::error::{RedDbError, Result};
+use crate::storage::{should_compact, Storage};
+use crate::RedDbHM;
+
+/// Handle to the running task. Dropping it stops the task once any compaction
+/// in progress has finished. The task only holds a weak reference to the
+/// storage between runs, so dropping the database releases the storage (and
+/// its file lock) without waiting for the task to notice.
+#[derive(Debug)]
+pub(crate) struct Compactor {
+    shutdown: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+    runs: Arc<AtomicU64>,
+}
+
+impl Compactor {
+    pub(crate) fn spawn<ST>(
+        settings: AutoCompaction,
+        ratio: f64,
+        storage: &Arc<ST>,
+        data: Arc<RwLock<RedDbHM>>,
+    ) -> Self
+    where
+        ST: Storage + Send + Sync + 'static,
+    {
+        let (shutdown, mut stop) = oneshot::channel();
+        let runs = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&runs);
+        let storage: Weak<ST> = Arc::downgrade(storage);
+        let handle = tokio::spawn(async move {
+            let mut ticker = time::interval(settings.check_interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // The first tick completes immediately; the store was just loaded.
+            ticker.tick().await;
+            let mut last_run = Instant::now();
+            loop {
+                tokio::select! {
+                    // Fires on an explicit stop and when the handle is dropped.
+                    _ = &mut stop => break,
+                    _ = ticker.tick() => {}
+                }
+                let Some(storage) = storage.upgrade() else {
+                    break;
+                };
+                let overdue = settings
+                    .max_interval
+                    .is_some_and(|max| last_run.elapsed() >= max);
+                // Failures are retried on the next tick.
+                if let Ok(true) = is_due(&*storage, &data, ratio, overdue).await {
+                    if storage.compact_online(&data).await.is_ok() {
+                        counter.fetch_add(1, Ordering::Relaxed);
+                        last_run = Instant::now();
+                    }
+                }
+            }
+        });
+        Compactor {
+            shutdown,
+            handle,
+            runs,
+        }
+    }
+
+    /// Number of compactions the task has completed.
+    pub(crate) fn runs(&self) -> u64 {
+        self.runs.load(Ordering::Relaxed)
+    }
+
+    /// Stop the task and wait for it to exit.
+    pub(crate) async fn stop(self) -> Result<()> {
+        let _ = self.shutdown.send(());
+        self.handle
+            .await
+            .map_err(|e| RedDbError::PersistFailed(e.to_string()))
+    }
+}
+
+async fn is_due<ST>(storage: &ST, data: &RwLock<RedDbHM>, ratio: f64, overdue: bool) -> Result<bool>
+where
+    ST: Storage + Sync,
+{
+    let live_size = storage.compacted_size(&*data.read().await);
+    let file_size = storage.file_size().await?;
+    Ok(should_compact(file_size, live_size, ratio) || (overdue && file_size > live_size))
+}
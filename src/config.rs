@@ -1,4 +1,7 @@
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::serializer::FormatId;
 
 /// Controls whether the in-memory store or the backing file is updated first
 /// on each write operation.
@@ -16,6 +19,138 @@ pub enum WriteOrder {
     FileFirst,
 }
 
+/// Payload compression used by the file-backed engines.
+///
+/// The codec is recorded in every file's header, so a file is always read back
+/// with the codec it was written with. Appends keep using the file's codec;
+/// the configured one takes over at the next compaction.
+///
+/// The variants available depend on the enabled features, so matches on it
+/// outside this crate need a wildcard arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Compression {
+    #[default]
+    None,
+    /// LZ4 block compression (`lz4_compression` feature).
+    #[cfg(feature = "lz4_compression")]
+    Lz4,
+    /// zstd at the given level (`zstd_compression` feature).
+    #[cfg(feature = "zstd_compression")]
+    Zstd(i32),
+}
+
+/// A 256-bit key for encrypting record payloads at rest
+/// (`encryption` feature). Its `Debug` output never shows the key bytes, it
+/// cannot be compared, and its bytes are wiped when it is dropped.
+#[cfg(feature = "encryption")]
+#[derive(Clone, zeroize::ZeroizeOnDrop)]
+pub struct EncryptionKey([u8; 32]);
+
+#[cfg(feature = "encryption")]
+impl EncryptionKey {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        EncryptionKey(bytes)
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl From<[u8; 32]> for EncryptionKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        EncryptionKey(bytes)
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// When the file-backed engines fsync appended records.
+///
+/// Writers that need an fsync at the same time share one (group commit), so
+/// `Always` costs one fsync per burst of concurrent writes rather than one per
+/// write. Under the other modes a crash can lose acknowledged writes that were
+/// not synced yet; [`RedDb::flush`](crate::RedDb::flush) forces a sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// Every write is durable when it returns.
+    #[default]
+    Always,
+    /// A write waits for an fsync once `n` records have been appended since
+    /// the last one.
+    EveryN(u64),
+    /// A background task fsyncs once per interval; writes return immediately.
+    Interval(Duration),
+    /// Records are only synced by `flush`, compaction and segment rollover.
+    Never,
+}
+
+/// Settings for the background compaction task, enabled with
+/// [`DbConfig::auto_compaction`].
+///
+/// Every `check_interval` the task compares the size of the store with the
+/// size of its live data and compacts once `compaction_ratio` is reached. With
+/// `max_interval` set it also compacts when that much time has passed since
+/// the last compaction and the store holds anything to reclaim.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoCompaction {
+    pub check_interval: Duration,
+    pub max_interval: Option<Duration>,
+}
+
+impl AutoCompaction {
+    pub fn new(check_interval: Duration) -> Self {
+        AutoCompaction {
+            check_interval,
+            max_interval: None,
+        }
+    }
+
+    pub fn max_interval(mut self, interval: Duration) -> Self {
+        self.max_interval = Some(interval);
+        self
+    }
+}
+
+/// Settings for paged mode (`paged` feature), enabled with
+/// [`DbConfig::paged`].
+///
+/// Instead of every payload, the in-memory map then only knows where each
+/// document's latest record sits in the log, and payloads are read back from
+/// the memory-mapped file when a document is accessed. Up to `cache_bytes`
+/// of recently read payloads are kept in an LRU cache.
+#[cfg(feature = "paged")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Paging {
+    pub cache_bytes: usize,
+}
+
+#[cfg(feature = "paged")]
+impl Paging {
+    pub fn new(cache_bytes: usize) -> Self {
+        Paging { cache_bytes }
+    }
+}
+
+/// A point in a database's history, set with [`DbConfig::as_of`].
+///
+/// Every record appended to a file-backed database gets a log sequence
+/// number, one higher than the record before it, and the time it was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// Just after the record with this sequence number.
+    Lsn(u64),
+    /// Just after the last record written at or before this time.
+    Time(SystemTime),
+}
+
 #[derive(Debug, Clone)]
 pub struct DbConfig {
     pub name: String,
@@ -24,6 +159,44 @@ pub struct DbConfig {
     /// Default: 2.0 — compact when file is 2× larger than live data.
     pub compaction_ratio: f64,
     pub write_order: WriteOrder,
+    /// Codec for record payloads in newly written files. Default: none.
+    pub compression: Compression,
+    /// Key that record payloads are encrypted with. A plaintext file opened
+    /// with a key is encrypted on open; an encrypted file needs the key it was
+    /// written with. Default: `None`.
+    #[cfg(feature = "encryption")]
+    pub encryption_key: Option<EncryptionKey>,
+    /// When appended records are fsynced. Default: [`SyncMode::Always`].
+    pub sync_mode: SyncMode,
+    /// Size in bytes at which [`SegmentedStorage`](crate::storage::SegmentedStorage)
+    /// closes the active WAL segment and starts a new one. Default: 64 MiB.
+    pub segment_size: u64,
+    /// Compact in a background task while the database is open.
+    /// Default: `None` — compaction only runs on open and on `RedDb::compact`.
+    pub auto_compaction: Option<AutoCompaction>,
+    /// Open an existing database for reading only: nothing is created,
+    /// repaired or compacted, writes fail with
+    /// [`RedDbError::ReadOnly`](crate::RedDbError::ReadOnly), and any number
+    /// of read-only handles can share the files with one writer. The handle
+    /// sees the data as of opening. Default: `false`.
+    pub read_only: bool,
+    /// Replay the log only up to this point, opening the database as it was
+    /// then. The handle is read-only whatever [`read_only`](Self::read_only)
+    /// says, and the files are left untouched. Honoured by the file-backed
+    /// engines, whose history reaches back to their last compaction. Default:
+    /// `None`.
+    pub as_of: Option<AsOf>,
+    /// Keep payloads on disk and only their location in memory, so the data
+    /// set may be larger than RAM. Supported by
+    /// [`FileStorage`](crate::storage::FileStorage); writes are always
+    /// [`WriteOrder::FileFirst`] in this mode. Default: `None`.
+    #[cfg(feature = "paged")]
+    pub paged: Option<Paging>,
+    /// The format an [`AnySerializer`](crate::serializer::AnySerializer)
+    /// reads and writes; other serializers ignore it.
+    /// [`AnyDb::open_path`](crate::AnyDb::open_path) sets it from the file
+    /// header. Default: `None`.
+    pub format: Option<FormatId>,
 }
 
 impl DbConfig {
@@ -33,6 +206,17 @@ impl DbConfig {
             dir: PathBuf::from("."),
             compaction_ratio: 2.0,
             write_order: WriteOrder::MemoryFirst,
+            compression: Compression::None,
+            #[cfg(feature = "encryption")]
+            encryption_key: None,
+            sync_mode: SyncMode::Always,
+            segment_size: 64 * 1024 * 1024,
+            auto_compaction: None,
+            read_only: false,
+            as_of: None,
+            #[cfg(feature = "paged")]
+            paged: None,
+            format: None,
         }
     }
 
@@ -51,6 +235,59 @@ impl DbConfig {
         self
     }
 
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn encryption_key(mut self, key: impl Into<EncryptionKey>) -> Self {
+        self.encryption_key = Some(key.into());
+        self
+    }
+
+    pub fn sync_mode(mut self, mode: SyncMode) -> Self {
+        self.sync_mode = mode;
+        self
+    }
+
+    pub fn segment_size(mut self, bytes: u64) -> Self {
+        self.segment_size = bytes;
+        self
+    }
+
+    pub fn auto_compaction(mut self, settings: AutoCompaction) -> Self {
+        self.auto_compaction = Some(settings);
+        self
+    }
+
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn as_of(mut self, point: AsOf) -> Self {
+        self.as_of = Some(point);
+        self
+    }
+
+    #[cfg(feature = "paged")]
+    pub fn paged(mut self, paging: Paging) -> Self {
+        self.paged = Some(paging);
+        self
+    }
+
+    pub fn format(mut self, format: FormatId) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Whether the database is opened for reading only, either explicitly or
+    /// because it is opened [`as_of`](Self::as_of) a past point.
+    pub(crate) fn opens_read_only(&self) -> bool {
+        self.read_only || self.as_of.is_some()
+    }
+
     pub fn file_stem(&self) -> PathBuf {
         self.dir.join(&self.name)
     }
@@ -102,6 +339,62 @@ mod tests {
         assert_eq!(cfg.compaction_ratio, 3.5);
     }
 
+    #[test]
+    fn default_compression_is_none() {
+        let cfg = DbConfig::new("mydb");
+        assert_eq!(cfg.compression, Compression::None);
+    }
+
+    #[cfg(feature = "zstd_compression")]
+    #[test]
+    fn builder_overrides_compression() {
+        let cfg = DbConfig::new("mydb").compression(Compression::Zstd(7));
+        assert_eq!(cfg.compression, Compression::Zstd(7));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn builder_sets_encryption_key() {
+        let cfg = DbConfig::new("mydb").encryption_key([7u8; 32]);
+        assert_eq!(
+            cfg.encryption_key.map(|key| *key.as_bytes()),
+            Some([7u8; 32])
+        );
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encryption_key_debug_is_redacted() {
+        let cfg = DbConfig::new("mydb").encryption_key([7u8; 32]);
+        let shown = format!("{cfg:?}");
+        assert!(shown.contains("EncryptionKey(..)"));
+        assert!(!shown.contains("7, 7"));
+    }
+
+    #[test]
+    fn default_sync_mode_is_always() {
+        let cfg = DbConfig::new("mydb");
+        assert_eq!(cfg.sync_mode, SyncMode::Always);
+    }
+
+    #[test]
+    fn builder_overrides_sync_mode() {
+        let cfg = DbConfig::new("mydb").sync_mode(SyncMode::EveryN(64));
+        assert_eq!(cfg.sync_mode, SyncMode::EveryN(64));
+    }
+
+    #[test]
+    fn default_segment_size_is_64_mib() {
+        let cfg = DbConfig::new("mydb");
+        assert_eq!(cfg.segment_size, 64 * 1024 * 1024);
+    }
+
+    #[test]
+    fn builder_overrides_segment_size() {
+        let cfg = DbConfig::new("mydb").segment_size(4096);
+        assert_eq!(cfg.segment_size, 4096);
+    }
+
     #[test]
     fn file_stem_joins_dir_and_name() {
         let cfg = DbConfig::new("users").dir("/data");
@@ -113,4 +406,55 @@ mod tests {
         let cfg = DbConfig::default();
         assert_eq!(cfg.name, "reddb");
     }
+
+    #[test]
+    fn auto_compaction_is_off_by_default() {
+        let cfg = DbConfig::new("mydb");
+        assert_eq!(cfg.auto_compaction, None);
+    }
+
+    #[test]
+    fn builder_enables_auto_compaction() {
+        let settings =
+            AutoCompaction::new(Duration::from_secs(30)).max_interval(Duration::from_secs(3600));
+        let cfg = DbConfig::new("mydb").auto_compaction(settings);
+        assert_eq!(
+            cfg.auto_compaction,
+            Some(AutoCompaction {
+                check_interval: Duration::from_secs(30),
+                max_interval: Some(Duration::from_secs(3600)),
+            })
+        );
+    }
+
+    #[test]
+    fn read_only_is_off_by_default() {
+        assert!(!DbConfig::new("mydb").read_only);
+        assert!(DbConfig::new("mydb").read_only(true).read_only);
+    }
+
+    #[cfg(feature = "paged")]
+    #[test]
+    fn builder_enables_paging() {
+        let cfg = DbConfig::new("mydb");
+        assert_eq!(cfg.paged, None);
+        let cfg = cfg.paged(Paging::new(1 << 20));
+        assert_eq!(
+            cfg.paged,
+            Some(Paging {
+                cache_bytes: 1 << 20
+            })
+        );
+    }
+
+    #[test]
+    fn as_of_opens_read_only() {
+        let cfg = DbConfig::new("mydb");
+        assert_eq!(cfg.as_of, None);
+        assert!(!cfg.opens_read_only());
+        let cfg = cfg.as_of(AsOf::Lsn(7));
+        assert_eq!(cfg.as_of, Some(AsOf::Lsn(7)));
+        assert!(!cfg.read_only);
+        assert!(cfg.opens_read_only());
+    }
 }
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+
+type Slot = Option<(Uuid, Vec<u8>)>;
+
+/// Serialized documents by id, kept in the order they were first inserted.
+///
+/// Entries live in a vector indexed by a hash map. A removal leaves an empty
+/// slot behind instead of shifting the rest down, and the slots are compacted
+/// once they outnumber the documents, so inserts, updates and removals all
+/// take amortized constant time. A document inserted again after its removal
+/// goes to the end.
+#[derive(Clone, Default)]
+pub struct Documents {
+    slots: Vec<Slot>,
+    index: HashMap<Uuid, usize>,
+}
+
+impl Documents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(capacity),
+            index: HashMap::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn contains_key(&self, id: &Uuid) -> bool {
+        self.index.contains_key(id)
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<&Vec<u8>> {
+        let slot = self.slots[*self.index.get(id)?].as_ref();
+        slot.map(|(_, raw)| raw)
+    }
+
+    pub fn get_mut(&mut self, id: &Uuid) -> Option<&mut Vec<u8>> {
+        let slot = self.slots[*self.index.get(id)?].as_mut();
+        slot.map(|(_, raw)| raw)
+    }
+
+    /// Where `id` sits in the order. Positions only compare within one
+    /// unchanged map: a removal may renumber the rest.
+    pub fn position(&self, id: &Uuid) -> Option<usize> {
+        self.index.get(id).copied()
+    }
+
+    /// Store `raw` under `id`, returning the payload it replaces. An update
+    /// keeps the document's place; a new document goes to the end.
+    pub fn insert(&mut self, id: Uuid, raw: Vec<u8>) -> Option<Vec<u8>> {
+        if let Some(&at) = self.index.get(&id) {
+            let (_, old) = self.slots[at].as_mut()?;
+            return Some(std::mem::replace(old, raw));
+        }
+        self.index.insert(id, self.slots.len());
+        self.slots.push(Some((id, raw)));
+        None
+    }
+
+    /// Remove the document `id` and return its payload. The documents after
+    /// it keep their order.
+    pub fn remove(&mut self, id: &Uuid) -> Option<Vec<u8>> {
+        let at = self.index.remove(id)?;
+        let (_, raw) = self.slots[at].take()?;
+        if self.slots.len() > 2 * self.index.len() {
+            self.compact();
+        }
+        Some(raw)
+    }
+
+    /// Keep only the documents `keep` returns `true` for, in one pass.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Uuid, &mut Vec<u8>) -> bool) {
+        for slot in &mut self.slots {
+            if let Some((id, raw)) = slot {
+                if !keep(id, raw) {
+                    self.index.remove(id);
+                    *slot = None;
+                }
+            }
+        }
+        self.compact();
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.index.clear();
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter(self.slots.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_> {
+        IterMut(self.slots.iter_mut())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Uuid> + '_ {
+        self.iter().map(|(id, _)| id)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Vec<u8>> + '_ {
+        self.iter().map(|(_, raw)| raw)
+    }
+
+    /// Drop the empty slots and point the index at the new positions.
+    fn compact(&mut self) {
+        if self.slots.len() == self.index.len() {
+            return;
+        }
+        self.slots.retain(Option::is_some);
+        for (at, (id, _)) in self.slots.iter().flatten().enumerate() {
+            self.index.insert(*id, at);
+        }
+    }
+}
+
+/// Equal when they hold the same documents in the same order.
+impl PartialEq for Documents {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for Documents {}
+
+impl fmt::Debug for Documents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl std::ops::Index<&Uuid> for Documents {
+    type Output = Vec<u8>;
+
+    /// The payload of `id`. Panics if there is no such document.
+    fn index(&self, id: &Uuid) -> &Vec<u8> {
+        self.get(id).expect("no document with this id")
+    }
+}
+
+impl Extend<(Uuid, Vec<u8>)> for Documents {
+    fn extend<I: IntoIterator<Item = (Uuid, Vec<u8>)>>(&mut self, iter: I) {
+        for (id, raw) in iter {
+            self.insert(id, raw);
+        }
+    }
+}
+
+impl FromIterator<(Uuid, Vec<u8>)> for Documents {
+    fn from_iter<I: IntoIterator<Item = (Uuid, Vec<u8>)>>(iter: I) -> Self {
+        let mut documents = Self::new();
+        documents.extend(iter);
+        documents
+    }
+}
+
+impl<const N: usize> From<[(Uuid, Vec<u8>); N]> for Documents {
+    fn from(entries: [(Uuid, Vec<u8>); N]) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+/// The documents of a [`Documents`], in order.
+pub struct Iter<'a>(std::slice::Iter<'a, Slot>);
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Uuid, &'a Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.by_ref().flatten().next().map(|(id, raw)| (id, raw))
+    }
+}
+
+impl<'a> IntoIterator for &'a Documents {
+    type Item = (&'a Uuid, &'a Vec<u8>);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// The documents of a [`Documents`], in order, with mutable payloads.
+pub struct IterMut<'a>(std::slice::IterMut<'a, Slot>);
+
+impl<'a> Iterator for IterMut<'a> {
+    type Item = (&'a Uuid, &'a mut Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0
+            .by_ref()
+            .flatten()
+            .next()
+            .map(|(id, raw)| (&*id, raw))
+    }
+}
+
+/// The documents of a [`Documents`], in order, by value.
+pub struct IntoIter(std::iter::Flatten<std::vec::IntoIter<Slot>>);
+
+impl Iterator for IntoIter {
+    type Item = (Uuid, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl IntoIterator for Documents {
+    type Item = (Uuid, Vec<u8>);
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        IntoIter(self.slots.into_iter().flatten())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(documents: &Documents) -> Vec<Uuid> {
+        documents.keys().copied().collect()
+    }
+
+    #[test]
+    fn updates_keep_their_place_and_reinserts_go_to_the_end() {
+        let [a, b, c] = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        let mut documents = Documents::from([(a, vec![1]), (b, vec![2]), (c, vec![3])]);
+        assert_eq!(documents.insert(a, vec![4]), Some(vec![1]));
+        assert_eq!(ids(&documents), [a, b, c]);
+        assert_eq!(documents.remove(&a), Some(vec![4]));
+        assert_eq!(documents.remove(&a), None);
+        documents.insert(a, vec![5]);
+        assert_eq!(ids(&documents), [b, c, a]);
+        assert_eq!(documents.get(&a), Some(&vec![5]));
+        assert_eq!(documents.len(), 3);
+    }
+
+    #[test]
+    fn removals_compact_the_slots_and_keep_the_order() {
+        let all: Vec<Uuid> = (0..100).map(|_| Uuid::new_v4()).collect();
+        let mut documents: Documents = all.iter().map(|id| (*id, id.as_bytes().to_vec())).collect();
+        for id in all.iter().step_by(2) {
+            documents.remove(id);
+        }
+        assert!(documents.slots.len() < 2 * documents.len() + 1);
+        let rest: Vec<Uuid> = all.iter().skip(1).step_by(2).copied().collect();
+        assert_eq!(ids(&documents), rest);
+        for id in &rest {
+            assert_eq!(documents.get(id), Some(&id.as_bytes().to_vec()));
+        }
+    }
+
+    #[test]
+    fn churn_does_not_grow_the_slots() {
+        let id = Uuid::new_v4();
+        let mut documents = Documents::from([(Uuid::new_v4(), Vec::new())]);
+        for _ in 0..1000 {
+            documents.insert(id, Vec::new());
+            documents.remove(&id);
+        }
+        assert!(documents.slots.len() <= 3);
+    }
+
+    #[test]
+    fn equality_follows_the_order() {
+        let [a, b] = [Uuid::new_v4(), Uuid::new_v4()];
+        let ab = Documents::from([(a, Vec::new()), (b, Vec::new())]);
+        let ba = Documents::from([(b, Vec::new()), (a, Vec::new())]);
+        assert_ne!(ab, ba);
+        assert_eq!(ab, ab.clone().into_iter().collect());
+    }
+}
@@ -1,6 +1,8 @@
 use thiserror::Error;
 use uuid::Uuid;
 
+use crate::serializer::FormatId;
+
 pub type Result<T> = std::result::Result<T, RedDbError>;
 
 #[derive(Debug, Error)]
@@ -20,8 +22,60 @@ pub enum RedDbError {
     #[error("lock poisoned")]
     LockPoisoned,
 
-    #[error("data corrupted")]
-    DataCorrupted,
+    #[error("not a reddb database file")]
+    NotADatabase,
+
+    #[error("file is shorter than its 32-byte header")]
+    TruncatedHeader,
+
+    #[error("file was written with format {found:?}, not {expected:?}")]
+    FormatMismatch {
+        /// The format of the serializer the database was opened with.
+        expected: FormatId,
+        /// The format recorded in the file header.
+        found: FormatId,
+    },
+
+    #[error("file header names unknown format {0}")]
+    UnknownFormat(u8),
+
+    #[error("file is in format {0:?}, whose serializer is not enabled in this build")]
+    FormatNotEnabled(FormatId),
+
+    #[error("file uses cipher {0}, which this build does not know")]
+    UnknownCipher(u8),
+
+    #[error("corrupted record at byte offset {0}")]
+    CorruptRecord(u64),
+
+    #[error("file format version {0} is not supported; version 2 files can be upgraded with migrate::upgrade_v2")]
+    UnsupportedVersion(u16),
+
+    #[error("file uses compression codec {0}, which is not enabled in this build")]
+    UnsupportedCompression(u8),
+
+    #[error("file is encrypted; open it with its encryption key")]
+    EncryptionKeyRequired,
+
+    #[error("encryption key does not match the key the file was written with")]
+    InvalidKey,
+
+    #[error("{0} is not supported by this storage backend")]
+    Unsupported(&'static str),
+
+    #[error("database is locked by {}: {path}", holder(*.pid))]
+    Locked {
+        /// The lock file.
+        path: String,
+        /// Id of the process holding the lock, if it could be read.
+        pid: Option<u32>,
+    },
+
+    #[error("history before sequence number {0} was removed by compaction")]
+    HistoryUnavailable(u64),
+
+    #[error("database was opened read-only")]
+    ReadOnly,
 
     #[error("persistence failed: {0}")]
     PersistFailed(String),
@@ -30,6 +84,14 @@ pub enum RedDbError {
     IndexNotFound(String),
 }
 
+fn holder(pid: Option<u32>) -> String {
+    match pid {
+        Some(pid) if pid == std::process::id() => format!("this process ({pid})"),
+        Some(pid) => format!("process {pid}"),
+        None => "another process".to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -67,9 +129,21 @@ mod tests {
     }
 
     #[test]
-    fn data_corrupted_displays() {
-        let err = RedDbError::DataCorrupted;
-        assert_eq!(err.to_string(), "data corrupted");
+    fn format_mismatch_names_both_formats() {
+        let err = RedDbError::FormatMismatch {
+            expected: FormatId::Json,
+            found: FormatId::Bin,
+        };
+        assert_eq!(
+            err.to_string(),
+            "file was written with format Bin, not Json"
+        );
+    }
+
+    #[test]
+    fn corrupt_record_displays_offset() {
+        let err = RedDbError::CorruptRecord(1024);
+        assert_eq!(err.to_string(), "corrupted record at byte offset 1024");
     }
 
     #[test]
@@ -83,4 +157,28 @@ mod tests {
         let err = RedDbError::IndexNotFound("by_email".to_string());
         assert!(err.to_string().contains("by_email"));
     }
+
+    #[test]
+    fn invalid_key_is_distinct_from_corruption() {
+        let err = RedDbError::InvalidKey;
+        assert!(err.to_string().contains("encryption key"));
+        assert!(!matches!(err, RedDbError::UnknownCipher(_)));
+    }
+
+    #[test]
+    fn locked_names_the_holder() {
+        let err = RedDbError::Locked {
+            path: "db.json.lock".to_string(),
+            pid: Some(4242),
+        };
+        assert_eq!(
+            err.to_string(),
+            "database is locked by process 4242: db.json.lock"
+        );
+        let err = RedDbError::Locked {
+            path: "db.json.lock".to_string(),
+            pid: None,
+        };
+        assert!(err.to_string().contains("another process"));
+    }
 }
@@ -0,0 +1,54 @@
+//! Background fsync task started by [`RedDb::open`](crate::RedDb::open) for
+//! [`SyncMode::Interval`](crate::SyncMode::Interval).
+
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::{self, MissedTickBehavior};
+
+use crate::error::{RedDbError, Result};
+use crate::storage::Storage;
+
+/// Handle to the running task. Dropping it stops the task after the fsync in
+/// progress, if any. Like the compactor, it only holds the storage weakly.
+#[derive(Debug)]
+pub(crate) struct Flusher {
+    shutdown: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl Flusher {
+    pub(crate) fn spawn<ST>(interval: Duration, storage: &Arc<ST>) -> Self
+    where
+        ST: Storage + Send + Sync + 'static,
+    {
+        let (shutdown, mut stop) = oneshot::channel();
+        let storage: Weak<ST> = Arc::downgrade(storage);
+        let handle = tokio::spawn(async move {
+            let mut ticker = time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            ticker.tick().await;
+            loop {
+                tokio::select! {
+                    _ = &mut stop => break,
+                    _ = ticker.tick() => {}
+                }
+                let Some(storage) = storage.upgrade() else {
+                    break;
+                };
+                // Failures are retried on the next tick.
+                let _ = storage.flush().await;
+            }
+        });
+        Flusher { shutdown, handle }
+    }
+
+    /// Stop the task and wait for it to exit.
+    pub(crate) async fn stop(self) -> Result<()> {
+        let _ = self.shutdown.send(());
+        self.handle
+            .await
+            .map_err(|e| RedDbError::PersistFailed(e.to_string()))
+    }
+}
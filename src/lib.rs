@@ -1,38 +1,59 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
 use std::fmt::Debug;
+use std::path::{self, Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 pub use uuid::Uuid;
 
+mod compaction;
 mod config;
 mod document;
+mod documents;
 mod error;
+mod flusher;
 mod index;
 #[cfg(feature = "migrate")]
 pub mod migrate;
 mod query;
 pub mod serializer;
-mod storage;
+pub mod storage;
 mod transaction;
 mod update;
 mod wal;
 
-pub use config::{DbConfig, WriteOrder};
+use compaction::Compactor;
+#[cfg(feature = "encryption")]
+pub use config::EncryptionKey;
+#[cfg(feature = "paged")]
+pub use config::Paging;
+pub use config::{AsOf, AutoCompaction, Compression, DbConfig, SyncMode, WriteOrder};
 pub use document::Document;
-use error::{RedDbError, Result};
+pub use documents::Documents;
+pub use error::RedDbError;
+use error::Result;
+use flusher::Flusher;
 use index::IndexRegistry;
 pub use query::QueryBuilder;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serializer::Serializer;
+pub use storage::BackupReport;
 pub use storage::FileStorage;
 pub use storage::MemStorage;
+pub use storage::SegmentedStorage;
 use storage::Storage;
+pub use storage::{Problem, ProblemKind, SalvageReport, VerifyReport};
 pub use transaction::Transaction;
 pub use update::UpdateWhereBuilder;
-use wal::WalOp;
+pub use wal::WalOp;
 
-type RedDbHM = HashMap<Uuid, Vec<u8>>;
+/// The in-memory document store: document id → serialized payload.
+///
+/// Documents are kept in the order they were first inserted: an update keeps
+/// a document's place and a delete closes the gap. Iteration, queries without
+/// `order_by` and compaction all follow that order, which survives reopening.
+pub type RedDbHM = Documents;
 
 #[cfg(feature = "bin_ser")]
 pub type BinDb = RedDb<serializer::Bin, FileStorage<serializer::Bin>>;
@@ -42,6 +63,15 @@ pub type JsonDb = RedDb<serializer::Json, FileStorage<serializer::Json>>;
 pub type YamlDb = RedDb<serializer::Yaml, FileStorage<serializer::Yaml>>;
 #[cfg(feature = "ron_ser")]
 pub type RonDb = RedDb<serializer::Ron, FileStorage<serializer::Ron>>;
+#[cfg(feature = "msgpack_ser")]
+pub type MsgPackDb = RedDb<serializer::MsgPack, FileStorage<serializer::MsgPack>>;
+#[cfg(feature = "cbor_ser")]
+pub type CborDb = RedDb<serializer::Cbor, FileStorage<serializer::Cbor>>;
+#[cfg(feature = "postcard_ser")]
+pub type PostcardDb = RedDb<serializer::Postcard, FileStorage<serializer::Postcard>>;
+/// A file-backed database of any built-in format, chosen when it is opened
+/// with [`open_path`](RedDb::open_path).
+pub type AnyDb = RedDb<serializer::AnySerializer, FileStorage<serializer::AnySerializer>>;
 
 /// All-in-memory database with no file persistence. Uses the Bin serializer
 /// for the internal byte representation; the format does not affect behaviour.
@@ -57,16 +87,34 @@ pub struct StorageStats {
     pub live_document_count: usize,
     /// Configured compaction ratio (compact when file ≥ live × ratio).
     pub compaction_ratio: f64,
+    /// Bytes of a partially written trailing record that were truncated when
+    /// the file was opened (0 after a clean shutdown).
+    pub truncated_tail_bytes: u64,
+    /// Compactions completed by the background task (0 when
+    /// [`DbConfig::auto_compaction`] is off).
+    pub auto_compactions: u64,
+    /// Sequence number of the last record written, or of the last one
+    /// replayed for a read-only handle; a point to pass to [`AsOf::Lsn`].
+    /// 0 for [`MemStorage`].
+    pub last_lsn: u64,
 }
 
 pub struct RedDb<SE, ST> {
-    storage: ST,
-    serializer: SE,
+    storage: Arc<ST>,
+    serializer: Arc<SE>,
     data: Arc<RwLock<RedDbHM>>,
     pub(crate) write_order: WriteOrder,
     compaction_ratio: f64,
     pub(crate) indexes: Arc<RwLock<IndexRegistry>>,
     has_indexes: AtomicBool,
+    compactor: Option<Compactor>,
+    flusher: Option<Flusher>,
+    read_only: bool,
+    /// Opened in paged mode: map entries are empty and payloads are
+    /// fetched from the storage.
+    pub(crate) paged: bool,
+    /// Absolute [`DbConfig::file_stem`] the database was opened from.
+    file_stem: PathBuf,
 }
 
 impl<SE: Debug, ST: Debug> Debug for RedDb<SE, ST> {
@@ -80,7 +128,6 @@ impl<SE: Debug, ST: Debug> Debug for RedDb<SE, ST> {
     }
 }
 
-#[allow(private_bounds)]
 impl<SE, ST: 'static> RedDb<SE, ST>
 where
     SE: Serializer + Debug,
@@ -91,17 +138,45 @@ where
     where
         for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq + Send + Sync,
     {
-        let stem = config.file_stem().to_string_lossy().into_owned();
-        let storage = ST::new(&stem, config.compaction_ratio).await?;
-        let data = storage.load::<T>().await?;
+        let storage = Arc::new(ST::new(&config).await?);
+        let data = Arc::new(RwLock::new(storage.load::<T>().await?));
+        let writable = !config.opens_read_only();
+        let compactor = config.auto_compaction.filter(|_| writable).map(|settings| {
+            Compactor::spawn(
+                settings,
+                config.compaction_ratio,
+                &storage,
+                Arc::clone(&data),
+            )
+        });
+        let flusher = match config.sync_mode {
+            SyncMode::Interval(interval) if writable => Some(Flusher::spawn(interval, &storage)),
+            _ => None,
+        };
+        #[cfg(feature = "paged")]
+        let paged = config.paged.is_some();
+        #[cfg(not(feature = "paged"))]
+        let paged = false;
+        // A paged map entry is only a placeholder for a record, so the
+        // record has to be written first.
+        let write_order = if paged {
+            WriteOrder::FileFirst
+        } else {
+            config.write_order
+        };
         Ok(Self {
             storage,
-            data: Arc::new(RwLock::new(data)),
-            serializer: SE::default(),
-            write_order: config.write_order,
+            data,
+            serializer: Arc::new(SE::for_config(&config)?),
+            write_order,
             compaction_ratio: config.compaction_ratio,
             indexes: Arc::new(RwLock::new(IndexRegistry::new())),
             has_indexes: AtomicBool::new(false),
+            compactor,
+            flusher,
+            read_only: config.opens_read_only(),
+            paged,
+            file_stem: path::absolute(config.file_stem())?,
         })
     }
 
@@ -113,13 +188,41 @@ where
         Self::open::<T>(DbConfig::new(db_name)).await
     }
 
+    /// Open the database `config` describes as it was at `point`, replaying
+    /// its log only that far. Equivalent to `open(config.as_of(point))`: the
+    /// handle is read-only and the files are left as they are. Fails with
+    /// [`RedDbError::HistoryUnavailable`] if compaction has removed the
+    /// records from before `point`.
+    pub async fn open_as_of<T>(config: DbConfig, point: AsOf) -> Result<Self>
+    where
+        for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq + Send + Sync,
+    {
+        Self::open::<T>(config.as_of(point)).await
+    }
+
+    /// Open the database `config` describes after [salvaging](Self::salvage)
+    /// it, for a database that no longer opens because of damage in the
+    /// middle of its log.
+    pub async fn open_salvaged<T>(config: DbConfig) -> Result<(Self, SalvageReport)>
+    where
+        for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq + Send + Sync,
+    {
+        let report = Self::salvage::<T>(config.clone()).await?;
+        Ok((Self::open::<T>(config).await?, report))
+    }
+
     // ── lock helpers ──────────────────────────────────────────────────────────
 
     pub(crate) async fn read_lock(&self) -> Result<RwLockReadGuard<'_, RedDbHM>> {
         Ok(self.data.read().await)
     }
 
+    /// Every write goes through here, so read-only handles are refused
+    /// before anything changes.
     pub(crate) async fn write_lock(&self) -> Result<RwLockWriteGuard<'_, RedDbHM>> {
+        if self.read_only {
+            return Err(RedDbError::ReadOnly);
+        }
         Ok(self.data.write().await)
     }
 
@@ -127,20 +230,38 @@ where
 
     pub(crate) fn deserialize_raw<T>(&self, raw: &[u8]) -> Result<T>
     where
-        for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq,
+        T: DeserializeOwned,
     {
         self.deserialize(raw)
     }
 
     pub(crate) fn serialize_raw<T>(&self, value: &T) -> Result<Vec<u8>>
     where
-        for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq,
+        T: Serialize + ?Sized,
     {
         self.serialize(value)
     }
 
     // ── storage helpers ───────────────────────────────────────────────────────
 
+    /// The payload of document `id`, whose map entry is `raw`. In paged mode
+    /// the entry is empty and the payload is fetched from the storage.
+    pub(crate) fn payload<'a>(&self, id: &Uuid, raw: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        if self.paged {
+            return Ok(Cow::Owned(self.storage.fetch(id)?.to_vec()));
+        }
+        Ok(Cow::Borrowed(raw))
+    }
+
+    /// The map entry for a document just written with payload `raw`.
+    pub(crate) fn entry(&self, raw: &[u8]) -> Vec<u8> {
+        if self.paged {
+            Vec::new()
+        } else {
+            raw.to_vec()
+        }
+    }
+
     pub(crate) async fn storage_persist<T>(&self, docs: &[Document<T>], op: WalOp) -> Result<()>
     where
         for<'de> T: Serialize + Deserialize<'de> + Debug + Clone + Send + Sync,
@@ -208,19 +329,23 @@ where
     where
         for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq,
         F: Fn(&T) -> String + Send + Sync + 'static,
+        SE: 'static,
     {
         use index::IndexEntry;
 
+        let serializer = Arc::clone(&self.serializer);
         let extractor: index::ExtractorFn = Box::new(move |raw| {
-            let ser = SE::default();
-            ser.deserialize::<T>(raw).ok().map(|v| key_fn(&v))
+            serializer
+                .deserialize_owned::<T>(raw)
+                .ok()
+                .map(|v| key_fn(&v))
         });
 
         let initial_keys = {
             let data = self.read_lock().await?;
             let mut keys = std::collections::HashMap::new();
             for (id, raw) in data.iter() {
-                if let Some(key) = extractor(raw) {
+                if let Some(key) = extractor(&self.payload(id, raw)?) {
                     keys.entry(key).or_insert_with(Vec::new).push(*id);
                 }
             }
@@ -245,7 +370,7 @@ where
     where
         for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq,
     {
-        let ids: Vec<Uuid> = {
+        let mut ids: Vec<Uuid> = {
             let reg = self.indexes.read().await;
             let entry = reg
                 .entries
@@ -255,24 +380,158 @@ where
         };
 
         let data = self.read_lock().await?;
-        let docs = ids
-            .iter()
-            .filter_map(|id| {
-                data.get(id).and_then(|raw| {
-                    self.deserialize::<T>(raw)
-                        .ok()
-                        .map(|v| Document::new(*id, v))
-                })
-            })
-            .collect();
+        // In the order of the store, like every other lookup.
+        ids.sort_by_key(|id| data.position(id));
+        let mut docs = Vec::with_capacity(ids.len());
+        for id in &ids {
+            if let Some(raw) = data.get(id) {
+                if let Ok(v) = self.deserialize::<T>(&self.payload(id, raw)?) {
+                    docs.push(Document::new(*id, v));
+                }
+            }
+        }
         Ok(docs)
     }
 
     /// Compact the backing store, rewriting it with exactly one Insert record
-    /// per live document. No-op for [`MemStorage`].
+    /// per live document. No-op for [`MemStorage`]. Writes are accepted while
+    /// the file-backed engines write the compacted copy.
     pub async fn compact(&self) -> Result<()> {
-        let data = self.data.read().await;
-        self.storage.compact(&data).await
+        self.storage.compact_online(&self.data).await
+    }
+
+    /// Re-encrypt the database under `key` by compacting it, then use `key`
+    /// for every new record. Writes keep running meanwhile. Reopen with `key`
+    /// afterwards; the old key no longer opens the file. Supported by
+    /// [`FileStorage`] ([`MemStorage`] has nothing to re-encrypt); other
+    /// backends return [`RedDbError::Unsupported`].
+    #[cfg(feature = "encryption")]
+    pub async fn rotate_key(&self, key: impl Into<EncryptionKey>) -> Result<()> {
+        self.storage.rotate_key(&key.into(), &self.data).await
+    }
+
+    /// Write a compacted copy of the database to `path` while writes keep
+    /// running, replacing any file there. The copy reflects the database at
+    /// one instant and is written to a temporary file first, so `path` only
+    /// ever holds a complete backup. Supported by [`FileStorage`] and
+    /// [`SegmentedStorage`]; other backends return [`RedDbError::Unsupported`].
+    pub async fn backup_to(&self, path: impl AsRef<Path>) -> Result<BackupReport> {
+        self.storage.backup(path.as_ref(), &self.data, false).await
+    }
+
+    /// Bring the backup last written to `path` by this handle up to date by
+    /// appending only the records written since. Falls back to a full
+    /// [`backup_to`](Self::backup_to), reported in [`BackupReport::incremental`],
+    /// when there is no such backup, the file was changed since, or
+    /// compaction removed records it would need.
+    pub async fn backup_incremental_to(&self, path: impl AsRef<Path>) -> Result<BackupReport> {
+        self.storage.backup(path.as_ref(), &self.data, true).await
+    }
+
+    /// Replace the database `config` describes with the backup at `backup`.
+    /// The backup is read in full and every document checked to deserialize
+    /// as `T` first; if anything is wrong the live files are left untouched.
+    /// Fails with [`RedDbError::Locked`] while the database is open.
+    pub async fn restore_from<T>(backup: impl AsRef<Path>, config: DbConfig) -> Result<()>
+    where
+        for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq + Send + Sync,
+    {
+        let serializer = SE::for_config(&config)?;
+        let data = storage::read_backup(backup.as_ref(), serializer.format_id(), &config).await?;
+        for payload in data.values() {
+            serializer
+                .deserialize_owned::<T>(payload)
+                .map_err(|e| RedDbError::Deserialize(e.to_string()))?;
+        }
+        ST::restore(&config, &data).await
+    }
+
+    /// Rebuild the database `config` describes from every record that can
+    /// still be read, for when opening it fails with
+    /// [`RedDbError::CorruptRecord`]. Damaged records are skipped, along with
+    /// the bytes after them up to the next intact record, and so are
+    /// payloads that do not deserialize as `T`. Their bytes are appended to
+    /// a quarantine file next to the database and listed in the report. The
+    /// database is then rewritten compacted, with the documents the surviving
+    /// records leave. A damaged header cannot be skipped and fails the
+    /// salvage with the error opening gives. Fails with [`RedDbError::Locked`]
+    /// while the database is open.
+    pub async fn salvage<T>(config: DbConfig) -> Result<SalvageReport>
+    where
+        for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq + Send + Sync,
+    {
+        let serializer = SE::for_config(&config)?;
+        let check = move |payload: &[u8]| {
+            serializer
+                .deserialize_owned::<T>(payload)
+                .map(drop)
+                .map_err(|e| e.to_string())
+        };
+        ST::salvage(&config, &check).await
+    }
+
+    /// Write the documents this handle holds as the database `config`
+    /// describes, replacing any database there, like
+    /// [`restore_from`](Self::restore_from) does with a backup. A handle
+    /// opened [`as_of`](DbConfig::as_of) a past point exports that state and
+    /// leaves its own files untouched. The new database starts a new
+    /// sequence of record numbers. Fails with [`RedDbError::Locked`] while
+    /// the target is open, and refuses to overwrite the database this handle
+    /// was opened from.
+    pub async fn export_to(&self, config: DbConfig) -> Result<()> {
+        if path::absolute(config.file_stem())? == self.file_stem {
+            return Err(RedDbError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "cannot export a database onto itself",
+            )));
+        }
+        let data = {
+            let data = self.data.read().await;
+            data.iter()
+                .map(|(id, raw)| Ok((*id, self.payload(id, raw)?.into_owned())))
+                .collect::<Result<RedDbHM>>()?
+        };
+        ST::restore(&config, &data).await
+    }
+
+    /// Read every stored record back and report what is wrong with them:
+    /// damaged records, unknown op bytes, updates and deletes of documents
+    /// that do not exist, and payloads that do not deserialize as `T`.
+    /// Unlike opening, which stops at the first damaged record, the scan
+    /// carries on past damage and reports all of it. Records written while
+    /// it runs are left out. Reports [`RedDbError::Unsupported`] for
+    /// [`MemStorage`], which stores nothing to verify.
+    pub async fn verify<T>(&self) -> Result<VerifyReport>
+    where
+        for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq + Send + Sync,
+    {
+        let serializer = &self.serializer;
+        let check = move |payload: &[u8]| {
+            serializer
+                .deserialize_owned::<T>(payload)
+                .map(drop)
+                .map_err(|e| e.to_string())
+        };
+        self.storage.verify(&check).await
+    }
+
+    /// Make every write accepted so far durable, whatever the configured
+    /// [`SyncMode`]. Concurrent calls share one fsync. No-op for [`MemStorage`].
+    pub async fn flush(&self) -> Result<()> {
+        self.storage.flush().await
+    }
+
+    /// Stop the background tasks, waiting for a compaction in progress to
+    /// finish, then [`flush`](Self::flush). Dropping the database stops the
+    /// tasks as well, but without waiting for them and without a final flush.
+    pub async fn close(mut self) -> Result<()> {
+        if let Some(compactor) = self.compactor.take() {
+            compactor.stop().await?;
+        }
+        if let Some(flusher) = self.flusher.take() {
+            flusher.stop().await?;
+        }
+        self.flush().await
     }
 
     /// Return a snapshot of storage statistics.
@@ -283,6 +542,9 @@ where
             file_size_bytes,
             live_document_count,
             compaction_ratio: self.compaction_ratio,
+            truncated_tail_bytes: self.storage.truncated_tail_bytes(),
+            auto_compactions: self.compactor.as_ref().map_or(0, Compactor::runs),
+            last_lsn: self.storage.last_lsn().await,
         })
     }
 
@@ -298,15 +560,13 @@ where
     {
         if self.write_order == WriteOrder::FileFirst {
             let mut data = self.write_lock().await?;
-            let matches: Vec<(Uuid, Vec<u8>, T)> = data
-                .iter()
-                .filter_map(|(id, raw)| {
-                    self.deserialize::<T>(raw)
-                        .ok()
-                        .filter(|v| predicate(v))
-                        .map(|v| (*id, raw.clone(), v))
-                })
-                .collect();
+            let mut matches: Vec<(Uuid, Vec<u8>, T)> = Vec::new();
+            for (id, raw) in data.iter() {
+                let raw = self.payload(id, raw)?;
+                if let Some(v) = self.deserialize::<T>(&raw).ok().filter(|v| predicate(v)) {
+                    matches.push((*id, raw.into_owned(), v));
+                }
+            }
             if matches.is_empty() {
                 return Ok(0);
             }
@@ -315,9 +575,8 @@ where
                 .map(|(id, _, v)| Document::new(*id, v.clone()))
                 .collect();
             self.storage.persist(&docs, WalOp::Delete).await?;
-            for (id, _, _) in &matches {
-                data.remove(id);
-            }
+            let ids: Vec<Uuid> = matches.iter().map(|(id, _, _)| *id).collect();
+            remove_all(&mut data, &ids);
             let count = matches.len();
             drop(data);
             for (id, raw, _) in &matches {
@@ -329,22 +588,20 @@ where
         // MemoryFirst path
         let deleted: Vec<(Uuid, Vec<u8>, Document<T>)> = {
             let mut data = self.write_lock().await?;
-            let matches: Vec<(Uuid, Vec<u8>)> = data
+            let matches: Vec<Uuid> = data
                 .iter()
                 .filter_map(|(id, raw)| {
                     self.deserialize::<T>(raw)
                         .ok()
                         .filter(|v| predicate(v))
-                        .map(|_| (*id, raw.clone()))
+                        .map(|_| *id)
                 })
                 .collect();
-            matches
+            remove_all(&mut data, &matches)
                 .into_iter()
-                .filter_map(|(id, raw)| {
-                    data.remove(&id).map(|removed| {
-                        let v = self.deserialize::<T>(&removed).unwrap();
-                        (id, raw, Document::new(id, v))
-                    })
+                .map(|(id, raw)| {
+                    let v = self.deserialize::<T>(&raw).unwrap();
+                    (id, raw, Document::new(id, v))
                 })
                 .collect()
         };
@@ -367,11 +624,13 @@ where
     {
         let data = self.read_lock().await?;
         let serialized = self.serialize(search)?;
-        Ok(data
-            .iter()
-            .filter(|(_, value)| **value == serialized)
-            .map(|(id, _)| *id)
-            .collect())
+        let mut ids = Vec::new();
+        for (id, raw) in data.iter() {
+            if *self.payload(id, raw)? == *serialized {
+                ids.push(*id);
+            }
+        }
+        Ok(ids)
     }
 
     pub async fn insert_one<T>(&self, value: T) -> Result<Document<T>>
@@ -382,12 +641,17 @@ where
         let raw = self.serialize(&value)?;
         let doc = Document::new(id, value);
 
-        if self.write_order == WriteOrder::FileFirst {
-            self.storage
-                .persist(std::slice::from_ref(&doc), WalOp::Insert)
-                .await?;
+        {
+            // Persisted under the write lock, so a snapshot taken under the
+            // read lock never misses a record already in the log.
+            let mut data = self.write_lock().await?;
+            if self.write_order == WriteOrder::FileFirst {
+                self.storage
+                    .persist(std::slice::from_ref(&doc), WalOp::Insert)
+                    .await?;
+            }
+            data.insert(id, self.entry(&raw));
         }
-        self.write_lock().await?.insert(id, raw.clone());
         if self.write_order == WriteOrder::MemoryFirst {
             self.storage
                 .persist(std::slice::from_ref(&doc), WalOp::Insert)
@@ -415,13 +679,13 @@ where
             .map(|(id, _, v)| Document::new(*id, v.clone()))
             .collect();
 
-        if self.write_order == WriteOrder::FileFirst {
-            self.storage.persist(&docs, WalOp::Insert).await?;
-        }
         {
             let mut data = self.write_lock().await?;
+            if self.write_order == WriteOrder::FileFirst {
+                self.storage.persist(&docs, WalOp::Insert).await?;
+            }
             for (id, raw, _) in &prepared {
-                data.insert(*id, raw.clone());
+                data.insert(*id, self.entry(raw));
             }
         }
         if self.write_order == WriteOrder::MemoryFirst {
@@ -439,7 +703,10 @@ where
     {
         let data = self.read_lock().await?;
         match data.get(id) {
-            Some(raw) => Ok(Some(Document::new(*id, self.deserialize(raw)?))),
+            Some(raw) => {
+                let value = self.deserialize(&self.payload(id, raw)?)?;
+                Ok(Some(Document::new(*id, value)))
+            }
             None => Ok(None),
         }
     }
@@ -462,11 +729,11 @@ where
         if self.write_order == WriteOrder::FileFirst {
             let mut data = self.write_lock().await?;
             if let Some(entry) = data.get_mut(id) {
-                let old_raw = entry.clone();
+                let old_raw = self.payload(id, entry)?.into_owned();
                 self.storage
                     .persist(std::slice::from_ref(&doc), WalOp::Update)
                     .await?;
-                *entry = new_raw.clone();
+                *entry = self.entry(&new_raw);
                 drop(data);
                 self.index_on_update(*id, &old_raw, &new_raw).await;
                 Ok(true)
@@ -500,7 +767,8 @@ where
     {
         if self.write_order == WriteOrder::FileFirst {
             let mut data = self.write_lock().await?;
-            let raw = data.get(id).ok_or(RedDbError::NotFound(*id))?.clone();
+            let raw = data.get(id).ok_or(RedDbError::NotFound(*id))?;
+            let raw = self.payload(id, raw)?.into_owned();
             let value: T = self.deserialize(&raw)?;
             let doc = Document::new(*id, value);
             self.storage
@@ -530,10 +798,12 @@ where
         for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq,
     {
         let data = self.read_lock().await?;
-        Ok(data
-            .iter()
-            .map(|(id, raw)| Document::new(*id, self.deserialize(raw).unwrap()))
-            .collect())
+        data.iter()
+            .map(|(id, raw)| {
+                let raw = self.payload(id, raw)?;
+                Ok(Document::new(*id, self.deserialize(&raw).unwrap()))
+            })
+            .collect()
     }
 
     pub async fn find<T>(&self, search: &T) -> Result<Vec<Document<T>>>
@@ -542,11 +812,14 @@ where
     {
         let data = self.read_lock().await?;
         let serialized = self.serialize(search)?;
-        Ok(data
-            .iter()
-            .filter(|(_, raw)| **raw == serialized)
-            .map(|(id, raw)| Document::new(*id, self.deserialize(raw).unwrap()))
-            .collect())
+        let mut docs = Vec::new();
+        for (id, raw) in data.iter() {
+            let raw = self.payload(id, raw)?;
+            if *raw == *serialized {
+                docs.push(Document::new(*id, self.deserialize(&raw).unwrap()));
+            }
+        }
+        Ok(docs)
     }
 
     pub async fn update<T>(&self, search: &T, new_value: &T) -> Result<usize>
@@ -558,11 +831,12 @@ where
 
         if self.write_order == WriteOrder::FileFirst {
             let mut data = self.write_lock().await?;
-            let matching_ids: Vec<Uuid> = data
-                .iter()
-                .filter(|(_, raw)| **raw == serialized_search)
-                .map(|(id, _)| *id)
-                .collect();
+            let mut matching_ids: Vec<Uuid> = Vec::new();
+            for (id, raw) in data.iter() {
+                if *self.payload(id, raw)? == *serialized_search {
+                    matching_ids.push(*id);
+                }
+            }
             if matching_ids.is_empty() {
                 return Ok(0);
             }
@@ -573,7 +847,7 @@ where
             self.storage.persist(&docs, WalOp::Update).await?;
             for id in &matching_ids {
                 if let Some(entry) = data.get_mut(id) {
-                    *entry = new_raw.clone();
+                    *entry = self.entry(&new_raw);
                 }
             }
             drop(data);
@@ -620,23 +894,22 @@ where
 
         if self.write_order == WriteOrder::FileFirst {
             let mut data = self.write_lock().await?;
-            let (docs, raws): (Vec<Document<T>>, Vec<Vec<u8>>) = uuids
-                .iter()
-                .filter_map(|id| {
-                    data.get(id).and_then(|raw| {
-                        self.deserialize::<T>(raw)
-                            .ok()
-                            .map(|v| (Document::new(*id, v), raw.clone()))
-                    })
-                })
-                .unzip();
+            let mut docs: Vec<Document<T>> = Vec::new();
+            let mut raws: Vec<Vec<u8>> = Vec::new();
+            for id in &uuids {
+                if let Some(raw) = data.get(id) {
+                    let raw = self.payload(id, raw)?;
+                    if let Ok(v) = self.deserialize::<T>(&raw) {
+                        docs.push(Document::new(*id, v));
+                        raws.push(raw.into_owned());
+                    }
+                }
+            }
             if docs.is_empty() {
                 return Ok(0);
             }
             self.storage.persist(&docs, WalOp::Delete).await?;
-            for id in &uuids {
-                data.remove(id);
-            }
+            remove_all(&mut data, &uuids);
             drop(data);
             for (id, raw) in uuids.iter().zip(&raws) {
                 self.index_on_delete(*id, raw).await;
@@ -645,14 +918,12 @@ where
         } else {
             let (docs, raws): (Vec<Document<T>>, Vec<Vec<u8>>) = {
                 let mut data = self.write_lock().await?;
-                uuids
+                remove_all(&mut data, &uuids)
                     .into_iter()
-                    .filter_map(|id| {
-                        data.remove(&id).and_then(|raw| {
-                            self.deserialize::<T>(&raw)
-                                .ok()
-                                .map(|v| (Document::new(id, v), raw))
-                        })
+                    .filter_map(|(id, raw)| {
+                        self.deserialize::<T>(&raw)
+                            .ok()
+                            .map(|v| (Document::new(id, v), raw))
                     })
                     .unzip()
             };
@@ -670,7 +941,7 @@ where
 
     fn serialize<T>(&self, value: &T) -> Result<Vec<u8>>
     where
-        for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq,
+        T: Serialize + ?Sized,
     {
         self.serializer
             .serialize(value)
@@ -679,14 +950,65 @@ where
 
     fn deserialize<T>(&self, value: &[u8]) -> Result<T>
     where
-        for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq,
+        T: DeserializeOwned,
     {
         self.serializer
-            .deserialize(value)
+            .deserialize_owned(value)
             .map_err(|e| RedDbError::Deserialize(e.to_string()))
     }
 }
 
+impl AnyDb {
+    /// Open the existing database file at `path`, whatever its format: the
+    /// format is read from the file header and every payload goes through
+    /// its serializer. `config` supplies everything else, such as the
+    /// encryption key or [`read_only`](DbConfig::read_only); its name, dir
+    /// and format are taken from `path`.
+    ///
+    /// Fails with [`RedDbError::FormatNotEnabled`] if the serializer of the
+    /// file's format is not compiled in, and with [`RedDbError::Io`] if the
+    /// file does not exist or its extension is not that of its format, since
+    /// the extension decides which file a database opens.
+    pub async fn open_path<T>(path: impl AsRef<Path>, config: DbConfig) -> Result<Self>
+    where
+        for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq + Send + Sync,
+    {
+        let path = path.as_ref();
+        let format = storage::inspect::format(path).await?;
+        let serializer = serializer::AnySerializer::new(format)?;
+        let extension = serializer.extension();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(extension))
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| {
+                RedDbError::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} holds a {format:?} database; its name must end in {extension}",
+                        path.display()
+                    ),
+                ))
+            })?;
+        let config = DbConfig {
+            name: name.to_string(),
+            dir: path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            ..config
+        }
+        .format(format);
+        Self::open::<T>(config).await
+    }
+}
+
+/// Remove the documents `ids` from `data` and return their entries, in the
+/// order of `ids`. The remaining documents keep their order.
+fn remove_all(data: &mut RedDbHM, ids: &[Uuid]) -> Vec<(Uuid, Vec<u8>)> {
+    ids.iter()
+        .filter_map(|id| data.remove(id).map(|raw| (*id, raw)))
+        .collect()
+}
+
 // ── unit tests ─────────────────────────────────────────────────────────────────
 
 #[cfg(test)]
@@ -1061,6 +1383,7 @@ mod tests {
         assert!(uuids.contains(&doc3.id));
 
         fs::remove_file(".test2.db.ron").unwrap();
+        fs::remove_file(".test2.db.ron.lock").unwrap();
     }
 
     #[tokio::test]
@@ -1078,6 +1401,7 @@ mod tests {
         assert_eq!(find.id, doc.id);
         assert_eq!(find.data, doc.data);
         fs::remove_file(".insert_and_find_one.db.ron").unwrap();
+        fs::remove_file(".insert_and_find_one.db.ron.lock").unwrap();
     }
 
     #[tokio::test]
@@ -1093,6 +1417,7 @@ mod tests {
         assert!(found.is_some());
         assert_eq!(found.unwrap().data.foo, "hello");
         fs::remove_file(".get_existing.db.ron").unwrap();
+        fs::remove_file(".get_existing.db.ron.lock").unwrap();
     }
 
     #[tokio::test]
@@ -1101,6 +1426,7 @@ mod tests {
         let result: Option<Document<TestStruct>> = db.get(&Uuid::new_v4()).await.unwrap();
         assert!(result.is_none());
         fs::remove_file(".get_missing.db.ron").unwrap();
+        fs::remove_file(".get_missing.db.ron.lock").unwrap();
     }
 
     #[tokio::test]
@@ -1118,6 +1444,7 @@ mod tests {
         let result = db.find(&one).await.unwrap();
         assert_eq!(result.len(), 2);
         fs::remove_file(".find.db.ron").unwrap();
+        fs::remove_file(".find.db.ron.lock").unwrap();
     }
 
     #[tokio::test]
@@ -1134,6 +1461,7 @@ mod tests {
         let result: Document<TestStruct> = db.find_one(&doc.id).await.unwrap();
         assert_eq!(result.data, updated);
         fs::remove_file(".update_one.db.ron").unwrap();
+        fs::remove_file(".update_one.db.ron.lock").unwrap();
     }
 
     #[tokio::test]
@@ -1153,6 +1481,7 @@ mod tests {
         let result = db.find(&two).await.unwrap();
         assert_eq!(result.len(), 3);
         fs::remove_file(".update.db.ron").unwrap();
+        fs::remove_file(".update.db.ron.lock").unwrap();
     }
 
     #[tokio::test]
@@ -1170,6 +1499,7 @@ mod tests {
         let after: Option<Document<TestStruct>> = db.get(&doc.id).await.unwrap();
         assert!(after.is_none());
         fs::remove_file(".delete_one.db.ron").unwrap();
+        fs::remove_file(".delete_one.db.ron.lock").unwrap();
     }
 
     #[tokio::test]
@@ -1189,6 +1519,7 @@ mod tests {
         let not_deleted = db.delete(&one).await.unwrap();
         assert_eq!(not_deleted, 0);
         fs::remove_file(".delete.db.ron").unwrap();
+        fs::remove_file(".delete.db.ron.lock").unwrap();
     }
 
     #[tokio::test]
@@ -1201,5 +1532,6 @@ mod tests {
         let deserialized: TestStruct = db.serializer.deserialize(&serialized).unwrap();
         assert_eq!(deserialized, test);
         fs::remove_file(".serialize.db.ron").unwrap();
+        fs::remove_file(".serialize.db.ron.lock").unwrap();
     }
 }
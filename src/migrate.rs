@@ -1,15 +1,16 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::io::BufRead;
+use std::path;
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::error::{RedDbError, Result};
 use crate::serializer::Serializer;
-use crate::storage::{FileStorage, Storage};
+use crate::storage::{self, FileStorage, Storage};
 use crate::wal::WalOp;
-use crate::{DbConfig, RedDb};
+use crate::{DbConfig, RedDb, RedDbHM};
 
 // Internal v1 document shape for deserialization.
 // Fields and struct name match what v1 serde produced on disk.
@@ -46,14 +47,14 @@ enum V1Status {
 /// ```ignore
 /// reddb::migrate::from_v1::<MyType, reddb::serializer::Ron>("users.ron", "users_v2").await?;
 /// ```
-#[allow(private_bounds)]
 pub async fn from_v1<T, SE>(v1_path: &str, v2_name: &str) -> Result<usize>
 where
     for<'de> T: Serialize + Deserialize<'de> + Debug + Clone + PartialEq + Send + Sync + 'static,
     SE: Serializer + Debug + 'static,
     FileStorage<SE>: Storage + Debug + Send + Sync + 'static,
 {
-    let serializer = SE::default();
+    let config = DbConfig::new(v2_name);
+    let serializer = SE::for_config(&config)?;
     let mut live: HashMap<Uuid, Vec<u8>> = HashMap::new();
 
     let file = std::fs::File::open(v1_path)?;
@@ -67,7 +68,7 @@ where
         }
 
         let v1doc: V1Document<T> = serializer
-            .deserialize(trimmed.as_bytes())
+            .deserialize_owned(trimmed.as_bytes())
             .map_err(|e| RedDbError::Deserialize(e.to_string()))?;
 
         if v1doc._st == V1Status::De {
@@ -84,7 +85,7 @@ where
         return Ok(0);
     }
 
-    let db: RedDb<SE, FileStorage<SE>> = RedDb::open::<T>(DbConfig::new(v2_name)).await?;
+    let db: RedDb<SE, FileStorage<SE>> = RedDb::open::<T>(config).await?;
 
     let ops: Vec<(WalOp, Uuid, Vec<u8>)> = live
         .iter()
@@ -104,3 +105,81 @@ where
 
     Ok(count)
 }
+
+/// Upgrade a database written by reddb 2.0 (file format version 2) to the
+/// current format, in place. Opening a version 2 database fails with
+/// [`RedDbError::UnsupportedVersion`] until this has run.
+///
+/// Works for both [`FileStorage`] files and
+/// [`SegmentedStorage`](crate::SegmentedStorage) directories; `config` names
+/// the database and supplies its encryption key, if any. Version 2 records
+/// did not carry a sequence number or timestamp: upgraded records are
+/// numbered from 1 in replay order and get timestamp 0. Files already in the
+/// current format are left alone, so running it twice is harmless.
+///
+/// Returns the number of records rewritten. Fails with
+/// [`RedDbError::Locked`] while the database is open.
+///
+/// # Example
+/// ```ignore
+/// reddb::migrate::upgrade_v2::<reddb::serializer::Ron>(&DbConfig::new("users")).await?;
+/// ```
+pub async fn upgrade_v2<SE>(config: &DbConfig) -> Result<u64>
+where
+    SE: Serializer,
+{
+    let serializer = SE::for_config(config)?;
+    storage::upgrade_v2(config, serializer.format_id(), serializer.extension()).await
+}
+
+/// Convert the database `src` names, written with serializer `From`, into a
+/// database written with `To` at `dst`, replacing any database there.
+/// Every live document is decoded as `T` and encoded again, keeping its
+/// UUID; deleted documents and history are not carried over, and record
+/// numbers start afresh. `src` is opened read-only, so it may stay open
+/// elsewhere, and [`DbConfig::as_of`] picks a past state to convert.
+/// Each side uses its own encryption key, if any.
+///
+/// Returns the number of documents written. Fails with
+/// [`RedDbError::Locked`] while the destination is open, and refuses to
+/// convert a database onto itself.
+///
+/// # Example
+/// ```ignore
+/// use reddb::serializer::{Bin, Json};
+/// reddb::migrate::convert::<User, Json, Bin>(&DbConfig::new("users"), &DbConfig::new("users")).await?;
+/// ```
+pub async fn convert<T, From, To>(src: &DbConfig, dst: &DbConfig) -> Result<usize>
+where
+    for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq + Send + Sync,
+    From: Serializer + Debug + Send + Sync + 'static,
+    To: Serializer + Debug + Send + Sync + 'static,
+{
+    let encoder = To::for_config(dst)?;
+    if From::for_config(src)?.format_id() == encoder.format_id()
+        && path::absolute(src.file_stem())? == path::absolute(dst.file_stem())?
+    {
+        return Err(RedDbError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "cannot convert a database onto itself",
+        )));
+    }
+
+    let db: RedDb<From, FileStorage<From>> = RedDb::open::<T>(src.clone().read_only(true)).await?;
+    let converted = {
+        let data = db.read_lock().await?;
+        let mut converted = RedDbHM::with_capacity(data.len());
+        for (id, raw) in data.iter() {
+            let value: T = db.deserialize_raw(&db.payload(id, raw)?)?;
+            let raw = encoder
+                .serialize(&value)
+                .map_err(|e| RedDbError::Serialize(e.to_string()))?;
+            converted.insert(*id, raw);
+        }
+        converted
+    };
+    drop(db);
+
+    FileStorage::<To>::restore(dst, &converted).await?;
+    Ok(converted.len())
+}
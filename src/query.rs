@@ -25,7 +25,6 @@ pub struct QueryBuilder<'db, T, SE, ST> {
     _marker: PhantomData<T>,
 }
 
-#[allow(private_bounds)]
 impl<'db, T, SE, ST> QueryBuilder<'db, T, SE, ST>
 where
     SE: Serializer + Debug,
@@ -74,21 +73,17 @@ where
     }
 
     async fn execute(self) -> Result<Vec<Document<T>>> {
-        // Deserialize all entries while holding the read lock, then release it.
-        let mut docs: Vec<Document<T>> = {
+        // Deserialize and filter all entries while holding the read lock,
+        // then release it. Only matching documents are kept.
+        let mut docs: Vec<Document<T>> = Vec::new();
+        {
             let data = self.db.read_lock().await?;
-            data.iter()
-                .map(|(id, raw)| {
-                    self.db
-                        .deserialize_raw::<T>(raw)
-                        .map(|value| Document::new(*id, value))
-                })
-                .collect::<Result<Vec<_>>>()?
-        };
-
-        // Filter
-        if let Some(ref f) = self.filter {
-            docs.retain(|doc| f(&doc.data));
+            for (id, raw) in data.iter() {
+                let value: T = self.db.deserialize_raw(&self.db.payload(id, raw)?)?;
+                if self.filter.as_ref().is_none_or(|f| f(&value)) {
+                    docs.push(Document::new(*id, value));
+                }
+            }
         }
 
         // Sort
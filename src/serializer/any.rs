@@ -0,0 +1,179 @@
+use super::{FormatId, Serializer};
+use crate::config::DbConfig;
+use crate::error::{RedDbError, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// Call `$method($args)` on the serializer of the format `$any` holds.
+macro_rules! dispatch {
+    ($any:ident, $method:ident($($arg:ident),+)) => {
+        match $any.format {
+            #[cfg(feature = "json_ser")]
+            Some(FormatId::Json) => super::Json.$method($($arg),+),
+            #[cfg(feature = "ron_ser")]
+            Some(FormatId::Ron) => super::Ron.$method($($arg),+),
+            #[cfg(feature = "yaml_ser")]
+            Some(FormatId::Yaml) => super::Yaml.$method($($arg),+),
+            #[cfg(feature = "bin_ser")]
+            Some(FormatId::Bin) => super::Bin.$method($($arg),+),
+            #[cfg(feature = "msgpack_ser")]
+            Some(FormatId::MsgPack) => super::MsgPack.$method($($arg),+),
+            #[cfg(feature = "cbor_ser")]
+            Some(FormatId::Cbor) => super::Cbor.$method($($arg),+),
+            #[cfg(feature = "postcard_ser")]
+            Some(FormatId::Postcard) => super::Postcard.$method($($arg),+),
+            Some(other) => {
+                let _ = ($($arg),+);
+                Err(RedDbError::FormatNotEnabled(other).into())
+            }
+            None => {
+                let _ = ($($arg),+);
+                Err(anyhow::anyhow!(NO_FORMAT))
+            }
+        }
+    };
+}
+
+/// Why the default [`AnySerializer`] cannot encode or decode anything.
+const NO_FORMAT: &str = "AnySerializer has no format; build it with AnySerializer::new";
+
+/// A serializer whose format is chosen at run time, for databases whose
+/// format is only known once their header has been read. Every call is
+/// dispatched to the built-in serializer of that format.
+///
+/// [`AnyDb::open_path`](crate::AnyDb::open_path) builds one from the file
+/// header. Storages build theirs with [`Serializer::for_config`], from
+/// [`DbConfig::format`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AnySerializer {
+    format: Option<FormatId>,
+}
+
+impl AnySerializer {
+    /// A serializer of `format`. Fails with
+    /// [`RedDbError::FormatNotEnabled`] if the feature of its serializer is
+    /// not compiled in, and for [`FormatId::Custom`] formats, which only
+    /// their own serializer knows.
+    pub fn new(format: FormatId) -> Result<Self> {
+        let enabled = match format {
+            #[cfg(feature = "json_ser")]
+            FormatId::Json => true,
+            #[cfg(feature = "ron_ser")]
+            FormatId::Ron => true,
+            #[cfg(feature = "yaml_ser")]
+            FormatId::Yaml => true,
+            #[cfg(feature = "bin_ser")]
+            FormatId::Bin => true,
+            #[cfg(feature = "msgpack_ser")]
+            FormatId::MsgPack => true,
+            #[cfg(feature = "cbor_ser")]
+            FormatId::Cbor => true,
+            #[cfg(feature = "postcard_ser")]
+            FormatId::Postcard => true,
+            _ => false,
+        };
+        if !enabled {
+            return Err(RedDbError::FormatNotEnabled(format));
+        }
+        Ok(AnySerializer {
+            format: Some(format),
+        })
+    }
+
+    /// The format this serializer was built for; `None` for the default one.
+    pub fn format(&self) -> Option<FormatId> {
+        self.format
+    }
+}
+
+impl Serializer for AnySerializer {
+    /// # Panics
+    ///
+    /// If the serializer was built with `AnySerializer::default()`, which has
+    /// no format. Its other methods fail instead.
+    fn format_id(&self) -> FormatId {
+        self.format.expect(NO_FORMAT)
+    }
+
+    fn for_config(config: &DbConfig) -> Result<Self> {
+        match config.format {
+            Some(format) => Self::new(format),
+            None => Err(RedDbError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "AnySerializer needs DbConfig::format; open with AnyDb::open_path",
+            ))),
+        }
+    }
+
+    fn serialize<T>(&self, val: &T) -> anyhow::Result<Vec<u8>>
+    where
+        T: Serialize + ?Sized,
+    {
+        dispatch!(self, serialize(val))
+    }
+
+    fn serialize_into<T>(&self, val: &T, writer: &mut impl Write) -> anyhow::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        dispatch!(self, serialize_into(val, writer))
+    }
+
+    fn deserialize<'de, T>(&self, val: &'de [u8]) -> anyhow::Result<T>
+    where
+        T: Deserialize<'de>,
+    {
+        dispatch!(self, deserialize(val))
+    }
+
+    fn deserialize_owned<T>(&self, val: &[u8]) -> anyhow::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        dispatch!(self, deserialize_owned(val))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_formats_are_not_enabled() {
+        assert!(matches!(
+            AnySerializer::new(FormatId::Custom(1)),
+            Err(RedDbError::FormatNotEnabled(FormatId::Custom(1)))
+        ));
+    }
+
+    #[test]
+    fn for_config_needs_a_format() {
+        assert!(AnySerializer::for_config(&DbConfig::new("db")).is_err());
+    }
+
+    #[test]
+    fn the_default_fails_instead_of_panicking() {
+        let none = AnySerializer::default();
+        assert!(none.serialize(&1u8).is_err());
+        assert!(none.serialize_into(&1u8, &mut Vec::new()).is_err());
+        assert!(none.deserialize::<u8>(b"1").is_err());
+        assert!(none.deserialize_owned::<u8>(b"1").is_err());
+    }
+
+    #[cfg(all(feature = "json_ser", feature = "bin_ser"))]
+    #[test]
+    fn dispatches_to_the_format() {
+        let json = AnySerializer::new(FormatId::Json).unwrap();
+        assert_eq!(json.format_id(), FormatId::Json);
+        assert_eq!(json.extension(), ".json");
+        assert_eq!(json.serialize(&vec![1, 2]).unwrap(), b"[1,2]");
+        let bin = AnySerializer::for_config(&DbConfig::new("db").format(FormatId::Bin)).unwrap();
+        let bytes = bin.serialize(&"hi".to_string()).unwrap();
+        assert_eq!(
+            bytes,
+            super::super::Bin.serialize(&"hi".to_string()).unwrap()
+        );
+        assert_eq!(bin.deserialize::<String>(&bytes).unwrap(), "hi");
+    }
+}
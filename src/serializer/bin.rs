@@ -1,6 +1,7 @@
 use super::{FormatId, Serializer};
 use anyhow::{Error, Result};
 use serde::{Deserialize, Serialize};
+use std::io::Write;
 
 #[derive(Debug, Default)]
 pub struct Bin;
@@ -13,7 +14,7 @@ impl Serializer for Bin {
 
     fn serialize<T>(&self, data: &T) -> Result<Vec<u8>, Error>
     where
-        for<'de> T: Serialize + Deserialize<'de>,
+        T: Serialize + ?Sized,
     {
         Ok(bincode::serde::encode_to_vec(
             data,
@@ -21,11 +22,19 @@ impl Serializer for Bin {
         )?)
     }
 
-    fn deserialize<T>(&self, data: &[u8]) -> Result<T, Error>
+    fn serialize_into<T>(&self, data: &T, writer: &mut impl Write) -> Result<(), Error>
     where
-        for<'de> T: Serialize + Deserialize<'de>,
+        T: Serialize + ?Sized,
     {
-        let (val, _) = bincode::serde::decode_from_slice(data, bincode::config::standard())?;
+        bincode::serde::encode_into_std_write(data, writer, bincode::config::standard())?;
+        Ok(())
+    }
+
+    fn deserialize<'de, T>(&self, data: &'de [u8]) -> Result<T, Error>
+    where
+        T: Deserialize<'de>,
+    {
+        let (val, _) = bincode::serde::borrow_decode_from_slice(data, bincode::config::standard())?;
         Ok(val)
     }
 }
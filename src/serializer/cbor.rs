@@ -0,0 +1,117 @@
+use super::{FormatId, Serializer};
+use anyhow::{Error, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// CBOR (RFC 8949). Binary and self-describing: structs are written as maps
+/// keyed by field name.
+#[derive(Debug, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor_ser")]
+impl Serializer for Cbor {
+    fn format_id(&self) -> FormatId {
+        FormatId::Cbor
+    }
+
+    fn serialize<T>(&self, data: &T) -> Result<Vec<u8>, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let mut buf = Vec::new();
+        ciborium::into_writer(data, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn serialize_into<T>(&self, data: &T, writer: &mut impl Write) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(ciborium::into_writer(data, writer)?)
+    }
+
+    /// ciborium only decodes owned values, so the input is read into a
+    /// [`ciborium::Value`] first and `T` decoded from that; strings are
+    /// always copied. [`deserialize_owned`](Serializer::deserialize_owned)
+    /// skips the intermediate value.
+    fn deserialize<'de, T>(&self, data: &'de [u8]) -> Result<T, Error>
+    where
+        T: Deserialize<'de>,
+    {
+        let value: ciborium::Value = ciborium::from_reader(data)?;
+        Ok(value.deserialized()?)
+    }
+
+    fn deserialize_owned<T>(&self, data: &[u8]) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        Ok(ciborium::from_reader(data)?)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cbor_ser")]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct S {
+        x: u32,
+        name: String,
+    }
+
+    #[test]
+    fn round_trip() {
+        let s = S {
+            x: 5,
+            name: "hello".into(),
+        };
+        let ser = Cbor.serialize(&s).unwrap();
+        let de: S = Cbor.deserialize(&ser).unwrap();
+        assert_eq!(de, s);
+    }
+
+    #[test]
+    fn borrowing_types_decode_through_a_value() {
+        use std::borrow::Cow;
+        #[derive(Deserialize)]
+        struct Borrowed<'a> {
+            #[serde(borrow)]
+            name: Cow<'a, str>,
+        }
+        let s = S {
+            x: 5,
+            name: "hello".into(),
+        };
+        let bytes = Cbor.serialize(&s).unwrap();
+        let borrowed: Borrowed = Cbor.deserialize(&bytes).unwrap();
+        assert_eq!(borrowed.name, "hello");
+        let owned: S = Cbor.deserialize_owned(&bytes).unwrap();
+        assert_eq!(owned, s);
+    }
+
+    #[test]
+    fn structs_are_keyed_by_field_name() {
+        let bytes = Cbor
+            .serialize(&BTreeMap::from([("x".to_string(), 7)]))
+            .unwrap();
+        assert_eq!(bytes, [0xA1, 0x61, b'x', 0x07]);
+        let bytes = Cbor
+            .serialize(&S {
+                x: 7,
+                name: String::new(),
+            })
+            .unwrap();
+        let map: BTreeMap<String, ciborium::Value> = Cbor.deserialize(&bytes).unwrap();
+        assert_eq!(map.keys().collect::<Vec<_>>(), ["name", "x"]);
+    }
+
+    #[test]
+    fn format_id_is_cbor() {
+        assert_eq!(Cbor.format_id(), FormatId::Cbor);
+    }
+}
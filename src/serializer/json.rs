@@ -1,6 +1,7 @@
 use super::{FormatId, Serializer};
 use anyhow::{Error, Result};
 use serde::{Deserialize, Serialize};
+use std::io::Write;
 
 #[derive(Debug, Default)]
 pub struct Json;
@@ -13,14 +14,21 @@ impl Serializer for Json {
 
     fn serialize<T>(&self, data: &T) -> Result<Vec<u8>, Error>
     where
-        for<'de> T: Serialize + Deserialize<'de>,
+        T: Serialize + ?Sized,
     {
         Ok(serde_json::to_vec(data)?)
     }
 
-    fn deserialize<T>(&self, data: &[u8]) -> Result<T, Error>
+    fn serialize_into<T>(&self, data: &T, writer: &mut impl Write) -> Result<(), Error>
     where
-        for<'de> T: Serialize + Deserialize<'de>,
+        T: Serialize + ?Sized,
+    {
+        Ok(serde_json::to_writer(writer, data)?)
+    }
+
+    fn deserialize<'de, T>(&self, data: &'de [u8]) -> Result<T, Error>
+    where
+        T: Deserialize<'de>,
     {
         Ok(serde_json::from_slice(data)?)
     }
@@ -52,6 +60,20 @@ mod tests {
         assert_ne!(bytes.last().copied(), Some(b'\n'));
     }
 
+    #[test]
+    fn deserialize_borrows_from_the_input() {
+        #[derive(Deserialize)]
+        struct Borrowed<'a> {
+            name: &'a str,
+        }
+        let bytes = Json
+            .serialize(&serde_json::json!({ "name": "ada" }))
+            .unwrap();
+        let value: Borrowed = Json.deserialize(&bytes).unwrap();
+        assert_eq!(value.name, "ada");
+        assert!(bytes.as_ptr_range().contains(&value.name.as_ptr()));
+    }
+
     #[test]
     fn format_id_is_json() {
         assert_eq!(Json.format_id(), FormatId::Json);
@@ -1,42 +1,73 @@
 use anyhow::{Error, Result};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::io::Write;
 
+use crate::config::DbConfig;
+
+mod any;
 #[cfg(feature = "bin_ser")]
 mod bin;
+#[cfg(feature = "cbor_ser")]
+mod cbor;
 #[cfg(feature = "json_ser")]
 mod json;
+#[cfg(feature = "msgpack_ser")]
+mod msgpack;
+#[cfg(feature = "postcard_ser")]
+mod postcard;
 #[cfg(feature = "ron_ser")]
 mod ron;
 #[cfg(feature = "yaml_ser")]
 mod yaml;
 
+pub use self::any::AnySerializer;
 #[cfg(feature = "bin_ser")]
 pub use self::bin::Bin;
+#[cfg(feature = "cbor_ser")]
+pub use self::cbor::Cbor;
 #[cfg(feature = "json_ser")]
 pub use self::json::Json;
+#[cfg(feature = "msgpack_ser")]
+pub use self::msgpack::MsgPack;
+#[cfg(feature = "postcard_ser")]
+pub use self::postcard::Postcard;
 #[cfg(feature = "ron_ser")]
 pub use self::ron::Ron;
 #[cfg(feature = "yaml_ser")]
 pub use self::yaml::Yaml;
 
 /// Identifies the serialization format and its file extension.
-/// The `u8` discriminant is stored in the 32-byte file header (byte 10).
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
+/// It is stored in the 32-byte file header, so a file only opens with a
+/// serializer of the format it was written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum FormatId {
-    Json = 0,
-    Ron = 1,
-    Yaml = 2,
-    Bin = 3,
+    Json,
+    Ron,
+    Yaml,
+    Bin,
+    MsgPack,
+    Cbor,
+    Postcard,
+    /// A format implemented outside this crate. The tag tells custom formats
+    /// apart from each other; pick one no other serializer you use has.
+    Custom(u8),
 }
 
 impl FormatId {
+    /// The extension of files in this format, including the dot. Custom
+    /// formats default to `.db`; their serializer can pick another with
+    /// [`Serializer::extension`].
     pub fn extension(self) -> &'static str {
         match self {
             FormatId::Bin => ".bin",
             FormatId::Json => ".json",
             FormatId::Yaml => ".yaml",
             FormatId::Ron => ".ron",
+            FormatId::MsgPack => ".msgpack",
+            FormatId::Cbor => ".cbor",
+            FormatId::Postcard => ".postcard",
+            FormatId::Custom(_) => ".db",
         }
     }
 }
@@ -47,13 +78,55 @@ impl FormatId {
 pub trait Serializer: Default + Send + Sync {
     fn format_id(&self) -> FormatId;
 
+    /// The extension of the files this serializer writes, including the dot:
+    /// the extension of its [`format_id`](Self::format_id) unless overridden.
+    fn extension(&self) -> &'static str {
+        self.format_id().extension()
+    }
+
+    /// The serializer a storage uses for the database `config` describes:
+    /// `Self::default()` unless overridden. [`AnySerializer`] takes its
+    /// format from [`DbConfig::format`] here.
+    fn for_config(config: &DbConfig) -> crate::error::Result<Self>
+    where
+        Self: Sized,
+    {
+        let _ = config;
+        Ok(Self::default())
+    }
+
     fn serialize<T>(&self, val: &T) -> Result<Vec<u8>, Error>
     where
-        for<'de> T: Serialize + Deserialize<'de>;
+        T: Serialize + ?Sized;
 
-    fn deserialize<T>(&self, val: &[u8]) -> Result<T, Error>
+    /// Append the encoding of `val` to `writer`, the same bytes `serialize`
+    /// returns. The default copies them from `serialize`; the built-in
+    /// serializers write straight to `writer`, which is how the storages
+    /// encode documents into their log without a buffer per record.
+    fn serialize_into<T>(&self, val: &T, writer: &mut impl Write) -> Result<(), Error>
     where
-        for<'de> T: Serialize + Deserialize<'de>;
+        T: Serialize + ?Sized,
+    {
+        writer.write_all(&self.serialize(val)?)?;
+        Ok(())
+    }
+
+    /// Decode a value from `val`, which it may borrow from: a
+    /// `#[serde(borrow)] Cow<str>` field points into `val` where the format
+    /// supports it and holds a copy where it does not.
+    fn deserialize<'de, T>(&self, val: &'de [u8]) -> Result<T, Error>
+    where
+        T: Deserialize<'de>;
+
+    /// Decode a value that borrows nothing from `val`. The default calls
+    /// `deserialize`; formats with a faster path for owned values override
+    /// it. `RedDb` decodes its documents with this.
+    fn deserialize_owned<T>(&self, val: &[u8]) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        self.deserialize(val)
+    }
 }
 
 // Keep backward-compatible re-export used by FileStorage to get the extension
@@ -69,6 +142,10 @@ mod tests {
         assert_eq!(FormatId::Json.extension(), ".json");
         assert_eq!(FormatId::Yaml.extension(), ".yaml");
         assert_eq!(FormatId::Ron.extension(), ".ron");
+        assert_eq!(FormatId::MsgPack.extension(), ".msgpack");
+        assert_eq!(FormatId::Cbor.extension(), ".cbor");
+        assert_eq!(FormatId::Postcard.extension(), ".postcard");
+        assert_eq!(FormatId::Custom(7).extension(), ".db");
     }
 
     #[test]
@@ -77,5 +154,6 @@ mod tests {
         let b = a; // Copy
         assert_eq!(a, b);
         assert_ne!(FormatId::Json, FormatId::Bin);
+        assert_ne!(FormatId::Custom(0), FormatId::Custom(1));
     }
 }
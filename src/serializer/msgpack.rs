@@ -0,0 +1,70 @@
+use super::{FormatId, Serializer};
+use anyhow::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// MessagePack. Structs are written as maps keyed by field name, so payloads
+/// are self-describing and survive fields being reordered or added.
+#[derive(Debug, Default)]
+pub struct MsgPack;
+
+#[cfg(feature = "msgpack_ser")]
+impl Serializer for MsgPack {
+    fn format_id(&self) -> FormatId {
+        FormatId::MsgPack
+    }
+
+    fn serialize<T>(&self, data: &T) -> Result<Vec<u8>, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(rmp_serde::to_vec_named(data)?)
+    }
+
+    fn serialize_into<T>(&self, data: &T, writer: &mut impl Write) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(rmp_serde::encode::write_named(writer, data)?)
+    }
+
+    fn deserialize<'de, T>(&self, data: &'de [u8]) -> Result<T, Error>
+    where
+        T: Deserialize<'de>,
+    {
+        Ok(rmp_serde::from_slice(data)?)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "msgpack_ser")]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct S {
+        x: u32,
+    }
+
+    #[test]
+    fn round_trip() {
+        let s = S { x: 42 };
+        let ser = MsgPack.serialize(&s).unwrap();
+        let de: S = MsgPack.deserialize(&ser).unwrap();
+        assert_eq!(de, s);
+    }
+
+    #[test]
+    fn structs_are_keyed_by_field_name() {
+        let bytes = MsgPack.serialize(&S { x: 7 }).unwrap();
+        let map: BTreeMap<String, u32> = MsgPack.deserialize(&bytes).unwrap();
+        assert_eq!(map, BTreeMap::from([("x".to_string(), 7)]));
+    }
+
+    #[test]
+    fn format_id_is_msgpack() {
+        assert_eq!(MsgPack.format_id(), FormatId::MsgPack);
+    }
+}
@@ -0,0 +1,77 @@
+use super::{FormatId, Serializer};
+use anyhow::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// Postcard: the most compact format, with varint integers and no field
+/// names. Like Bin, it is not self-describing, so a document type must match
+/// the one it was written with field for field.
+#[derive(Debug, Default)]
+pub struct Postcard;
+
+#[cfg(feature = "postcard_ser")]
+impl Serializer for Postcard {
+    fn format_id(&self) -> FormatId {
+        FormatId::Postcard
+    }
+
+    fn serialize<T>(&self, data: &T) -> Result<Vec<u8>, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(postcard::to_stdvec(data)?)
+    }
+
+    fn serialize_into<T>(&self, data: &T, writer: &mut impl Write) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        postcard::to_io(data, writer)?;
+        Ok(())
+    }
+
+    fn deserialize<'de, T>(&self, data: &'de [u8]) -> Result<T, Error>
+    where
+        T: Deserialize<'de>,
+    {
+        Ok(postcard::from_bytes(data)?)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "postcard_ser")]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct S {
+        x: u32,
+        name: String,
+    }
+
+    #[test]
+    fn round_trip() {
+        let s = S {
+            x: 300,
+            name: "hello".into(),
+        };
+        let ser = Postcard.serialize(&s).unwrap();
+        let de: S = Postcard.deserialize(&ser).unwrap();
+        assert_eq!(de, s);
+    }
+
+    #[test]
+    fn encoding_is_compact() {
+        let s = S {
+            x: 1,
+            name: "ab".into(),
+        };
+        assert_eq!(Postcard.serialize(&s).unwrap(), [1, 2, b'a', b'b']);
+    }
+
+    #[test]
+    fn format_id_is_postcard() {
+        assert_eq!(Postcard.format_id(), FormatId::Postcard);
+    }
+}
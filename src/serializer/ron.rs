@@ -1,6 +1,7 @@
 use super::{FormatId, Serializer};
 use anyhow::{Error, Result};
 use serde::{Deserialize, Serialize};
+use std::io::Write;
 
 #[derive(Debug, Default)]
 pub struct Ron;
@@ -13,14 +14,21 @@ impl Serializer for Ron {
 
     fn serialize<T>(&self, data: &T) -> Result<Vec<u8>, Error>
     where
-        for<'de> T: Serialize + Deserialize<'de>,
+        T: Serialize + ?Sized,
     {
         Ok(::ron::ser::to_string(data)?.into_bytes())
     }
 
-    fn deserialize<T>(&self, data: &[u8]) -> Result<T, Error>
+    fn serialize_into<T>(&self, data: &T, writer: &mut impl Write) -> Result<(), Error>
     where
-        for<'de> T: Serialize + Deserialize<'de>,
+        T: Serialize + ?Sized,
+    {
+        Ok(::ron::ser::to_writer(writer, data)?)
+    }
+
+    fn deserialize<'de, T>(&self, data: &'de [u8]) -> Result<T, Error>
+    where
+        T: Deserialize<'de>,
     {
         Ok(::ron::de::from_bytes(data)?)
     }
@@ -1,6 +1,7 @@
 use super::{FormatId, Serializer};
 use anyhow::{Error, Result};
 use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
 
 #[derive(Debug, Default)]
 pub struct Yaml;
@@ -13,7 +14,7 @@ impl Serializer for Yaml {
 
     fn serialize<T>(&self, data: &T) -> Result<Vec<u8>, Error>
     where
-        for<'de> T: Serialize + Deserialize<'de>,
+        T: Serialize + ?Sized,
     {
         let mut bytes = serde_yaml::to_string(data)?.into_bytes();
         // serde_yaml appends a trailing newline; strip it so record framing
@@ -24,14 +25,51 @@ impl Serializer for Yaml {
         Ok(bytes)
     }
 
-    fn deserialize<T>(&self, data: &[u8]) -> Result<T, Error>
+    fn serialize_into<T>(&self, data: &T, writer: &mut impl Write) -> Result<(), Error>
     where
-        for<'de> T: Serialize + Deserialize<'de>,
+        T: Serialize + ?Sized,
+    {
+        let mut writer = TrimNewline {
+            inner: writer,
+            pending: false,
+        };
+        Ok(serde_yaml::to_writer(&mut writer, data)?)
+    }
+
+    fn deserialize<'de, T>(&self, data: &'de [u8]) -> Result<T, Error>
+    where
+        T: Deserialize<'de>,
     {
         Ok(serde_yaml::from_slice(data)?)
     }
 }
 
+/// Passes writes on to `inner` but holds back a trailing newline until more
+/// output follows, so the one serde_yaml ends its output with is dropped.
+struct TrimNewline<W> {
+    inner: W,
+    pending: bool,
+}
+
+impl<W: Write> Write for TrimNewline<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some((&last, body)) = buf.split_last() else {
+            return Ok(0);
+        };
+        if self.pending {
+            self.inner.write_all(b"\n")?;
+        }
+        self.pending = last == b'\n';
+        self.inner
+            .write_all(if self.pending { body } else { buf })?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "yaml_ser")]
 mod tests {
@@ -58,6 +96,17 @@ mod tests {
         assert_ne!(bytes.last().copied(), Some(b'\n'));
     }
 
+    #[test]
+    fn serialize_into_drops_only_the_final_newline() {
+        let nested = vec![vec![S { x: 1 }, S { x: 2 }], vec![S { x: 3 }]];
+        let mut buf = b"prefix".to_vec();
+        Yaml.serialize_into(&nested, &mut buf).unwrap();
+        let expected = Yaml.serialize(&nested).unwrap();
+        assert_eq!(&buf[..6], b"prefix");
+        assert_eq!(buf[6..], expected[..]);
+        assert!(expected.contains(&b'\n'));
+    }
+
     #[test]
     fn format_id_is_yaml() {
         assert_eq!(Yaml.format_id(), FormatId::Yaml);
@@ -0,0 +1,224 @@
+//! Backup files shared by the file-backed engines.
+//!
+//! A backup has the layout of a [`FileStorage`](super::FileStorage) file: a
+//! header, one Insert per document of the snapshot it was taken from, then
+//! the records of any incremental backups appended after it. Replaying it
+//! gives the state as of the latest backup.
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use super::file::sync_dir;
+use super::format::{
+    build_header, read_header, replay_records, write_record, Framing, Record, Replay, Stamp,
+};
+use crate::config::DbConfig;
+use crate::error::Result;
+use crate::serializer::FormatId;
+use crate::wal::WalOp;
+use crate::RedDbHM;
+use uuid::Uuid;
+
+/// What [`RedDb::backup_to`](crate::RedDb::backup_to) and
+/// [`RedDb::backup_incremental_to`](crate::RedDb::backup_incremental_to) wrote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackupReport {
+    /// Whether records were appended to an existing backup. `false` means a
+    /// full snapshot was written, possibly in place of an incremental backup.
+    pub incremental: bool,
+    /// Number of records written to the backup file.
+    pub records: u64,
+}
+
+/// Where the last backup left off, so the next incremental one knows which
+/// records to append. `P` is the engine's position in its log.
+#[derive(Debug)]
+pub(super) struct BackupCursor<P> {
+    pub(super) dest: PathBuf,
+    /// Length of the backup file after the last backup; anything else means
+    /// it was replaced or modified since.
+    pub(super) len: u64,
+    pub(super) framing: Framing,
+    pub(super) position: P,
+}
+
+impl<P> BackupCursor<P> {
+    /// Whether the next backup to `dest` can append to the previous one.
+    pub(super) fn continues(&self, dest: &Path) -> bool {
+        self.dest == dest
+    }
+}
+
+fn tmp_path(dest: &Path) -> PathBuf {
+    let mut name = dest.as_os_str().to_owned();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+/// The payload of an entry of a map that holds payloads, for [`write_full`].
+pub(super) fn stored<'a>(_id: &Uuid, entry: &'a [u8]) -> Result<Option<Cow<'a, [u8]>>> {
+    Ok(Some(Cow::Borrowed(entry)))
+}
+
+/// Write `data` as a compacted file at `dest`, replacing it atomically, and
+/// return the file's length. `base` is the stamp of the last record `data`
+/// reflects. `payload` gives the payload to write for an entry, or `None` to
+/// leave it out.
+pub(super) async fn write_full<'a>(
+    dest: &Path,
+    format: FormatId,
+    framing: &Framing,
+    data: &'a RedDbHM,
+    base: Stamp,
+    payload: impl Fn(&Uuid, &'a [u8]) -> Result<Option<Cow<'a, [u8]>>>,
+) -> Result<u64> {
+    let tmp_path = tmp_path(dest);
+    let mut tmp = File::create(&tmp_path).await?;
+    tmp.write_all(&build_header(format, framing, base.lsn))
+        .await?;
+    for (id, entry) in data {
+        if let Some(payload) = payload(id, entry)? {
+            write_record(&mut tmp, WalOp::Insert, *id, &payload, base, framing).await?;
+        }
+    }
+    tmp.sync_all().await?;
+    let len = tmp.metadata().await?.len();
+    drop(tmp);
+    fs::rename(&tmp_path, dest).await?;
+    sync_dir(dest).await?;
+    Ok(len)
+}
+
+/// Append `records` to the backup described by `cursor` and return its new
+/// length, or `None` if the file is no longer the one the cursor describes.
+pub(super) async fn append<P>(cursor: &BackupCursor<P>, records: &[Record]) -> Result<Option<u64>> {
+    let mut file = match OpenOptions::new().append(true).open(&cursor.dest).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut len = file.metadata().await?.len();
+    if len != cursor.len {
+        return Ok(None);
+    }
+    for r in records {
+        len += write_record(&mut file, r.op, r.id, &r.payload, r.stamp, &cursor.framing).await?;
+    }
+    file.sync_data().await?;
+    Ok(Some(len))
+}
+
+/// Read and check the backup at `path` for a database of `format` opened
+/// with `config`. A torn trailing record, left by a crash during an
+/// incremental backup, is dropped; any other damage is an error.
+pub(crate) async fn read_backup(
+    path: &Path,
+    format: FormatId,
+    config: &DbConfig,
+) -> Result<RedDbHM> {
+    let mut file = File::open(path).await?;
+    let (framing, _) = read_header(&mut file, format, &Framing::from_config(config)).await?;
+    let mut replay = Replay::default();
+    replay_records(&mut file, &framing, |record| {
+        replay.apply(record);
+        Ok(())
+    })
+    .await?;
+    Ok(replay.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framing() -> Framing {
+        Framing::from_config(&DbConfig::default())
+    }
+
+    #[tokio::test]
+    async fn full_backup_replays_to_the_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("backup.bin");
+        let mut data: RedDbHM = RedDbHM::new();
+        data.insert(Uuid::new_v4(), b"one".to_vec());
+        data.insert(Uuid::new_v4(), b"two".to_vec());
+
+        let len = write_full(
+            &dest,
+            FormatId::Bin,
+            &framing(),
+            &data,
+            Stamp::default(),
+            stored,
+        )
+        .await
+        .unwrap();
+        assert_eq!(len, fs::metadata(&dest).await.unwrap().len());
+        assert!(!tmp_path(&dest).exists());
+        let read = read_backup(&dest, FormatId::Bin, &DbConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(read, data);
+    }
+
+    #[tokio::test]
+    async fn append_refuses_a_file_changed_since_the_cursor() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("backup.bin");
+        let id = Uuid::new_v4();
+        let len = write_full(
+            &dest,
+            FormatId::Bin,
+            &framing(),
+            &RedDbHM::new(),
+            Stamp::default(),
+            stored,
+        )
+        .await
+        .unwrap();
+        let mut cursor = BackupCursor {
+            dest: dest.clone(),
+            len,
+            framing: framing(),
+            position: (),
+        };
+        let records = vec![Record {
+            op: WalOp::Insert,
+            id,
+            payload: b"x".to_vec(),
+            stamp: Stamp::default().next(),
+        }];
+
+        cursor.len = append(&cursor, &records).await.unwrap().unwrap();
+        let read = read_backup(&dest, FormatId::Bin, &DbConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(read.get(&id).unwrap(), b"x");
+
+        cursor.len -= 1;
+        assert_eq!(append(&cursor, &records).await.unwrap(), None);
+        cursor.dest = dir.path().join("missing.bin");
+        assert_eq!(append(&cursor, &records).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn backup_of_another_format_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("backup.bin");
+        write_full(
+            &dest,
+            FormatId::Bin,
+            &framing(),
+            &RedDbHM::new(),
+            Stamp::default(),
+            stored,
+        )
+        .await
+        .unwrap();
+        assert!(read_backup(&dest, FormatId::Json, &DbConfig::default())
+            .await
+            .is_err());
+    }
+}
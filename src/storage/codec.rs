@@ -0,0 +1,173 @@
+//! Payload compression codecs and their header ids.
+
+use std::borrow::Cow;
+
+use crate::config::Compression;
+use crate::error::{RedDbError, Result};
+
+const NONE: u8 = 0;
+#[cfg(feature = "lz4_compression")]
+const LZ4: u8 = 1;
+#[cfg(feature = "zstd_compression")]
+const ZSTD: u8 = 2;
+
+/// zstd level used to append to a zstd file opened with a different codec
+/// configured; the level is not recorded in the header.
+#[cfg(feature = "zstd_compression")]
+const ZSTD_DEFAULT_LEVEL: i32 = 3;
+
+pub(super) fn codec_id(compression: Compression) -> u8 {
+    match compression {
+        Compression::None => NONE,
+        #[cfg(feature = "lz4_compression")]
+        Compression::Lz4 => LZ4,
+        #[cfg(feature = "zstd_compression")]
+        Compression::Zstd(_) => ZSTD,
+    }
+}
+
+/// The codec stored under header id `id`. Codecs whose cargo feature is off
+/// are reported as unsupported rather than corrupt.
+pub(super) fn from_codec_id(id: u8) -> Result<Compression> {
+    match id {
+        NONE => Ok(Compression::None),
+        #[cfg(feature = "lz4_compression")]
+        LZ4 => Ok(Compression::Lz4),
+        #[cfg(feature = "zstd_compression")]
+        ZSTD => Ok(Compression::Zstd(ZSTD_DEFAULT_LEVEL)),
+        other => Err(RedDbError::UnsupportedCompression(other)),
+    }
+}
+
+/// Compress a record payload. Empty payloads (delete records) stay empty.
+pub(super) fn compress(compression: Compression, payload: &[u8]) -> Result<Cow<'_, [u8]>> {
+    if payload.is_empty() {
+        return Ok(Cow::Borrowed(payload));
+    }
+    match compression {
+        Compression::None => Ok(Cow::Borrowed(payload)),
+        #[cfg(feature = "lz4_compression")]
+        Compression::Lz4 => Ok(Cow::Owned(lz4_flex::compress_prepend_size(payload))),
+        #[cfg(feature = "zstd_compression")]
+        Compression::Zstd(level) => Ok(Cow::Owned(zstd::bulk::compress(payload, level)?)),
+    }
+}
+
+/// Reverse [`compress`]. `None` means the bytes do not decode with the codec.
+pub(super) fn decompress(compression: Compression, stored: &[u8]) -> Option<Vec<u8>> {
+    if stored.is_empty() {
+        return Some(Vec::new());
+    }
+    match compression {
+        Compression::None => Some(stored.to_vec()),
+        #[cfg(feature = "lz4_compression")]
+        Compression::Lz4 => lz4_flex::decompress_size_prepended(stored).ok(),
+        #[cfg(feature = "zstd_compression")]
+        Compression::Zstd(_) => zstd::stream::decode_all(stored).ok(),
+    }
+}
+
+/// Payloads compressed by [`estimate_compressed`] to measure the ratio.
+const SAMPLE_SIZE: usize = 64;
+
+/// Estimate the compressed size of payloads totalling `total` bytes from a
+/// sample of them, without compressing everything.
+pub(super) fn estimate_compressed<'a>(
+    compression: Compression,
+    payloads: impl Iterator<Item = &'a [u8]>,
+    total: u64,
+) -> u64 {
+    if compression == Compression::None {
+        return total;
+    }
+    let (raw, stored) = payloads
+        .take(SAMPLE_SIZE)
+        .fold((0u64, 0u64), |(raw, stored), payload| {
+            let len = compress(compression, payload).map_or(payload.len(), |c| c.len());
+            (raw + payload.len() as u64, stored + len as u64)
+        });
+    if raw == 0 {
+        return total;
+    }
+    (total as u128 * stored as u128 / raw as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codecs() -> Vec<Compression> {
+        vec![
+            Compression::None,
+            #[cfg(feature = "lz4_compression")]
+            Compression::Lz4,
+            #[cfg(feature = "zstd_compression")]
+            Compression::Zstd(3),
+        ]
+    }
+
+    #[test]
+    fn every_codec_round_trips() {
+        let payload = b"{\"name\":\"reddb\",\"tags\":[\"a\",\"a\",\"a\",\"a\"]}".repeat(8);
+        for codec in codecs() {
+            let stored = compress(codec, &payload).unwrap();
+            assert_eq!(decompress(codec, &stored).unwrap(), payload, "{codec:?}");
+        }
+    }
+
+    #[test]
+    fn empty_payloads_stay_empty() {
+        for codec in codecs() {
+            assert!(compress(codec, b"").unwrap().is_empty());
+            assert_eq!(decompress(codec, b"").unwrap(), Vec::<u8>::new());
+        }
+    }
+
+    #[test]
+    fn codec_ids_round_trip() {
+        for codec in codecs() {
+            let id = codec_id(codec);
+            assert_eq!(codec_id(from_codec_id(id).unwrap()), id);
+        }
+    }
+
+    #[test]
+    fn unknown_codec_id_is_unsupported() {
+        assert!(matches!(
+            from_codec_id(0xEE),
+            Err(RedDbError::UnsupportedCompression(0xEE))
+        ));
+    }
+
+    #[cfg(feature = "lz4_compression")]
+    #[test]
+    fn lz4_shrinks_repetitive_payloads() {
+        let payload = b"abcdefgh".repeat(64);
+        assert!(compress(Compression::Lz4, &payload).unwrap().len() < payload.len() / 4);
+    }
+
+    #[test]
+    fn estimate_is_exact_without_compression() {
+        let payloads = [b"abc".as_slice(), b"defg".as_slice()];
+        assert_eq!(
+            estimate_compressed(Compression::None, payloads.into_iter(), 7),
+            7
+        );
+    }
+
+    #[cfg(feature = "zstd_compression")]
+    #[test]
+    fn estimate_scales_total_by_sampled_ratio() {
+        let payload = b"abcdefgh".repeat(64);
+        let payloads = std::iter::repeat_n(payload.as_slice(), 100);
+        let total = payload.len() as u64 * 100;
+        let estimate = estimate_compressed(Compression::Zstd(3), payloads, total);
+        assert!(estimate < total / 4, "estimate {estimate} of {total}");
+    }
+
+    #[cfg(feature = "zstd_compression")]
+    #[test]
+    fn garbage_does_not_decompress() {
+        assert!(decompress(Compression::Zstd(3), b"not zstd").is_none());
+    }
+}
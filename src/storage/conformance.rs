@@ -0,0 +1,356 @@
+//! Conformance checks for [`Storage`] implementations.
+//!
+//! Every check opens its own store through `ST::new`, using `config` with the
+//! check name appended to [`DbConfig::name`], and panics with a descriptive
+//! message when the backend breaks one of the [`Storage`] invariants. Call
+//! [`run`] from an ordinary test, pointing `config.dir` at a scratch directory:
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn blob_store_conforms() {
+//!     let dir = tempfile::tempdir().unwrap();
+//!     let config = DbConfig::new("blob").dir(dir.path());
+//!     conformance::run::<BlobStorage>(&config, Durability::Persistent).await;
+//! }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use super::Storage;
+use crate::config::DbConfig;
+use crate::document::Document;
+use crate::wal::WalOp;
+use crate::RedDbHM;
+
+/// Whether a backend is expected to keep records across instances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// A store reopened with the same config must `load` what was persisted
+    /// before the previous instance was dropped.
+    Persistent,
+    /// Nothing survives the instance (e.g. [`MemStorage`](super::MemStorage));
+    /// only the call contracts are checked.
+    Volatile,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Probe {
+    n: u32,
+    label: String,
+}
+
+fn probe(n: u32) -> Document<Probe> {
+    Document::new(
+        Uuid::new_v4(),
+        Probe {
+            n,
+            label: format!("probe_{n}"),
+        },
+    )
+}
+
+fn scoped(config: &DbConfig, check: &str) -> DbConfig {
+    let mut config = config.clone();
+    config.name = format!("{}_{}", config.name, check);
+    config
+}
+
+async fn open<ST>(config: &DbConfig) -> ST
+where
+    ST: Storage,
+{
+    ST::new(config)
+        .await
+        .unwrap_or_else(|e| panic!("Storage::new failed: {e}"))
+}
+
+async fn reopen_and_load<ST>(config: &DbConfig) -> RedDbHM
+where
+    ST: Storage,
+{
+    let storage: ST = open(config).await;
+    storage
+        .load::<Probe>()
+        .await
+        .unwrap_or_else(|e| panic!("Storage::load failed: {e}"))
+}
+
+/// Run every check against `ST`.
+pub async fn run<ST>(config: &DbConfig, durability: Durability)
+where
+    ST: Storage + Sync,
+{
+    new_store_loads_empty::<ST>(config).await;
+    calls_succeed::<ST>(config).await;
+    if durability == Durability::Persistent {
+        persisted_records_are_replayed::<ST>(config).await;
+        raw_payloads_are_stored_unchanged::<ST>(config).await;
+        replay_applies_records_in_order::<ST>(config).await;
+        unknown_ids_are_ignored::<ST>(config).await;
+        compact_preserves_live_state::<ST>(config).await;
+        writes_after_compact_apply_on_top::<ST>(config).await;
+        online_compact_preserves_live_state::<ST>(config).await;
+    }
+}
+
+/// A store that has never been written to loads as an empty map.
+pub async fn new_store_loads_empty<ST>(config: &DbConfig)
+where
+    ST: Storage,
+{
+    let config = scoped(config, "new_store_loads_empty");
+    let map = reopen_and_load::<ST>(&config).await;
+    assert!(map.is_empty(), "fresh store loaded {} records", map.len());
+}
+
+/// Every trait method accepts well-formed input.
+pub async fn calls_succeed<ST>(config: &DbConfig)
+where
+    ST: Storage,
+{
+    let config = scoped(config, "calls_succeed");
+    let storage: ST = open(&config).await;
+    let map = storage.load::<Probe>().await.expect("load");
+    let doc = probe(1);
+    storage
+        .persist(std::slice::from_ref(&doc), WalOp::Insert)
+        .await
+        .expect("persist");
+    storage
+        .persist_raw(&[(WalOp::Delete, doc.id, Vec::new())])
+        .await
+        .expect("persist_raw");
+    storage.compact(&map).await.expect("compact");
+    storage.file_size().await.expect("file_size");
+}
+
+/// Typed inserts, updates and deletes survive a reopen.
+pub async fn persisted_records_are_replayed<ST>(config: &DbConfig)
+where
+    ST: Storage,
+{
+    let config = scoped(config, "persisted_records_are_replayed");
+    let kept = probe(1);
+    let removed = probe(2);
+    {
+        let storage: ST = open(&config).await;
+        storage.load::<Probe>().await.expect("load");
+        storage
+            .persist(&[kept.clone(), removed.clone()], WalOp::Insert)
+            .await
+            .expect("persist insert");
+    }
+
+    let inserted = reopen_and_load::<ST>(&config).await;
+    assert_eq!(inserted.len(), 2, "expected two inserted records");
+    let first_payload = inserted
+        .get(&kept.id)
+        .cloned()
+        .expect("inserted record missing after reopen");
+    assert!(!first_payload.is_empty(), "insert payload is empty");
+
+    {
+        let storage: ST = open(&config).await;
+        storage.load::<Probe>().await.expect("load");
+        let mut updated = kept.clone();
+        updated.data.n = 10;
+        storage
+            .persist(&[updated], WalOp::Update)
+            .await
+            .expect("persist update");
+        storage
+            .persist(std::slice::from_ref(&removed), WalOp::Delete)
+            .await
+            .expect("persist delete");
+    }
+
+    let map = reopen_and_load::<ST>(&config).await;
+    assert_eq!(map.len(), 1, "expected one live record, got {}", map.len());
+    assert!(!map.contains_key(&removed.id), "deleted record came back");
+    let payload = map
+        .get(&kept.id)
+        .expect("updated record missing after reopen");
+    assert_ne!(payload, &first_payload, "update was not applied on replay");
+}
+
+/// `persist_raw` payloads come back byte for byte.
+pub async fn raw_payloads_are_stored_unchanged<ST>(config: &DbConfig)
+where
+    ST: Storage,
+{
+    let config = scoped(config, "raw_payloads_are_stored_unchanged");
+    let id = Uuid::new_v4();
+    let payload = vec![0x00, 0x0A, 0xFF, b'\n', 0x7F];
+    {
+        let storage: ST = open(&config).await;
+        storage.load::<Probe>().await.expect("load");
+        storage
+            .persist_raw(&[(WalOp::Insert, id, payload.clone())])
+            .await
+            .expect("persist_raw");
+    }
+    let map = reopen_and_load::<ST>(&config).await;
+    assert_eq!(map.get(&id), Some(&payload), "raw payload changed");
+}
+
+/// Records are replayed in the order they were written, within and across
+/// batches, and documents load in the order they were inserted.
+pub async fn replay_applies_records_in_order<ST>(config: &DbConfig)
+where
+    ST: Storage,
+{
+    let config = scoped(config, "replay_applies_records_in_order");
+    let a = Uuid::new_v4();
+    let b = Uuid::new_v4();
+    {
+        let storage: ST = open(&config).await;
+        storage.load::<Probe>().await.expect("load");
+        storage
+            .persist_raw(&[
+                (WalOp::Insert, a, b"a1".to_vec()),
+                (WalOp::Insert, b, b"b1".to_vec()),
+                (WalOp::Update, a, b"a2".to_vec()),
+            ])
+            .await
+            .expect("persist_raw");
+        storage
+            .persist_raw(&[
+                (WalOp::Delete, b, Vec::new()),
+                (WalOp::Insert, b, b"b2".to_vec()),
+                (WalOp::Update, a, b"a3".to_vec()),
+            ])
+            .await
+            .expect("persist_raw");
+    }
+    let map = reopen_and_load::<ST>(&config).await;
+    assert_eq!(map.get(&a).map(Vec::as_slice), Some(&b"a3"[..]));
+    assert_eq!(map.get(&b).map(Vec::as_slice), Some(&b"b2"[..]));
+    assert_eq!(map.len(), 2);
+    assert!(map.keys().eq([&a, &b]), "documents loaded out of order");
+}
+
+/// Deleting an id that was never inserted does not fail the load.
+pub async fn unknown_ids_are_ignored<ST>(config: &DbConfig)
+where
+    ST: Storage,
+{
+    let config = scoped(config, "unknown_ids_are_ignored");
+    let known = Uuid::new_v4();
+    {
+        let storage: ST = open(&config).await;
+        storage.load::<Probe>().await.expect("load");
+        storage
+            .persist_raw(&[
+                (WalOp::Delete, Uuid::new_v4(), Vec::new()),
+                (WalOp::Insert, known, b"k".to_vec()),
+            ])
+            .await
+            .expect("persist_raw");
+    }
+    let map = reopen_and_load::<ST>(&config).await;
+    assert_eq!(map.len(), 1);
+    assert!(map.contains_key(&known));
+}
+
+/// After `compact(data)` the store loads exactly `data`.
+pub async fn compact_preserves_live_state<ST>(config: &DbConfig)
+where
+    ST: Storage,
+{
+    let config = scoped(config, "compact_preserves_live_state");
+    let expected = {
+        let storage: ST = open(&config).await;
+        storage.load::<Probe>().await.expect("load");
+        let ids: Vec<Uuid> = (0..8).map(|_| Uuid::new_v4()).collect();
+        let mut ops: Vec<(WalOp, Uuid, Vec<u8>)> = ids
+            .iter()
+            .map(|id| (WalOp::Insert, *id, id.as_bytes().to_vec()))
+            .collect();
+        ops.extend(ids[..4].iter().map(|id| (WalOp::Delete, *id, Vec::new())));
+        storage.persist_raw(&ops).await.expect("persist_raw");
+
+        let live: RedDbHM = ids[4..]
+            .iter()
+            .map(|id| (*id, id.as_bytes().to_vec()))
+            .collect();
+        let before = storage.file_size().await.expect("file_size");
+        storage.compact(&live).await.expect("compact");
+        let after = storage.file_size().await.expect("file_size");
+        assert!(
+            after <= before,
+            "compaction grew the store from {before} to {after} bytes"
+        );
+        live
+    };
+    let map = reopen_and_load::<ST>(&config).await;
+    assert_eq!(map, expected, "compacted store loaded a different state");
+    assert!(
+        map.keys().eq(expected.keys()),
+        "compacted store loaded the documents in a different order"
+    );
+}
+
+/// Records persisted after a compaction are applied on top of the compacted state.
+pub async fn writes_after_compact_apply_on_top<ST>(config: &DbConfig)
+where
+    ST: Storage,
+{
+    let config = scoped(config, "writes_after_compact_apply_on_top");
+    let a = Uuid::new_v4();
+    let b = Uuid::new_v4();
+    {
+        let storage: ST = open(&config).await;
+        storage.load::<Probe>().await.expect("load");
+        let live: RedDbHM = [(a, b"a".to_vec())].into_iter().collect();
+        storage
+            .persist_raw(&[(WalOp::Insert, a, b"a".to_vec())])
+            .await
+            .expect("persist_raw");
+        storage.compact(&live).await.expect("compact");
+        storage
+            .persist_raw(&[
+                (WalOp::Insert, b, b"b".to_vec()),
+                (WalOp::Delete, a, Vec::new()),
+            ])
+            .await
+            .expect("persist_raw");
+    }
+    let map = reopen_and_load::<ST>(&config).await;
+    assert_eq!(map.len(), 1);
+    assert_eq!(map.get(&b).map(Vec::as_slice), Some(&b"b"[..]));
+}
+
+/// `compact_online` keeps the state held by the lock and later writes.
+pub async fn online_compact_preserves_live_state<ST>(config: &DbConfig)
+where
+    ST: Storage + Sync,
+{
+    let config = scoped(config, "online_compact_preserves_live_state");
+    let a = Uuid::new_v4();
+    let b = Uuid::new_v4();
+    {
+        let storage: ST = open(&config).await;
+        storage.load::<Probe>().await.expect("load");
+        storage
+            .persist_raw(&[
+                (WalOp::Insert, a, b"a1".to_vec()),
+                (WalOp::Insert, b, b"b".to_vec()),
+                (WalOp::Delete, b, Vec::new()),
+                (WalOp::Update, a, b"a2".to_vec()),
+            ])
+            .await
+            .expect("persist_raw");
+        let live = RwLock::new([(a, b"a2".to_vec())].into_iter().collect::<RedDbHM>());
+        storage.compact_online(&live).await.expect("compact_online");
+        storage
+            .persist_raw(&[(WalOp::Insert, b, b"b2".to_vec())])
+            .await
+            .expect("persist_raw");
+    }
+    let map = reopen_and_load::<ST>(&config).await;
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.get(&a).map(Vec::as_slice), Some(&b"a2"[..]));
+    assert_eq!(map.get(&b).map(Vec::as_slice), Some(&b"b2"[..]));
+}
@@ -0,0 +1,151 @@
+//! Authenticated encryption of record payloads (XChaCha20-Poly1305).
+//!
+//! A sealed payload is `[24-byte random nonce][ciphertext + 16-byte tag]`. The
+//! record's op and id are bound as associated data, so a payload cannot be
+//! moved to another record without failing authentication.
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config::EncryptionKey;
+use crate::error::{RedDbError, Result};
+
+/// Header id of XChaCha20-Poly1305.
+pub(super) const CIPHER_ID: u8 = 1;
+
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+
+/// Bytes a sealed payload adds to the plaintext.
+pub(super) const SEAL_OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+
+/// Length of the key-check value stored in the header.
+pub(super) const KCV_LEN: usize = 8;
+
+const KCV_AAD: &[u8] = b"reddb key check";
+
+#[derive(Clone)]
+pub(super) struct Cipher {
+    aead: Arc<XChaCha20Poly1305>,
+    kcv: [u8; KCV_LEN],
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cipher").finish_non_exhaustive()
+    }
+}
+
+impl Cipher {
+    pub(super) fn new(key: &EncryptionKey) -> Self {
+        let aead = XChaCha20Poly1305::new(key.as_bytes().into());
+        // The tag of an empty message under a fixed nonce identifies the key
+        // without revealing anything about it.
+        let tag = aead
+            .encrypt(
+                &XNonce::default(),
+                Payload {
+                    msg: &[],
+                    aad: KCV_AAD,
+                },
+            )
+            .expect("sealing an empty message cannot fail");
+        let mut kcv = [0u8; KCV_LEN];
+        kcv.copy_from_slice(&tag[..KCV_LEN]);
+        Cipher {
+            aead: Arc::new(aead),
+            kcv,
+        }
+    }
+
+    /// Key-check value written to the header of files sealed with this key.
+    pub(super) fn kcv(&self) -> [u8; KCV_LEN] {
+        self.kcv
+    }
+
+    pub(super) fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .aead
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| RedDbError::PersistFailed("payload encryption failed".into()))?;
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Reverse [`seal`](Self::seal). `None` means the payload fails authentication.
+    pub(super) fn open(&self, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < SEAL_OVERHEAD {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.aead
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> EncryptionKey {
+        EncryptionKey::new([byte; 32])
+    }
+
+    #[test]
+    fn sealed_payloads_round_trip() {
+        let cipher = Cipher::new(&key(1));
+        let sealed = cipher.seal(b"aad", b"secret").unwrap();
+        assert_eq!(sealed.len(), b"secret".len() + SEAL_OVERHEAD);
+        assert_eq!(cipher.open(b"aad", &sealed).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn sealing_is_randomized() {
+        let cipher = Cipher::new(&key(1));
+        assert_ne!(
+            cipher.seal(b"", b"same").unwrap(),
+            cipher.seal(b"", b"same").unwrap()
+        );
+    }
+
+    #[test]
+    fn open_rejects_wrong_key_aad_or_tampering() {
+        let cipher = Cipher::new(&key(1));
+        let mut sealed = cipher.seal(b"aad", b"secret").unwrap();
+        assert!(Cipher::new(&key(2)).open(b"aad", &sealed).is_none());
+        assert!(cipher.open(b"other", &sealed).is_none());
+        let last = sealed.len() - 1;
+        sealed[last] ^= 0x01;
+        assert!(cipher.open(b"aad", &sealed).is_none());
+        assert!(cipher.open(b"aad", &[0u8; 10]).is_none());
+    }
+
+    #[test]
+    fn key_check_value_depends_only_on_the_key() {
+        assert_eq!(Cipher::new(&key(1)).kcv(), Cipher::new(&key(1)).kcv());
+        assert_ne!(Cipher::new(&key(1)).kcv(), Cipher::new(&key(2)).kcv());
+    }
+
+    #[test]
+    fn debug_does_not_print_the_key() {
+        assert_eq!(format!("{:?}", Cipher::new(&key(7))), "Cipher { .. }");
+    }
+}
@@ -1,65 +1,61 @@
 use async_trait::async_trait;
 use core::fmt::Debug;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::io;
-
-use super::Storage;
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "paged")]
+use std::sync::Arc;
+
+use super::backup::{self, BackupCursor, BackupReport};
+use super::codec::estimate_compressed;
+#[cfg(feature = "encryption")]
+use super::crypto::Cipher;
+use super::format::{
+    build_header, copy_payload, encode_record_into, last_stamp, read_header, read_records_from,
+    record_overhead, replay_extents, write_record, Cutoff, Framing, Replay, Stamp, HEADER_LEN,
+    KEPT_FRAMES,
+};
+#[cfg(feature = "paged")]
+use super::format::{Extent, Record};
+use super::group_commit::GroupCommit;
+use super::lock::LockFile;
+#[cfg(feature = "paged")]
+use super::paged::{place, PageIndex, Paged};
+use super::salvage::Salvager;
+use super::verify::Verifier;
+use super::{should_compact, PayloadCheck, SalvageReport, Storage, VerifyReport};
+#[cfg(feature = "encryption")]
+use crate::config::EncryptionKey;
+use crate::config::{AsOf, DbConfig};
 use crate::document::Document;
 use crate::error::{RedDbError, Result};
-use crate::serializer::{FormatId, Serializer};
+use crate::serializer::Serializer;
 use crate::wal::WalOp;
 use crate::RedDbHM;
 use tokio::fs::{File, OpenOptions};
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
-use tokio::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, RwLock};
 use uuid::Uuid;
 
-/// 32-byte file header layout:
-/// [0..8]   magic   b"REDDB\x00\x02\x00"
-/// [8..10]  version u16 LE (2)
-/// [10]     format  u8  (FormatId discriminant)
-/// [11..32] reserved (zeroed)
-const HEADER_LEN: u64 = 32;
-const MAGIC: &[u8; 8] = b"REDDB\x00\x02\x00";
-const VERSION: u16 = 2;
-
-/// Per-record layout: [u32 LE payload_len][u8 op][u8;16 uuid][payload_len bytes]
-const RECORD_OVERHEAD: usize = 21; // 4 + 1 + 16
-
-fn build_header(format: FormatId) -> [u8; 32] {
-    let mut h = [0u8; 32];
-    h[0..8].copy_from_slice(MAGIC);
-    h[8..10].copy_from_slice(&VERSION.to_le_bytes());
-    h[10] = format as u8;
-    h
-}
-
-fn verify_header(header: &[u8; 32], expected: FormatId) -> Result<()> {
-    if &header[0..8] != MAGIC {
-        return Err(RedDbError::DataCorrupted);
-    }
-    let version = u16::from_le_bytes(header[8..10].try_into().unwrap());
-    if version != VERSION {
-        return Err(RedDbError::DataCorrupted);
-    }
-    if header[10] != expected as u8 {
-        return Err(RedDbError::DataCorrupted);
-    }
-    Ok(())
-}
-
-/// Byte size the file would have after compacting `data`.
-fn compacted_size(data: &RedDbHM) -> u64 {
-    let payload_bytes: u64 = data.values().map(|v| v.len() as u64).sum();
-    HEADER_LEN + data.len() as u64 * RECORD_OVERHEAD as u64 + payload_bytes
+/// Byte size the file would have after compacting `data` with `framing`.
+/// Compressed payload sizes are estimated from a sample of the documents.
+pub(super) fn compacted_size(data: &RedDbHM, framing: &Framing) -> u64 {
+    let raw_bytes: u64 = data.values().map(|v| v.len() as u64).sum();
+    let payload_bytes = estimate_compressed(
+        framing.compression,
+        data.values().map(Vec::as_slice),
+        raw_bytes,
+    );
+    let sealed = data.values().filter(|v| !v.is_empty()).count() * framing.seal_overhead();
+    HEADER_LEN + data.len() as u64 * record_overhead(true) as u64 + payload_bytes + sealed as u64
 }
 
-fn should_compact(file_size: u64, live_size: u64, ratio: f64) -> bool {
-    live_size > 0 && (file_size as f64) >= (live_size as f64) * ratio
+pub(super) fn db_path(config: &DbConfig, extension: &str) -> String {
+    format!("{}{}", config.file_stem().to_string_lossy(), extension)
 }
 
-async fn open_append(path: &str) -> Result<File> {
+pub(super) async fn open_append(path: &str) -> Result<File> {
     Ok(OpenOptions::new()
         .read(true)
         .append(true)
@@ -68,62 +64,131 @@ async fn open_append(path: &str) -> Result<File> {
         .await?)
 }
 
-async fn read_records(file: &mut File) -> Result<Vec<(WalOp, Uuid, Vec<u8>)>> {
-    file.seek(SeekFrom::Start(HEADER_LEN)).await?;
-    let mut records = Vec::new();
-    let mut len_buf = [0u8; 4];
-
-    loop {
-        match file.read_exact(&mut len_buf).await {
-            Ok(_) => {}
-            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
-            Err(e) => return Err(e.into()),
-        }
-
-        let payload_len = u32::from_le_bytes(len_buf) as usize;
-
-        let mut meta = [0u8; 17]; // 1 op + 16 uuid
-        file.read_exact(&mut meta).await?;
+/// Remove the compacted copy a crash during compaction, restore or salvage
+/// left next to the log at `db_path`. Until it is renamed over the log the
+/// log is intact and complete, so the copy is never needed. The caller
+/// holds the lock.
+async fn remove_stale_tmp(db_path: &str) -> Result<()> {
+    let tmp_path = format!("{db_path}.tmp");
+    match tokio::fs::remove_file(&tmp_path).await {
+        Ok(()) => sync_dir(Path::new(&tmp_path)).await,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
 
-        let op = match meta[0] {
-            0x01 => WalOp::Insert,
-            0x02 => WalOp::Update,
-            0x03 => WalOp::Delete,
-            _ => return Err(RedDbError::DataCorrupted),
+/// Make the creation, rename or removal of the entry at `path` durable by
+/// syncing the directory that holds it. Until then a crash can undo a rename
+/// that has returned. Directories cannot be synced on Windows, where this
+/// does nothing.
+pub(super) async fn sync_dir(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
         };
+        File::open(dir).await?.sync_all().await?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
 
-        let id = Uuid::from_bytes(meta[1..17].try_into().unwrap());
+/// Abort the process when the `REDDB_CRASH_AT` environment variable names
+/// `step`, for tests of what a crash there leaves behind.
+#[cfg(test)]
+pub(super) fn crash_point(step: &str) {
+    if std::env::var("REDDB_CRASH_AT").is_ok_and(|at| at == step) {
+        std::process::abort();
+    }
+}
 
-        let mut payload = vec![0u8; payload_len];
-        file.read_exact(&mut payload).await?;
+#[cfg(not(test))]
+pub(super) fn crash_point(_step: &str) {}
 
-        records.push((op, id, payload));
-    }
+/// The open log file together with the framing it was written with.
+#[derive(Debug)]
+struct WalFile {
+    file: File,
+    framing: Framing,
+    /// Records appended since the storage was opened, across compactions.
+    written: u64,
+    /// Bumped each time compaction replaces the file, which invalidates
+    /// byte offsets into the old one.
+    generation: u64,
+    /// Stamp of the last record written, which the next one follows.
+    last: Stamp,
+    /// Frames of the batch being appended, kept for the next batch.
+    frames: Vec<u8>,
+}
 
-    Ok(records)
+/// A compacted log being written to a temporary file.
+struct Snapshot {
+    file: File,
+    len: u64,
+    /// Where each record went, kept in paged mode.
+    #[cfg(feature = "paged")]
+    index: Option<PageIndex>,
 }
 
-async fn write_record(file: &mut File, op: WalOp, id: Uuid, payload: &[u8]) -> Result<()> {
-    let len = payload.len() as u32;
-    let mut frame = Vec::with_capacity(RECORD_OVERHEAD + payload.len());
-    frame.extend_from_slice(&len.to_le_bytes());
-    frame.push(match op {
-        WalOp::Insert => 0x01,
-        WalOp::Update => 0x02,
-        WalOp::Delete => 0x03,
-    });
-    frame.extend_from_slice(id.as_bytes());
-    frame.extend_from_slice(payload);
-    file.write_all(&frame).await?;
-    Ok(())
+impl Snapshot {
+    async fn write(
+        &mut self,
+        op: WalOp,
+        id: Uuid,
+        payload: &[u8],
+        stamp: Stamp,
+        framing: &Framing,
+    ) -> Result<()> {
+        let len = write_record(&mut self.file, op, id, payload, stamp, framing).await?;
+        #[cfg(feature = "paged")]
+        if let Some(index) = &mut self.index {
+            let offset = self.len;
+            place(index, op, id, Extent { offset, len });
+        }
+        self.len += len;
+        Ok(())
+    }
 }
 
+/// Single-file storage: a header followed by an append-only log of records,
+/// rewritten in place by compaction.
+///
+/// The file is named after [`DbConfig::file_stem`] with the serializer's
+/// extension. `<file>.lock` is locked while the storage is open, so a second
+/// open of the same file fails with [`RedDbError::Locked`] until it is dropped.
+///
+/// With [`DbConfig::read_only`] set, the file is opened without the lock and
+/// without write access, alongside a writer if there is one. Compaction
+/// replaces the file with an atomic rename, so a reader sees either the old
+/// or the new file, never a partial one.
+///
+/// In paged mode (`paged` feature) only the extent of each document's
+/// record is kept in memory, and payloads are read from a memory mapping of
+/// the file through an LRU cache.
 #[derive(Debug)]
 pub struct FileStorage<SE> {
     file_path: String,
+    /// `None` when opened read-only.
+    lock: Option<LockFile>,
     compaction_ratio: f64,
+    /// Framing of files written from now on; replaced by key rotation.
+    configured: std::sync::RwLock<Framing>,
     serializer: SE,
-    db_file: Mutex<File>,
+    db_file: Mutex<WalFile>,
+    commit: GroupCommit,
+    /// Serializes compactions, which share the temp file.
+    compacting: Mutex<()>,
+    /// Where the last backup ended, as (generation, offset) in the log.
+    /// Also serializes backups.
+    backup: Mutex<Option<BackupCursor<(u64, u64)>>>,
+    /// Where `load` stops replaying, from [`DbConfig::as_of`].
+    as_of: Option<AsOf>,
+    truncated_tail: AtomicU64,
+    /// Record extents and cached payloads, in paged mode.
+    #[cfg(feature = "paged")]
+    paged: Option<Paged>,
 }
 
 #[async_trait]
@@ -131,15 +196,46 @@ impl<SE> Storage for FileStorage<SE>
 where
     SE: Serializer + Debug + Sync + Send,
 {
-    async fn new(db_name: &str, compaction_ratio: f64) -> Result<Self> {
-        let serializer = SE::default();
-        let db_path = format!("{}{}", db_name, serializer.format_id().extension());
-        let file = open_append(&db_path).await?;
+    async fn new(config: &DbConfig) -> Result<Self> {
+        let serializer = SE::for_config(config)?;
+        let db_path = db_path(config, serializer.extension());
+        let (lock, file) = if config.opens_read_only() {
+            (None, File::open(&db_path).await?)
+        } else {
+            let lock = LockFile::acquire(db_path.as_ref())?;
+            remove_stale_tmp(&db_path).await?;
+            (Some(lock), open_append(&db_path).await?)
+        };
+        let framing = Framing::from_config(config);
+        #[cfg(feature = "paged")]
+        let paged = match config.paged {
+            Some(paging) => {
+                let mapped = file.try_clone().await?.into_std().await;
+                Some(Paged::new(paging, mapped, framing.clone()))
+            }
+            None => None,
+        };
         let storage = Self {
+            lock,
             serializer,
-            compaction_ratio,
+            compaction_ratio: config.compaction_ratio,
+            configured: std::sync::RwLock::new(framing.clone()),
             file_path: db_path,
-            db_file: Mutex::new(file),
+            db_file: Mutex::new(WalFile {
+                file,
+                framing,
+                written: 0,
+                generation: 0,
+                last: Stamp::default(),
+                frames: Vec::new(),
+            }),
+            commit: GroupCommit::new(config.sync_mode),
+            compacting: Mutex::new(()),
+            backup: Mutex::new(None),
+            as_of: config.as_of,
+            truncated_tail: AtomicU64::new(0),
+            #[cfg(feature = "paged")]
+            paged,
         };
         storage.init_header().await?;
         Ok(storage)
@@ -150,23 +246,76 @@ where
     where
         for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq + Send + Sync,
     {
-        let (map, file_size) = {
-            let mut file = self.db_file.lock().await;
-            let file_size = file.metadata().await?.len();
-            let records = read_records(&mut file).await?;
-            let mut map: RedDbHM = HashMap::new();
-            for (op, id, payload) in records {
-                if op == WalOp::Delete {
-                    map.remove(&id);
-                } else {
-                    map.insert(id, payload);
+        let (map, file_size, unencrypted) = {
+            let mut wal = self.db_file.lock().await;
+            let framing = wal.framing.clone();
+            let file_size = wal.file.metadata().await?.len();
+            // Records are applied as they are read, so loading needs little
+            // memory beyond the map itself.
+            let mut map = Replay::default();
+            let mut cutoff = self
+                .as_of
+                .map(|p| Cutoff::new(wal.last.lsn, p))
+                .transpose()?;
+            let mut last = wal.last;
+            #[cfg(feature = "paged")]
+            let mut index = PageIndex::new();
+            let valid_len = replay_extents(&mut wal.file, &framing, |record, extent| {
+                if let Some(cutoff) = cutoff.as_mut() {
+                    if !cutoff.admits(record.stamp)? {
+                        return Ok(());
+                    }
                 }
+                last = record.stamp;
+                #[cfg(feature = "paged")]
+                if self.paged.is_some() {
+                    place(&mut index, record.op, record.id, extent);
+                    // The map only lists the id; the payload is read back
+                    // from the file when it is needed.
+                    let record = Record {
+                        payload: Vec::new(),
+                        ..record
+                    };
+                    map.apply(record);
+                    return Ok(());
+                }
+                #[cfg(not(feature = "paged"))]
+                let _ = extent;
+                map.apply(record);
+                Ok(())
+            })
+            .await?;
+            if let Some(cutoff) = &cutoff {
+                cutoff.finish()?;
             }
-            (map, file_size)
+            if valid_len < file_size {
+                // Drop the torn tail so new records are appended after the
+                // last intact one. A reader leaves it to the writer, which
+                // may still be appending it.
+                if self.lock.is_some() {
+                    wal.file.set_len(valid_len).await?;
+                    wal.file.sync_all().await?;
+                }
+                self.truncated_tail
+                    .store(file_size - valid_len, Ordering::Relaxed);
+            }
+            #[cfg(feature = "paged")]
+            if let Some(paged) = &self.paged {
+                let mapped = wal.file.try_clone().await?.into_std().await;
+                paged.reset(index, mapped, framing.clone());
+            }
+            wal.last = last;
+            (map.finish(), valid_len, !framing.encrypted())
         };
+        if self.lock.is_none() {
+            return Ok(map);
+        }
 
-        let live_size = compacted_size(&map);
-        if should_compact(file_size, live_size, self.compaction_ratio) {
+        let new_framing = self.new_framing();
+        let live_size = self.live_size(&map);
+        // A plaintext file opened with a key is encrypted right away.
+        let encrypt = unencrypted && new_framing.encrypted();
+        if encrypt || should_compact(file_size, live_size, self.compaction_ratio) {
             self.compact(&map).await?;
         }
 
@@ -177,71 +326,453 @@ where
     where
         for<'de> T: Serialize + Deserialize<'de> + Debug + Sync + Clone,
     {
-        let mut file = self.db_file.lock().await;
-        for doc in data {
-            let payload = if op == WalOp::Delete {
-                Vec::new()
-            } else {
-                self.serializer
-                    .serialize(&doc.data)
-                    .map_err(|e| RedDbError::Serialize(e.to_string()))?
-            };
-            write_record(&mut file, op, doc.id, &payload).await?;
-        }
-        file.sync_data().await?;
-        Ok(())
+        let records = data.iter().map(|doc| (op, doc.id, &doc.data));
+        self.append(records, |value, buf| {
+            if op == WalOp::Delete {
+                return Ok(());
+            }
+            self.serializer
+                .serialize_into(value, buf)
+                .map_err(|e| RedDbError::Serialize(e.to_string()))
+        })
+        .await
     }
 
     async fn compact(&self, data: &RedDbHM) -> Result<()> {
-        let tmp_path = format!("{}.tmp", self.file_path);
-
-        {
-            let mut tmp = File::create(&tmp_path).await?;
-            let header = build_header(self.serializer.format_id());
-            tmp.write_all(&header).await?;
-            for (id, payload) in data {
-                write_record(&mut tmp, WalOp::Insert, *id, payload).await?;
-            }
-            tmp.sync_all().await?;
-        }
+        self.writable()?;
+        let _compacting = self.compacting.lock().await;
+        let framing = self.new_framing();
+        let base = self.db_file.lock().await.last;
+        let tmp_path = self.tmp_path();
+        let snapshot = self.write_snapshot(&tmp_path, data, &framing, base).await?;
+        snapshot.file.sync_all().await?;
+
+        let mut wal = self.db_file.lock().await;
+        self.replace_with(&mut wal, &tmp_path, snapshot, framing)
+            .await
+    }
 
-        let mut file = self.db_file.lock().await;
-        tokio::fs::rename(&tmp_path, &self.file_path).await?;
-        *file = open_append(&self.file_path).await?;
-        Ok(())
+    async fn compact_online(&self, data: &RwLock<RedDbHM>) -> Result<()> {
+        self.writable()?;
+        let _compacting = self.compacting.lock().await;
+        self.rewrite_online(data, self.new_framing()).await
+    }
+
+    fn compacted_size(&self, data: &RedDbHM) -> u64 {
+        self.live_size(data)
     }
 
     async fn file_size(&self) -> Result<u64> {
-        let file = self.db_file.lock().await;
-        Ok(file.metadata().await?.len())
+        let wal = self.db_file.lock().await;
+        Ok(wal.file.metadata().await?.len())
     }
 
     async fn persist_raw(&self, records: &[(WalOp, Uuid, Vec<u8>)]) -> Result<()> {
-        let mut file = self.db_file.lock().await;
-        for (op, id, payload) in records {
-            write_record(&mut file, *op, *id, payload).await?;
+        let records = records
+            .iter()
+            .map(|(op, id, payload)| (*op, *id, payload.as_slice()));
+        self.append(records, copy_payload).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let written = self.db_file.lock().await.written;
+        self.commit.sync_to(written, || self.current_file()).await
+    }
+
+    #[cfg(feature = "encryption")]
+    async fn rotate_key(&self, key: &EncryptionKey, data: &RwLock<RedDbHM>) -> Result<()> {
+        self.writable()?;
+        let _compacting = self.compacting.lock().await;
+        let framing = Framing {
+            cipher: Some(Cipher::new(key)),
+            ..self.new_framing()
+        };
+        self.rewrite_online(data, framing.clone()).await?;
+        *self.configured.write().unwrap() = framing;
+        Ok(())
+    }
+
+    async fn backup(
+        &self,
+        dest: &Path,
+        data: &RwLock<RedDbHM>,
+        incremental: bool,
+    ) -> Result<BackupReport> {
+        let mut last = self.backup.lock().await;
+        if incremental {
+            if let Some(report) = self.continue_backup(dest, &mut last).await? {
+                return Ok(report);
+            }
         }
-        file.sync_data().await?;
+        // Taken together under the read lock, like `rewrite_online` does.
+        let (snapshot, base, position) = {
+            let data = data.read().await;
+            let wal = self.db_file.lock().await;
+            let offset = wal.file.metadata().await?.len();
+            (data.clone(), wal.last, (wal.generation, offset))
+        };
+        let framing = self.new_framing();
+        let format = self.serializer.format_id();
+        let len = backup::write_full(dest, format, &framing, &snapshot, base, |id, entry| {
+            self.snapshot_payload(id, entry)
+        })
+        .await?;
+        // A reader's data does not follow the file, so it never continues a
+        // backup from the log.
+        *last = self.lock.as_ref().map(|_| BackupCursor {
+            dest: dest.to_path_buf(),
+            len,
+            framing,
+            position,
+        });
+        Ok(BackupReport {
+            incremental: false,
+            records: snapshot.len() as u64,
+        })
+    }
+
+    async fn restore(config: &DbConfig, data: &RedDbHM) -> Result<()>
+    where
+        Self: 'static,
+    {
+        if config.opens_read_only() {
+            return Err(RedDbError::ReadOnly);
+        }
+        let serializer = SE::for_config(config)?;
+        let format = serializer.format_id();
+        let db_path = db_path(config, serializer.extension());
+        let _lock = LockFile::acquire(db_path.as_ref())?;
+        let framing = Framing::from_config(config);
+        // The backup's history is not carried over; sequence numbers restart.
+        backup::write_full(
+            db_path.as_ref(),
+            format,
+            &framing,
+            data,
+            Stamp::default(),
+            backup::stored,
+        )
+        .await?;
         Ok(())
     }
+
+    async fn salvage(config: &DbConfig, check: &PayloadCheck<'_>) -> Result<SalvageReport>
+    where
+        Self: 'static,
+    {
+        if config.opens_read_only() {
+            return Err(RedDbError::ReadOnly);
+        }
+        let serializer = SE::for_config(config)?;
+        let format = serializer.format_id();
+        let db_path = db_path(config, serializer.extension());
+        let _lock = LockFile::acquire(db_path.as_ref())?;
+        let file = File::open(&db_path).await?;
+        let end = file.metadata().await?.len();
+        let framing = Framing::from_config(config);
+        let mut salvager = Salvager::new(db_path.as_ref(), &framing, check);
+        salvager
+            .file(Path::new(&db_path), file, format, end)
+            .await?;
+        let (data, last, report) = salvager.finish().await?;
+        backup::write_full(
+            db_path.as_ref(),
+            format,
+            &framing,
+            &data,
+            last,
+            backup::stored,
+        )
+        .await?;
+        Ok(report)
+    }
+
+    fn truncated_tail_bytes(&self) -> u64 {
+        self.truncated_tail.load(Ordering::Relaxed)
+    }
+
+    async fn verify(&self, check: &PayloadCheck<'_>) -> Result<VerifyReport> {
+        // Opened under the lock, so the file cannot be swapped by compaction
+        // in between; records appended afterwards are left out.
+        let (file, end, framing) = {
+            let wal = self.db_file.lock().await;
+            let file = File::open(&self.file_path).await?;
+            let end = file.metadata().await?.len();
+            (file, end, wal.framing.clone())
+        };
+        let mut verifier = Verifier::new(&framing, check);
+        let format = self.serializer.format_id();
+        verifier
+            .file(Path::new(&self.file_path), file, Some(format), end)
+            .await?;
+        Ok(verifier.finish())
+    }
+
+    #[cfg(feature = "paged")]
+    fn fetch(&self, id: &Uuid) -> Result<Arc<[u8]>> {
+        match &self.paged {
+            Some(paged) => paged.fetch(id),
+            None => Err(RedDbError::Unsupported("paged mode")),
+        }
+    }
+
+    async fn last_lsn(&self) -> u64 {
+        self.db_file.lock().await.last.lsn
+    }
 }
 
 impl<SE> FileStorage<SE>
 where
     SE: Serializer + Debug,
 {
+    /// Append a batch, then wait for the fsync the sync mode asks for. Each
+    /// record's payload is written by `write_payload` straight into the
+    /// frames of the batch, which go to the file in one write. The log mutex
+    /// is released before syncing so other writers can join the fsync.
+    async fn append<P>(
+        &self,
+        records: impl Iterator<Item = (WalOp, Uuid, P)>,
+        write_payload: impl Fn(P, &mut Vec<u8>) -> Result<()>,
+    ) -> Result<()> {
+        self.writable()?;
+        let written = {
+            let mut wal = self.db_file.lock().await;
+            let wal = &mut *wal;
+            #[cfg(feature = "paged")]
+            let (mut placed, offset) = match self.paged {
+                Some(_) => (Vec::new(), wal.file.metadata().await?.len()),
+                None => (Vec::new(), 0),
+            };
+            wal.frames.clear();
+            let mut last = wal.last;
+            let mut count = 0;
+            for (op, id, payload) in records {
+                let stamp = last.next();
+                let start = wal.frames.len();
+                encode_record_into(&mut wal.frames, op, id, stamp, &wal.framing, |buf| {
+                    write_payload(payload, buf)
+                })?;
+                #[cfg(feature = "paged")]
+                placed.push((
+                    op,
+                    id,
+                    Extent {
+                        offset: offset + start as u64,
+                        len: (wal.frames.len() - start) as u64,
+                    },
+                ));
+                #[cfg(not(feature = "paged"))]
+                let _ = start;
+                last = stamp;
+                count += 1;
+            }
+            wal.file.write_all(&wal.frames).await?;
+            wal.frames.clear();
+            wal.frames.shrink_to(KEPT_FRAMES);
+            wal.last = last;
+            #[cfg(feature = "paged")]
+            if let Some(paged) = &self.paged {
+                // Offsets are only valid once the writes have reached the
+                // file, and the next batch reads its offset from the length.
+                wal.file.flush().await?;
+                paged.apply(&placed);
+            }
+            wal.written += count;
+            wal.written
+        };
+        if self.commit.due(written) {
+            self.commit.sync_to(written, || self.current_file()).await?;
+        }
+        Ok(())
+    }
+
+    fn writable(&self) -> Result<()> {
+        match self.lock {
+            Some(_) => Ok(()),
+            None => Err(RedDbError::ReadOnly),
+        }
+    }
+
+    /// Record count and a handle for syncing the current log file.
+    async fn current_file(&self) -> Result<(u64, File)> {
+        let wal = self.db_file.lock().await;
+        Ok((wal.written, wal.file.try_clone().await?))
+    }
+
+    /// Framing of files written from now on: checksummed, with the configured
+    /// codec and key.
+    fn new_framing(&self) -> Framing {
+        self.configured.read().unwrap().clone()
+    }
+
+    /// Size of the file right after compacting `data`: exact in paged mode,
+    /// where the records are not re-encoded, and estimated otherwise.
+    fn live_size(&self, data: &RedDbHM) -> u64 {
+        #[cfg(feature = "paged")]
+        if let Some(paged) = &self.paged {
+            return paged.compacted_size();
+        }
+        compacted_size(data, &self.new_framing())
+    }
+
+    /// The payload to write for the entry `entry` of `id` in a copy of the
+    /// map: the entry itself, or in paged mode the payload read back from
+    /// the file. `None` if the document has been deleted since the copy.
+    fn snapshot_payload<'a>(&self, id: &Uuid, entry: &'a [u8]) -> Result<Option<Cow<'a, [u8]>>> {
+        #[cfg(feature = "paged")]
+        if let Some(paged) = &self.paged {
+            return Ok(paged.read(id)?.map(Cow::Owned));
+        }
+        #[cfg(not(feature = "paged"))]
+        let _ = id;
+        Ok(Some(Cow::Borrowed(entry)))
+    }
+
+    /// Online compaction into a file framed with `framing`. The caller holds
+    /// the compaction lock.
+    async fn rewrite_online(&self, data: &RwLock<RedDbHM>, framing: Framing) -> Result<()> {
+        // Taken together under the read lock, so every record before `offset`
+        // is already reflected in `snapshot`, and `base` is the last of them.
+        let (snapshot, offset, base) = {
+            let data = data.read().await;
+            let wal = self.db_file.lock().await;
+            (data.clone(), wal.file.metadata().await?.len(), wal.last)
+        };
+
+        let tmp_path = self.tmp_path();
+        let mut tmp = self
+            .write_snapshot(&tmp_path, &snapshot, &framing, base)
+            .await?;
+        drop(snapshot);
+
+        // Writers are only blocked while the records appended since the
+        // snapshot are copied over.
+        let mut wal = self.db_file.lock().await;
+        let old = wal.framing.clone();
+        let tail = read_records_from(&mut wal.file, offset, &old).await?;
+        for r in &tail.records {
+            tmp.write(r.op, r.id, &r.payload, r.stamp, &framing).await?;
+        }
+        tmp.file.sync_all().await?;
+        self.replace_with(&mut wal, &tmp_path, tmp, framing).await
+    }
+
+    /// Append the records logged since the last backup to it, or return
+    /// `None` if that backup went to another file, was changed since, or the
+    /// log was compacted in between.
+    async fn continue_backup(
+        &self,
+        dest: &Path,
+        last: &mut Option<BackupCursor<(u64, u64)>>,
+    ) -> Result<Option<BackupReport>> {
+        let Some(cursor) = last.as_mut().filter(|c| c.continues(dest)) else {
+            return Ok(None);
+        };
+        let (generation, offset) = cursor.position;
+        let tail = {
+            let mut wal = self.db_file.lock().await;
+            if wal.generation != generation {
+                return Ok(None);
+            }
+            let framing = wal.framing.clone();
+            read_records_from(&mut wal.file, offset, &framing).await?
+        };
+        let Some(len) = backup::append(cursor, &tail.records).await? else {
+            return Ok(None);
+        };
+        cursor.len = len;
+        cursor.position = (generation, tail.valid_len);
+        Ok(Some(BackupReport {
+            incremental: true,
+            records: tail.records.len() as u64,
+        }))
+    }
+
+    fn tmp_path(&self) -> String {
+        format!("{}.tmp", self.file_path)
+    }
+
+    /// Write a header and one Insert per entry of `data` to a fresh file at
+    /// `path`. `base` is the stamp of the last record `data` reflects.
+    async fn write_snapshot(
+        &self,
+        path: &str,
+        data: &RedDbHM,
+        framing: &Framing,
+        base: Stamp,
+    ) -> Result<Snapshot> {
+        let mut file = File::create(path).await?;
+        let header = build_header(self.serializer.format_id(), framing, base.lsn);
+        file.write_all(&header).await?;
+        let mut snapshot = Snapshot {
+            file,
+            len: HEADER_LEN,
+            #[cfg(feature = "paged")]
+            index: self.paged.as_ref().map(|_| PageIndex::new()),
+        };
+        for (id, entry) in data {
+            if let Some(payload) = self.snapshot_payload(id, entry)? {
+                snapshot
+                    .write(WalOp::Insert, *id, &payload, base, framing)
+                    .await?;
+            }
+        }
+        crash_point("compact:written");
+        Ok(snapshot)
+    }
+
+    /// Move `snapshot`, synced at `tmp_path`, over the log and append to it
+    /// from now on. It holds every record written so far, so they are all
+    /// durable.
+    async fn replace_with(
+        &self,
+        wal: &mut WalFile,
+        tmp_path: &str,
+        snapshot: Snapshot,
+        framing: Framing,
+    ) -> Result<()> {
+        crash_point("compact:synced");
+        tokio::fs::rename(tmp_path, &self.file_path).await?;
+        crash_point("compact:renamed");
+        // Until the rename is durable a crash can bring back the old log,
+        // which may lack records only the new one has synced.
+        sync_dir(Path::new(&self.file_path)).await?;
+        crash_point("compact:dir-synced");
+        *wal = WalFile {
+            file: open_append(&self.file_path).await?,
+            framing,
+            written: wal.written,
+            generation: wal.generation + 1,
+            last: wal.last,
+            frames: std::mem::take(&mut wal.frames),
+        };
+        self.commit.mark_synced(wal.written);
+        #[cfg(feature = "paged")]
+        if let (Some(paged), Some(index)) = (&self.paged, snapshot.index) {
+            let mapped = wal.file.try_clone().await?.into_std().await;
+            paged.reset(index, mapped, wal.framing.clone());
+        }
+        #[cfg(not(feature = "paged"))]
+        drop(snapshot);
+        Ok(())
+    }
+
     async fn init_header(&self) -> Result<()> {
-        let mut file = self.db_file.lock().await;
-        let metadata = file.metadata().await?;
-        if metadata.len() == 0 {
-            let header = build_header(self.serializer.format_id());
-            file.write_all(&header).await?;
-            file.sync_all().await?;
+        let mut wal = self.db_file.lock().await;
+        let metadata = wal.file.metadata().await?;
+        if self.lock.is_none() && metadata.len() < HEADER_LEN {
+            // A writer has just created the file; there is nothing to read yet.
+        } else if metadata.len() == 0 {
+            let header = build_header(self.serializer.format_id(), &wal.framing, 0);
+            wal.file.write_all(&header).await?;
+            wal.file.sync_all().await?;
+            sync_dir(Path::new(&self.file_path)).await?;
         } else {
-            let mut header = [0u8; 32];
-            file.seek(SeekFrom::Start(0)).await?;
-            file.read_exact(&mut header).await?;
-            verify_header(&header, self.serializer.format_id())?;
+            let configured = self.new_framing();
+            let (framing, base) =
+                read_header(&mut wal.file, self.serializer.format_id(), &configured).await?;
+            wal.framing = framing;
+            wal.last = last_stamp(base, &[]);
         }
         Ok(())
     }
@@ -249,77 +780,227 @@ where
 
 #[cfg(test)]
 mod tests {
+    use super::super::format::read_records;
     use super::*;
+    use crate::serializer::FormatId;
 
     #[test]
-    fn build_header_magic_and_version() {
-        let h = build_header(FormatId::Ron);
-        assert_eq!(&h[0..8], MAGIC);
-        assert_eq!(u16::from_le_bytes(h[8..10].try_into().unwrap()), VERSION);
-        assert_eq!(h[10], FormatId::Ron as u8);
-        assert!(h[11..].iter().all(|&b| b == 0));
+    fn compacted_size_is_header_plus_records() {
+        let mut data = RedDbHM::new();
+        let id = Uuid::new_v4();
+        let payload = vec![1u8; 10];
+        data.insert(id, payload);
+        // HEADER_LEN(32) + 1 * (RECORD_OVERHEAD(37) + CHECKSUM_LEN(4)) + 10 payload bytes = 83
+        let framing = Framing::from_config(&DbConfig::default());
+        assert_eq!(compacted_size(&data, &framing), 83);
     }
 
+    #[cfg(feature = "encryption")]
     #[test]
-    fn verify_header_succeeds_for_matching_format() {
-        let h = build_header(FormatId::Json);
-        assert!(verify_header(&h, FormatId::Json).is_ok());
+    fn compacted_size_counts_seal_overhead() {
+        let mut data = RedDbHM::new();
+        data.insert(Uuid::new_v4(), vec![1u8; 10]);
+        let config = DbConfig::default().encryption_key([7u8; 32]);
+        // 83 + 24-byte nonce + 16-byte tag
+        assert_eq!(compacted_size(&data, &Framing::from_config(&config)), 123);
     }
 
-    #[test]
-    fn verify_header_fails_for_wrong_magic() {
-        let mut h = build_header(FormatId::Bin);
-        h[0] = 0xFF;
-        assert!(matches!(
-            verify_header(&h, FormatId::Bin),
-            Err(RedDbError::DataCorrupted)
-        ));
+    #[cfg(all(feature = "paged", feature = "json_ser"))]
+    #[tokio::test]
+    async fn paged_load_leaves_payloads_in_the_file() {
+        type Storage = FileStorage<crate::serializer::Json>;
+        let dir = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db")
+            .dir(dir.path())
+            .paged(crate::Paging::new(0));
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let storage = Storage::new(&config).await.unwrap();
+        storage.load::<String>().await.unwrap();
+        storage
+            .persist_raw(&[
+                (WalOp::Insert, a, b"one".to_vec()),
+                (WalOp::Insert, b, b"two".to_vec()),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(&*storage.fetch(&b).unwrap(), b"two");
+        storage
+            .persist_raw(&[
+                (WalOp::Update, a, b"three".to_vec()),
+                (WalOp::Delete, b, Vec::new()),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(&*storage.fetch(&a).unwrap(), b"three");
+        assert!(matches!(storage.fetch(&b), Err(RedDbError::NotFound(_))));
+        drop(storage);
+
+        let storage = Storage::new(&config).await.unwrap();
+        let map = storage.load::<String>().await.unwrap();
+        assert_eq!(map, RedDbHM::from([(a, Vec::new())]));
+        assert_eq!(&*storage.fetch(&a).unwrap(), b"three");
     }
 
-    #[test]
-    fn verify_header_fails_for_wrong_format() {
-        let h = build_header(FormatId::Bin);
-        assert!(matches!(
-            verify_header(&h, FormatId::Json),
-            Err(RedDbError::DataCorrupted)
-        ));
+    #[cfg(feature = "json_ser")]
+    #[tokio::test]
+    async fn sequence_numbers_continue_across_compaction_and_reopen() {
+        type Storage = FileStorage<crate::serializer::Json>;
+        let dir = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(dir.path());
+        let insert = |n: u8| (WalOp::Insert, Uuid::new_v4(), vec![n]);
+
+        let storage = Storage::new(&config).await.unwrap();
+        storage.load::<String>().await.unwrap();
+        storage
+            .persist_raw(&[insert(1), insert(2), insert(3)])
+            .await
+            .unwrap();
+        let map = storage.load::<String>().await.unwrap();
+        storage.compact(&map).await.unwrap();
+        drop(storage);
+
+        let storage = Storage::new(&config).await.unwrap();
+        storage.load::<String>().await.unwrap();
+        storage.persist_raw(&[insert(4)]).await.unwrap();
+        let mut wal = storage.db_file.lock().await;
+        let configured = storage.new_framing();
+        let (framing, base) = read_header(&mut wal.file, FormatId::Json, &configured)
+            .await
+            .unwrap();
+        let records = read_records(&mut wal.file, &framing).await.unwrap().records;
+        let lsns: Vec<u64> = records.iter().map(|r| r.stamp.lsn).collect();
+        assert_eq!(base, 3);
+        assert_eq!(lsns, vec![3, 3, 3, 4]);
+        assert!(records[3].stamp.timestamp >= records[0].stamp.timestamp);
     }
 
-    #[test]
-    fn verify_header_fails_for_wrong_version() {
-        let mut h = build_header(FormatId::Yaml);
-        h[8] = 0xFF;
-        assert!(matches!(
-            verify_header(&h, FormatId::Yaml),
-            Err(RedDbError::DataCorrupted)
-        ));
+    #[cfg(feature = "json_ser")]
+    #[tokio::test]
+    async fn persist_writes_a_batch_whole_or_not_at_all() {
+        use std::collections::BTreeMap;
+        type Doc = BTreeMap<Vec<u8>, u8>;
+        let dir = tempfile::tempdir().unwrap();
+        let storage =
+            FileStorage::<crate::serializer::Json>::new(&DbConfig::new("db").dir(dir.path()))
+                .await
+                .unwrap();
+        storage.load::<Doc>().await.unwrap();
+        let good = Document::new(Uuid::new_v4(), Doc::new());
+        // JSON object keys must be strings.
+        let bad = Document::new(Uuid::new_v4(), Doc::from([(vec![1], 1)]));
+        let err = storage
+            .persist(&[good.clone(), bad], WalOp::Insert)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RedDbError::Serialize(_)), "{err:?}");
+        storage
+            .persist(std::slice::from_ref(&good), WalOp::Insert)
+            .await
+            .unwrap();
+
+        let mut wal = storage.db_file.lock().await;
+        let configured = storage.new_framing();
+        let (framing, _) = read_header(&mut wal.file, FormatId::Json, &configured)
+            .await
+            .unwrap();
+        let records = read_records(&mut wal.file, &framing).await.unwrap().records;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, good.id);
+        assert_eq!(records[0].stamp.lsn, 1);
+        assert_eq!(records[0].payload, b"{}");
     }
 
-    #[test]
-    fn format_id_discriminants() {
-        assert_eq!(FormatId::Json as u8, 0);
-        assert_eq!(FormatId::Ron as u8, 1);
-        assert_eq!(FormatId::Yaml as u8, 2);
-        assert_eq!(FormatId::Bin as u8, 3);
+    /// Points in compaction, in order, at which a crash is simulated.
+    #[cfg(feature = "json_ser")]
+    const COMPACTION_STEPS: [&str; 4] = [
+        "compact:written",
+        "compact:synced",
+        "compact:renamed",
+        "compact:dir-synced",
+    ];
+
+    #[cfg(feature = "json_ser")]
+    fn crash_records() -> Vec<(WalOp, Uuid, Vec<u8>)> {
+        let id = Uuid::from_u128;
+        let mut records: Vec<_> = (1..=6)
+            .map(|n| (WalOp::Insert, id(n), format!("\"{n}\"").into_bytes()))
+            .collect();
+        records.push((WalOp::Update, id(1), b"\"one\"".to_vec()));
+        records.push((WalOp::Delete, id(2), Vec::new()));
+        records.push((WalOp::Delete, id(3), Vec::new()));
+        records
     }
 
-    #[test]
-    fn compacted_size_is_header_plus_records() {
-        let mut data: RedDbHM = HashMap::new();
-        let id = Uuid::new_v4();
-        let payload = vec![1u8; 10];
-        data.insert(id, payload);
-        // HEADER_LEN(32) + 1 * RECORD_OVERHEAD(21) + 10 payload bytes = 63
-        assert_eq!(compacted_size(&data), 63);
+    /// Run in a child process by `compaction_survives_a_crash_at_every_step`:
+    /// write the records, then compact, aborting at `REDDB_CRASH_AT`.
+    #[cfg(feature = "json_ser")]
+    #[tokio::test]
+    #[ignore = "run in a child process"]
+    async fn compact_until_crash() {
+        let Ok(dir) = std::env::var("REDDB_CRASH_DIR") else {
+            return;
+        };
+        let storage = FileStorage::<crate::serializer::Json>::new(&DbConfig::new("db").dir(dir))
+            .await
+            .unwrap();
+        storage.load::<String>().await.unwrap();
+        storage.persist_raw(&crash_records()).await.unwrap();
+        let data = storage.load::<String>().await.unwrap();
+        storage.compact_online(&RwLock::new(data)).await.unwrap();
     }
 
-    #[test]
-    fn should_compact_triggers_when_file_exceeds_threshold() {
-        // file_size 200, live_size 50, ratio 2.0 → 200 >= 100 → compact
-        assert!(should_compact(200, 50, 2.0));
-        // file_size 80, live_size 50, ratio 2.0 → 80 < 100 → no compact
-        assert!(!should_compact(80, 50, 2.0));
-        // live_size 0 → never compact (empty db)
-        assert!(!should_compact(32, 0, 2.0));
+    #[cfg(feature = "json_ser")]
+    #[tokio::test]
+    async fn compaction_survives_a_crash_at_every_step() {
+        use super::super::format::{Record, Replay};
+        use std::process::{Command, Stdio};
+
+        let mut replay = Replay::default();
+        for (op, id, payload) in crash_records() {
+            replay.apply(Record {
+                op,
+                id,
+                payload,
+                stamp: Stamp::default(),
+            });
+        }
+        let expected = replay.finish();
+        for step in COMPACTION_STEPS {
+            let dir = tempfile::tempdir().unwrap();
+            let status = Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "storage::file::tests::compact_until_crash"])
+                .args(["--ignored", "--test-threads=1"])
+                .env("REDDB_CRASH_DIR", dir.path())
+                .env("REDDB_CRASH_AT", step)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .unwrap();
+            assert!(!status.success(), "no crash at {step}");
+            let tmp_path = dir.path().join("db.json.tmp");
+            let renamed = step == "compact:renamed" || step == "compact:dir-synced";
+            assert_eq!(tmp_path.exists(), !renamed, "{step}");
+
+            let config = DbConfig::new("db").dir(dir.path());
+            let storage = FileStorage::<crate::serializer::Json>::new(&config)
+                .await
+                .unwrap();
+            assert!(!tmp_path.exists(), "{step}: compacted copy left behind");
+            let map = storage.load::<String>().await.unwrap();
+            assert_eq!(map, expected, "{step}");
+            assert!(map.keys().eq(expected.keys()), "{step}");
+            let id = Uuid::new_v4();
+            storage
+                .persist_raw(&[(WalOp::Insert, id, b"\"x\"".to_vec())])
+                .await
+                .unwrap();
+            drop(storage);
+            let storage = FileStorage::<crate::serializer::Json>::new(&config)
+                .await
+                .unwrap();
+            let map = storage.load::<String>().await.unwrap();
+            assert_eq!(map.len(), expected.len() + 1, "{step}");
+        }
     }
 }
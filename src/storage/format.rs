@@ -0,0 +1,2142 @@
+//! On-disk layout shared by the file-backed storage engines: the 32-byte file
+//! header and the framing of individual WAL records.
+
+use super::codec::{codec_id, compress, decompress, from_codec_id};
+#[cfg(feature = "encryption")]
+use super::crypto::{Cipher, CIPHER_ID, KCV_LEN, SEAL_OVERHEAD};
+use super::verify::ProblemKind;
+use crate::config::{AsOf, Compression, DbConfig};
+use crate::error::{RedDbError, Result};
+use crate::serializer::FormatId;
+use crate::wal::WalOp;
+use crate::RedDbHM;
+use std::ops::ControlFlow;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::{
+    AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWriteExt, BufReader, SeekFrom,
+};
+use uuid::Uuid;
+
+/// 32-byte file header layout:
+/// [0..8]   magic   b"REDDB\x00\x02\x00"
+/// [8..10]  version u16 LE (3)
+/// [10]     format  u8  (0 = JSON, 1 = RON, 2 = YAML, 3 = Bin, 4 = MessagePack,
+///                      5 = CBOR, 6 = Postcard, FORMAT_CUSTOM)
+/// [11]     flags   u8  (FLAG_CHECKSUM)
+/// [12]     codec   u8  (payload compression, 0 = none)
+/// [13]     cipher  u8  (payload encryption, 0 = none)
+/// [14]     tag     u8  (of a FormatId::Custom format, zeroed otherwise)
+/// [15]     reserved (zeroed)
+/// [16..24] key-check value of the encryption key (zeroed when unencrypted)
+/// [24..32] base LSN u64 LE: the sequence number of the last record written
+///          before the file was started
+pub(super) const HEADER_LEN: u64 = 32;
+const MAGIC: &[u8; 8] = b"REDDB\x00\x02\x00";
+const VERSION: u16 = 3;
+/// Format byte of files written by a [`FormatId::Custom`] serializer, whose
+/// tag is stored in byte 14.
+const FORMAT_CUSTOM: u8 = 0xFF;
+/// Version 2 records carry no sequence number or timestamp.
+#[cfg(feature = "migrate")]
+const V2: u16 = 2;
+
+/// Header flag: every record ends with a CRC32C. Files written before
+/// checksums were introduced leave it clear and are read without them.
+const FLAG_CHECKSUM: u8 = 0x01;
+
+/// Per-record layout:
+/// [u32 LE payload_len][u8 op][u8;16 uuid][u64 LE lsn][u64 LE timestamp][payload_len bytes][u32 LE crc32c]
+/// The checksum covers all preceding bytes of the record and is only present
+/// when the header has `FLAG_CHECKSUM` set. Version 2 records lack the lsn
+/// and timestamp.
+const RECORD_OVERHEAD: usize = 37; // 4 + 1 + 16 + 8 + 8
+const V2_RECORD_OVERHEAD: usize = 21; // 4 + 1 + 16
+const CHECKSUM_LEN: usize = 4;
+
+/// Where a record sits in the history of a database: its log sequence
+/// number, which grows by one per appended record, and the wall-clock time
+/// it was written at, in microseconds since the Unix epoch.
+///
+/// The records of a compacted snapshot all carry the stamp of the last
+/// record the snapshot reflects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) struct Stamp {
+    pub(super) lsn: u64,
+    pub(super) timestamp: u64,
+}
+
+impl Stamp {
+    /// Stamp of the record appended after the one stamped `self`. The
+    /// timestamp never goes backwards, even if the clock does.
+    pub(super) fn next(self) -> Stamp {
+        Stamp {
+            lsn: self.lsn + 1,
+            timestamp: micros(SystemTime::now()).max(self.timestamp),
+        }
+    }
+}
+
+/// `time` in microseconds since the Unix epoch, as stored in records.
+fn micros(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_micros() as u64)
+}
+
+/// Stamp of the last record of a file with base LSN `base`, or the base
+/// itself if the file holds no records.
+pub(super) fn last_stamp(base: u64, records: &[Record]) -> Stamp {
+    records.last().map_or(
+        Stamp {
+            lsn: base,
+            timestamp: 0,
+        },
+        |r| r.stamp,
+    )
+}
+
+/// Which records a database opened [`as of`](DbConfig::as_of) `point`
+/// replays, decided one record at a time while the log of a file with base
+/// LSN `base` is read in order.
+///
+/// A point before the base, which compaction has folded into a snapshot,
+/// fails with [`RedDbError::HistoryUnavailable`]. A snapshot does not record
+/// its time when it holds no documents, so a time is then only known to be
+/// covered from the first record after it.
+#[derive(Debug)]
+pub(super) struct Cutoff {
+    base: u64,
+    point: AsOf,
+    /// Whether `point` is known to lie within the history.
+    covered: bool,
+}
+
+impl Cutoff {
+    pub(super) fn new(base: u64, point: AsOf) -> Result<Self> {
+        let covered = match point {
+            AsOf::Lsn(lsn) if lsn < base => return Err(RedDbError::HistoryUnavailable(base)),
+            AsOf::Lsn(_) => true,
+            AsOf::Time(_) => base == 0,
+        };
+        Ok(Cutoff {
+            base,
+            point,
+            covered,
+        })
+    }
+
+    /// Whether the record stamped `stamp`, the next one in the log, is
+    /// replayed.
+    pub(super) fn admits(&mut self, stamp: Stamp) -> Result<bool> {
+        match self.point {
+            AsOf::Lsn(lsn) => Ok(stamp.lsn <= lsn),
+            AsOf::Time(time) => {
+                let time = micros(time);
+                if !self.covered {
+                    if time < stamp.timestamp {
+                        return Err(RedDbError::HistoryUnavailable(self.base));
+                    }
+                    self.covered = true;
+                }
+                Ok(stamp.timestamp <= time)
+            }
+        }
+    }
+
+    /// Check, once the whole log was read, that `point` lies within it.
+    pub(super) fn finish(&self) -> Result<()> {
+        if self.covered {
+            Ok(())
+        } else {
+            Err(RedDbError::HistoryUnavailable(self.base))
+        }
+    }
+}
+
+/// A decoded record.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Record {
+    pub(super) op: WalOp,
+    pub(super) id: Uuid,
+    pub(super) payload: Vec<u8>,
+    pub(super) stamp: Stamp,
+}
+
+/// The in-memory map a log replays to, built a record at a time, with the
+/// documents in the order of their first insert. A document written again
+/// after its delete goes to the end.
+#[derive(Debug, Default)]
+pub(super) struct Replay {
+    map: RedDbHM,
+}
+
+impl Replay {
+    pub(super) fn apply(&mut self, record: Record) {
+        if record.op == WalOp::Delete {
+            self.map.remove(&record.id);
+        } else {
+            self.map.insert(record.id, record.payload);
+        }
+    }
+
+    pub(super) fn finish(self) -> RedDbHM {
+        self.map
+    }
+}
+
+/// Where a record's frame sits in its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Extent {
+    pub(super) offset: u64,
+    pub(super) len: u64,
+}
+
+#[cfg(feature = "paged")]
+impl Extent {
+    pub(super) fn end(self) -> u64 {
+        self.offset + self.len
+    }
+}
+
+/// Which version's record layout a file uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    #[cfg_attr(not(any(feature = "migrate", test)), allow(dead_code))]
+    V2,
+    V3,
+}
+
+/// How the records of a file are framed, as recorded in its header.
+#[derive(Debug, Clone)]
+pub(super) struct Framing {
+    pub(super) checksummed: bool,
+    pub(super) compression: Compression,
+    #[cfg(feature = "encryption")]
+    pub(super) cipher: Option<Cipher>,
+}
+
+impl Framing {
+    /// Framing of files newly written under `config`.
+    pub(super) fn from_config(config: &DbConfig) -> Self {
+        Framing {
+            checksummed: true,
+            compression: config.compression,
+            #[cfg(feature = "encryption")]
+            cipher: config.encryption_key.as_ref().map(Cipher::new),
+        }
+    }
+
+    pub(super) fn encrypted(&self) -> bool {
+        #[cfg(feature = "encryption")]
+        return self.cipher.is_some();
+        #[cfg(not(feature = "encryption"))]
+        return false;
+    }
+
+    /// Bytes encryption adds to every non-empty payload.
+    pub(super) fn seal_overhead(&self) -> usize {
+        #[cfg(feature = "encryption")]
+        if self.encrypted() {
+            return SEAL_OVERHEAD;
+        }
+        0
+    }
+}
+
+/// Header of a new file whose records continue after sequence number `base_lsn`.
+pub(super) fn build_header(format: FormatId, framing: &Framing, base_lsn: u64) -> [u8; 32] {
+    let mut h = [0u8; 32];
+    h[0..8].copy_from_slice(MAGIC);
+    h[8..10].copy_from_slice(&VERSION.to_le_bytes());
+    match format {
+        FormatId::Json => h[10] = 0,
+        FormatId::Ron => h[10] = 1,
+        FormatId::Yaml => h[10] = 2,
+        FormatId::Bin => h[10] = 3,
+        FormatId::MsgPack => h[10] = 4,
+        FormatId::Cbor => h[10] = 5,
+        FormatId::Postcard => h[10] = 6,
+        FormatId::Custom(tag) => {
+            h[10] = FORMAT_CUSTOM;
+            h[14] = tag;
+        }
+    }
+    if framing.checksummed {
+        h[11] |= FLAG_CHECKSUM;
+    }
+    h[12] = codec_id(framing.compression);
+    #[cfg(feature = "encryption")]
+    if let Some(cipher) = &framing.cipher {
+        h[13] = CIPHER_ID;
+        h[16..16 + KCV_LEN].copy_from_slice(&cipher.kcv());
+    }
+    h[24..32].copy_from_slice(&base_lsn.to_le_bytes());
+    h
+}
+
+/// Check the magic of a header and return the format it records.
+fn format_of(header: &[u8; 32]) -> Result<FormatId> {
+    if &header[0..8] != MAGIC {
+        return Err(RedDbError::NotADatabase);
+    }
+    match header[10] {
+        0 => Ok(FormatId::Json),
+        1 => Ok(FormatId::Ron),
+        2 => Ok(FormatId::Yaml),
+        3 => Ok(FormatId::Bin),
+        4 => Ok(FormatId::MsgPack),
+        5 => Ok(FormatId::Cbor),
+        6 => Ok(FormatId::Postcard),
+        FORMAT_CUSTOM => Ok(FormatId::Custom(header[14])),
+        other => Err(RedDbError::UnknownFormat(other)),
+    }
+}
+
+/// Check the magic and format of a header and return its version.
+fn header_version(header: &[u8; 32], expected: FormatId) -> Result<u16> {
+    let found = format_of(header)?;
+    if found != expected {
+        return Err(RedDbError::FormatMismatch { expected, found });
+    }
+    Ok(u16::from_le_bytes(header[8..10].try_into().unwrap()))
+}
+
+fn verify_header(header: &[u8; 32], expected: FormatId) -> Result<()> {
+    match header_version(header, expected)? {
+        VERSION => Ok(()),
+        version => Err(RedDbError::UnsupportedVersion(version)),
+    }
+}
+
+fn base_lsn(header: &[u8; 32]) -> u64 {
+    u64::from_le_bytes(header[24..32].try_into().unwrap())
+}
+
+async fn read_header_bytes(file: &mut File) -> Result<[u8; 32]> {
+    let mut header = [0u8; 32];
+    file.seek(SeekFrom::Start(0)).await?;
+    match file.read_exact(&mut header).await {
+        Ok(_) => Ok(header),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Err(RedDbError::TruncatedHeader),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Read the header of an existing file, check it against `expected` and
+/// return how its records are framed along with its base LSN. `configured`
+/// supplies what the header does not record: the encryption key and the
+/// zstd level.
+pub(super) async fn read_header(
+    file: &mut File,
+    expected: FormatId,
+    configured: &Framing,
+) -> Result<(Framing, u64)> {
+    let header = read_header_bytes(file).await?;
+    verify_header(&header, expected)?;
+    Ok((parse_framing(&header, configured)?, base_lsn(&header)))
+}
+
+/// The format a header records, after checking its magic and version.
+fn header_format(header: &[u8; 32]) -> Result<FormatId> {
+    let format = format_of(header)?;
+    verify_header(header, format)?;
+    Ok(format)
+}
+
+/// The format of an existing file, read from its header.
+pub(super) async fn read_format(file: &mut File) -> Result<FormatId> {
+    header_format(&read_header_bytes(file).await?)
+}
+
+/// Read the header of an existing file of any format and return that format,
+/// how its records are framed and its base LSN, as [`read_header`] does.
+pub(super) async fn read_any_header(
+    file: &mut File,
+    configured: &Framing,
+) -> Result<(FormatId, Framing, u64)> {
+    let header = read_header_bytes(file).await?;
+    let format = header_format(&header)?;
+    Ok((
+        format,
+        parse_framing(&header, configured)?,
+        base_lsn(&header),
+    ))
+}
+
+/// Read the header of a version 2 file and return how its records are
+/// framed, or `None` if the file is already at the current version.
+#[cfg(feature = "migrate")]
+pub(super) async fn read_v2_header(
+    file: &mut File,
+    expected: FormatId,
+    configured: &Framing,
+) -> Result<Option<Framing>> {
+    let header = read_header_bytes(file).await?;
+    match header_version(&header, expected)? {
+        VERSION => Ok(None),
+        V2 => Ok(Some(parse_framing(&header, configured)?)),
+        version => Err(RedDbError::UnsupportedVersion(version)),
+    }
+}
+
+/// Decode the records of a version 2 file framed as `framing`, handing each
+/// to `visitor` in order, as [`replay_records`] does for the current version.
+/// Its records come with default stamps.
+#[cfg(feature = "migrate")]
+pub(super) async fn replay_v2_records(
+    file: &mut File,
+    framing: &Framing,
+    visitor: impl Visitor,
+) -> Result<u64> {
+    let end = file.metadata().await?.len();
+    stream(file, HEADER_LEN, end, framing, Layout::V2, visitor).await
+}
+
+fn parse_framing(header: &[u8; 32], configured: &Framing) -> Result<Framing> {
+    let compression = from_codec_id(header[12])?;
+    let compression = if codec_id(compression) == codec_id(configured.compression) {
+        configured.compression
+    } else {
+        compression
+    };
+    #[cfg(not(feature = "encryption"))]
+    if header[13] != 0 {
+        return Err(RedDbError::EncryptionKeyRequired);
+    }
+    Ok(Framing {
+        checksummed: header[11] & FLAG_CHECKSUM != 0,
+        compression,
+        #[cfg(feature = "encryption")]
+        cipher: file_cipher(header, configured)?,
+    })
+}
+
+/// The configured cipher, if the key-check value shows it is the file's key.
+#[cfg(feature = "encryption")]
+fn file_cipher(header: &[u8; 32], configured: &Framing) -> Result<Option<Cipher>> {
+    match header[13] {
+        0 => Ok(None),
+        CIPHER_ID => {
+            let cipher = configured
+                .cipher
+                .clone()
+                .ok_or(RedDbError::EncryptionKeyRequired)?;
+            if header[16..16 + KCV_LEN] != cipher.kcv() {
+                return Err(RedDbError::InvalidKey);
+            }
+            Ok(Some(cipher))
+        }
+        other => Err(RedDbError::UnknownCipher(other)),
+    }
+}
+
+pub(super) fn record_overhead(checksummed: bool) -> usize {
+    layout_overhead(Layout::V3, checksummed)
+}
+
+fn layout_overhead(layout: Layout, checksummed: bool) -> usize {
+    let body = match layout {
+        Layout::V2 => V2_RECORD_OVERHEAD,
+        Layout::V3 => RECORD_OVERHEAD,
+    };
+    if checksummed {
+        body + CHECKSUM_LEN
+    } else {
+        body
+    }
+}
+
+fn encode_op(op: WalOp) -> u8 {
+    match op {
+        WalOp::Insert => 0x01,
+        WalOp::Update => 0x02,
+        WalOp::Delete => 0x03,
+    }
+}
+
+fn decode_op(byte: u8) -> Option<WalOp> {
+    match byte {
+        0x01 => Some(WalOp::Insert),
+        0x02 => Some(WalOp::Update),
+        0x03 => Some(WalOp::Delete),
+        _ => None,
+    }
+}
+
+/// Outcome of decoding the record at the start of a buffer.
+enum Frame<'a> {
+    Valid {
+        op: WalOp,
+        id: Uuid,
+        stamp: Stamp,
+        payload: &'a [u8],
+        len: usize,
+    },
+    /// The buffer ends before the record does.
+    Truncated,
+    /// The record is complete but its checksum or op byte is wrong.
+    Invalid(Flaw),
+}
+
+/// What is wrong with a complete record.
+#[derive(Debug, Clone, Copy)]
+enum Flaw {
+    /// The checksum does not match, so nothing in the record can be trusted.
+    Checksum,
+    /// The record is intact, but its op byte is unknown.
+    Op { byte: u8, id: Uuid, len: usize },
+}
+
+fn parse_record(buf: &[u8], checksummed: bool, layout: Layout) -> Frame<'_> {
+    let overhead = layout_overhead(layout, checksummed);
+    if buf.len() < overhead {
+        return Frame::Truncated;
+    }
+    let payload_len = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize;
+    let header_len = layout_overhead(layout, false);
+    let body_len = header_len + payload_len;
+    if buf.len() - overhead < payload_len {
+        return Frame::Truncated;
+    }
+    if checksummed {
+        let stored = u32::from_le_bytes(buf[body_len..body_len + 4].try_into().unwrap());
+        if crc32c::crc32c(&buf[..body_len]) != stored {
+            return Frame::Invalid(Flaw::Checksum);
+        }
+    }
+    let id = Uuid::from_bytes(buf[5..21].try_into().unwrap());
+    let len = overhead + payload_len;
+    let Some(op) = decode_op(buf[4]) else {
+        return Frame::Invalid(Flaw::Op {
+            byte: buf[4],
+            id,
+            len,
+        });
+    };
+    let stamp = match layout {
+        Layout::V2 => Stamp::default(),
+        Layout::V3 => Stamp {
+            lsn: u64::from_le_bytes(buf[21..29].try_into().unwrap()),
+            timestamp: u64::from_le_bytes(buf[29..37].try_into().unwrap()),
+        },
+    };
+    Frame::Valid {
+        op,
+        id,
+        stamp,
+        payload: &buf[header_len..body_len],
+        len,
+    }
+}
+
+/// Records decoded from a file, plus the offset just past the last intact one.
+#[derive(Debug)]
+pub(super) struct Scan {
+    pub(super) records: Vec<Record>,
+    pub(super) valid_len: u64,
+}
+
+/// A stretch of a record area that [`check`] could not read a record from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Damage {
+    pub(super) extent: Extent,
+    pub(super) kind: ProblemKind,
+    /// The document the record is about, if its id can be trusted.
+    pub(super) id: Option<Uuid>,
+}
+
+/// Walk the record area of `file` between byte offsets `start` and `end`,
+/// handing every intact record and every damaged stretch to `visit` in order.
+///
+/// Unlike [`stream`], which stops at the first damaged record, the walk
+/// carries on past damage: a record whose length can be trusted is skipped
+/// whole; otherwise the walk resumes at the next offset where an intact
+/// record starts. Only checksums make that offset trustworthy, so in a file
+/// without them everything from a record that runs past the end is one
+/// stretch. Memory does not grow with the size of the file; see
+/// [`walk_file`].
+pub(super) async fn check(
+    file: &mut File,
+    start: u64,
+    end: u64,
+    framing: &Framing,
+    mut visit: impl FnMut(Found),
+) -> Result<()> {
+    let mut walk = Walk::new(Layout::V3, start, end);
+    walk_file(file, &mut walk, framing, |found| {
+        visit(found);
+        ControlFlow::Continue(())
+    })
+    .await
+}
+
+/// What a [`Walk`] finds next: an intact record or a damaged stretch.
+pub(super) type Found = std::result::Result<(Record, Extent), Damage>;
+
+/// Decide whether the damaged frame at `start` is a torn tail left by a
+/// crash during a write, and return `start` as the end of the intact records
+/// if it is. The rest of the file is walked as [`check`] does: any intact
+/// record after the damage means the file itself is corrupted, which fails
+/// with [`RedDbError::CorruptRecord`]. Files without checksums cannot find
+/// records past damage, so in those only a frame running past the end is
+/// torn.
+async fn torn_tail(
+    file: &mut File,
+    start: u64,
+    end: u64,
+    framing: &Framing,
+    layout: Layout,
+) -> Result<u64> {
+    let mut walk = Walk::new(layout, start, end);
+    let mut first = true;
+    let mut torn = true;
+    walk_file(file, &mut walk, framing, |found| {
+        // The first stretch is the damage at `start`; anything after it was
+        // read from an intact frame.
+        torn = first
+            && match found {
+                Ok(_) => false,
+                Err(damage) => framing.checksummed || damage.kind == ProblemKind::TruncatedFrame,
+            };
+        first = false;
+        if torn {
+            ControlFlow::Continue(())
+        } else {
+            ControlFlow::Break(())
+        }
+    })
+    .await?;
+    if torn {
+        Ok(start)
+    } else {
+        Err(RedDbError::CorruptRecord(start))
+    }
+}
+
+/// Feed `walk` the bytes of `file` from where it stands to its end, in
+/// batches of [`BATCH_BYTES`], until it has walked them all or `visit` stops
+/// it. Only the bytes the walk has not got past are kept, and a frame larger
+/// than a batch is only read whole once its checksum is known to match, so a
+/// damaged length prefix cannot make it read the rest of the file at once.
+async fn walk_file(
+    file: &mut File,
+    walk: &mut Walk,
+    framing: &Framing,
+    mut visit: impl FnMut(Found) -> ControlFlow<()>,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    let mut read_to = walk.offset;
+    let mut at_end = false;
+    loop {
+        if !at_end {
+            at_end = read_more(file, &mut buf, &mut read_to, walk.end, BATCH_BYTES).await?;
+        }
+        let (walked, halt) = walk.feed(&buf, at_end, framing, &mut visit);
+        buf.drain(..walked);
+        match halt {
+            Halt::More if !at_end => {}
+            Halt::More | Halt::Stopped => return Ok(()),
+            Halt::Verify(len) => {
+                if intact_on_disk(file, walk.offset, len).await? {
+                    let missing = len.saturating_sub(buf.len());
+                    at_end = read_more(file, &mut buf, &mut read_to, walk.end, missing).await?;
+                } else {
+                    walk.bad = Some(walk.offset);
+                }
+            }
+        }
+    }
+}
+
+/// Append up to `len` bytes of `file` from offset `*read_to`, but none from
+/// `end` on, to `buf`, and advance `*read_to` past them. Returns whether the
+/// bytes ran out.
+async fn read_more(
+    file: &mut File,
+    buf: &mut Vec<u8>,
+    read_to: &mut u64,
+    end: u64,
+    len: usize,
+) -> Result<bool> {
+    let want = end.saturating_sub(*read_to).min(len as u64);
+    file.seek(SeekFrom::Start(*read_to)).await?;
+    let read = (&mut *file).take(want).read_to_end(buf).await? as u64;
+    *read_to += read;
+    Ok(read < want || *read_to >= end)
+}
+
+/// Whether the checksummed frame of `len` bytes at `offset` in `file` is
+/// intact; see [`checksum_matches`].
+async fn intact_on_disk(file: &mut File, offset: u64, len: usize) -> Result<bool> {
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut prefix = [0u8; 4];
+    Ok(read_fully(file, &mut prefix).await? && checksum_matches(file, prefix, len).await?)
+}
+
+/// Whether the checksummed frame of `len` bytes whose length prefix,
+/// `prefix`, was just read from `reader` matches its checksum. The rest of
+/// the frame is read a batch at a time rather than whole.
+async fn checksum_matches<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    prefix: [u8; 4],
+    len: usize,
+) -> Result<bool> {
+    let mut crc = crc32c::crc32c(&prefix);
+    let mut left = len - prefix.len() - CHECKSUM_LEN;
+    let mut piece = vec![0u8; left.min(BATCH_BYTES)];
+    while left > 0 {
+        let n = left.min(piece.len());
+        if !read_fully(reader, &mut piece[..n]).await? {
+            return Ok(false);
+        }
+        crc = crc32c::crc32c_append(crc, &piece[..n]);
+        left -= n;
+    }
+    let mut stored = [0u8; CHECKSUM_LEN];
+    Ok(read_fully(reader, &mut stored).await? && u32::from_le_bytes(stored) == crc)
+}
+
+/// A walk over a record area, fed its bytes piece by piece by
+/// [`walk_file`].
+struct Walk {
+    layout: Layout,
+    /// Offset of the first byte not walked past yet.
+    offset: u64,
+    /// Offset just past the record area.
+    end: u64,
+    /// Offset and kind of the damaged stretch being walked through, looking
+    /// for the next intact record.
+    damaged: Option<(u64, ProblemKind)>,
+    /// Offset of a frame too large to read on trust that [`walk_file`] found
+    /// damaged on disk.
+    bad: Option<u64>,
+}
+
+/// Why a [`Walk`] stopped walking the bytes it was fed.
+enum Halt {
+    /// It needs the bytes after them, if there are any.
+    More,
+    /// It got to a frame of this many bytes, too large to read before its
+    /// checksum is known to match.
+    Verify(usize),
+    /// `visit` stopped it.
+    Stopped,
+}
+
+impl Walk {
+    fn new(layout: Layout, offset: u64, end: u64) -> Self {
+        Walk {
+            layout,
+            offset,
+            end,
+            damaged: None,
+            bad: None,
+        }
+    }
+
+    /// Walk `buf`, which holds the bytes from [`offset`](Self::offset) on,
+    /// as far as it can without the bytes after it, and return how many
+    /// bytes it got past. When `at_end`, no bytes follow and all of `buf` is
+    /// walked unless `visit` stops it.
+    fn feed(
+        &mut self,
+        buf: &[u8],
+        at_end: bool,
+        framing: &Framing,
+        visit: &mut impl FnMut(Found) -> ControlFlow<()>,
+    ) -> (usize, Halt) {
+        let mut pos = 0;
+        let halt = self.walk(buf, &mut pos, at_end, framing, visit);
+        self.offset += pos as u64;
+        (pos, halt)
+    }
+
+    fn walk(
+        &mut self,
+        buf: &[u8],
+        pos: &mut usize,
+        at_end: bool,
+        framing: &Framing,
+        visit: &mut impl FnMut(Found) -> ControlFlow<()>,
+    ) -> Halt {
+        let checksummed = framing.checksummed;
+        loop {
+            if let Some((start, kind)) = self.damaged.take() {
+                match self.find_intact(buf, *pos, at_end, checksummed) {
+                    Ok(next) => *pos = next,
+                    Err((walked, halt)) => {
+                        self.damaged = Some((start, kind));
+                        *pos = walked;
+                        return halt;
+                    }
+                }
+                let damage = Damage {
+                    extent: Extent {
+                        offset: start,
+                        len: self.offset + *pos as u64 - start,
+                    },
+                    kind,
+                    id: None,
+                };
+                if visit(Err(damage)).is_break() {
+                    return Halt::Stopped;
+                }
+            }
+            if *pos == buf.len() {
+                return Halt::More;
+            }
+            let offset = self.offset + *pos as u64;
+            if self.bad == Some(offset) {
+                self.damaged = Some((offset, ProblemKind::ChecksumMismatch));
+                *pos += 1;
+                continue;
+            }
+            let damaged = |kind, id, len: usize| Damage {
+                extent: Extent {
+                    offset,
+                    len: len as u64,
+                },
+                kind,
+                id,
+            };
+            let found = match parse_record(&buf[*pos..], checksummed, self.layout) {
+                Frame::Valid {
+                    op,
+                    id,
+                    stamp,
+                    payload,
+                    len,
+                } => {
+                    *pos += len;
+                    match decode_payload(op, id, payload, framing) {
+                        Some(payload) => Ok((
+                            Record {
+                                op,
+                                id,
+                                payload,
+                                stamp,
+                            },
+                            Extent {
+                                offset,
+                                len: len as u64,
+                            },
+                        )),
+                        None => Err(damaged(ProblemKind::UnreadablePayload, Some(id), len)),
+                    }
+                }
+                Frame::Invalid(Flaw::Op { byte, id, len }) => {
+                    *pos += len;
+                    Err(damaged(ProblemKind::UnknownOp(byte), Some(id), len))
+                }
+                Frame::Invalid(Flaw::Checksum) => {
+                    let overhead = layout_overhead(self.layout, true);
+                    let len = overhead
+                        + u32::from_le_bytes(buf[*pos..*pos + 4].try_into().unwrap()) as usize;
+                    let rest = &buf[*pos + len..];
+                    // A following frame too large to wait for is found by
+                    // the search for the next intact record instead.
+                    let skip_whole = match parse_record(rest, true, self.layout) {
+                        Frame::Valid { .. } => true,
+                        Frame::Truncated
+                            if !at_end
+                                && frame_len(rest, overhead)
+                                    .is_none_or(|len| len <= BATCH_BYTES) =>
+                        {
+                            return Halt::More;
+                        }
+                        _ => rest.is_empty(),
+                    };
+                    if !skip_whole {
+                        self.damaged = Some((offset, ProblemKind::ChecksumMismatch));
+                        *pos += 1;
+                        continue;
+                    }
+                    *pos += len;
+                    Err(damaged(ProblemKind::ChecksumMismatch, None, len))
+                }
+                Frame::Truncated => {
+                    let len = frame_len(&buf[*pos..], layout_overhead(self.layout, checksummed));
+                    let runs_past = len.is_some_and(|len| len as u64 > self.end - offset);
+                    if !at_end && !runs_past {
+                        return match len {
+                            Some(len) if checksummed && len > BATCH_BYTES => Halt::Verify(len),
+                            _ => Halt::More,
+                        };
+                    }
+                    self.damaged = Some((offset, ProblemKind::TruncatedFrame));
+                    if checksummed {
+                        *pos += 1;
+                    }
+                    continue;
+                }
+            };
+            if visit(found).is_break() {
+                return Halt::Stopped;
+            }
+        }
+    }
+
+    /// Offset in `buf`, from `from` on, where the next intact record starts,
+    /// or the end of `buf` if none does and no bytes follow it. Otherwise
+    /// how far the search got and why it stopped. Without checksums no
+    /// record can be trusted, so the search runs to the end.
+    fn find_intact(
+        &self,
+        buf: &[u8],
+        from: usize,
+        at_end: bool,
+        checksummed: bool,
+    ) -> std::result::Result<usize, (usize, Halt)> {
+        let to_end = if at_end {
+            Ok(buf.len())
+        } else {
+            Err((buf.len(), Halt::More))
+        };
+        if !checksummed {
+            return to_end;
+        }
+        let overhead = layout_overhead(self.layout, true);
+        for p in from..buf.len() {
+            let rest = &buf[p..];
+            if rest.len() < overhead {
+                return if at_end {
+                    Ok(buf.len())
+                } else {
+                    Err((p, Halt::More))
+                };
+            }
+            let offset = self.offset + p as u64;
+            let len = overhead + u32::from_le_bytes(rest[0..4].try_into().unwrap()) as usize;
+            if !plausible(rest, self.layout)
+                || len as u64 > self.end.saturating_sub(offset)
+                || self.bad == Some(offset)
+            {
+                continue;
+            }
+            if len > rest.len() {
+                if at_end {
+                    continue;
+                }
+                let halt = if len > BATCH_BYTES {
+                    Halt::Verify(len)
+                } else {
+                    Halt::More
+                };
+                return Err((p, halt));
+            }
+            if matches!(parse_record(rest, true, self.layout), Frame::Valid { .. }) {
+                return Ok(p);
+            }
+        }
+        to_end
+    }
+}
+
+/// Length of the frame `buf` starts with, if `buf` holds its length prefix.
+fn frame_len(buf: &[u8], overhead: usize) -> Option<usize> {
+    let prefix = buf.get(..4)?;
+    Some(overhead + u32::from_le_bytes(prefix.try_into().unwrap()) as usize)
+}
+
+/// Whether the frame `buf` starts with, at least a frame header long, has
+/// an op byte and a stamp a record can carry. Together with its length this
+/// rules out nearly every offset in a damaged stretch before any checksum is
+/// computed.
+fn plausible(buf: &[u8], layout: Layout) -> bool {
+    decode_op(buf[4]).is_some()
+        && match layout {
+            Layout::V2 => true,
+            // No sequence number or timestamp in microseconds reaches 2^56.
+            Layout::V3 => buf[28] == 0 && buf[36] == 0,
+        }
+}
+
+/// Bytes of frames read ahead and decoded as one batch, which bounds the
+/// memory a scan needs on top of the records it hands out.
+const BATCH_BYTES: usize = 4 << 20;
+/// Batches at least this large are decoded on the blocking pool, split
+/// across its threads.
+const PARALLEL_BYTES: usize = 256 << 10;
+
+/// A frame read from a file but not decoded yet.
+struct RawFrame {
+    offset: u64,
+    bytes: Vec<u8>,
+}
+
+/// Consecutive frames read ahead of decoding.
+struct Batch {
+    frames: Vec<RawFrame>,
+    bytes: usize,
+    /// Reading stopped at a frame it could not read whole: one that runs
+    /// past the end, or one larger than a batch whose checksum is wrong.
+    incomplete: bool,
+}
+
+/// A frame after decoding.
+enum Decoded {
+    Record(Record),
+    /// Its checksum or op byte is wrong, which a torn tail can explain.
+    Damaged,
+    /// It is intact but its payload fails to decrypt or decompress.
+    Corrupt,
+}
+
+/// Read whole frames from `offset` on, stopping after [`BATCH_BYTES`], at
+/// `end` or at a frame that runs past it. Only the length prefix is looked
+/// at, except that a checksummed frame larger than a batch is only read
+/// whole once its checksum is known to match: a damaged length prefix must
+/// not make a damaged frame take up to 4 GiB of memory.
+async fn read_batch<R: AsyncRead + AsyncSeek + Unpin>(
+    reader: &mut R,
+    mut offset: u64,
+    end: u64,
+    overhead: usize,
+    checksummed: bool,
+) -> Result<Batch> {
+    let mut batch = Batch {
+        frames: Vec::new(),
+        bytes: 0,
+        incomplete: false,
+    };
+    while batch.bytes < BATCH_BYTES && offset < end {
+        let left = end - offset;
+        let mut prefix = [0u8; 4];
+        if left < overhead as u64 || !read_fully(reader, &mut prefix).await? {
+            batch.incomplete = true;
+            break;
+        }
+        let len = overhead as u64 + u32::from_le_bytes(prefix) as u64;
+        if len > left {
+            batch.incomplete = true;
+            break;
+        }
+        if checksummed && len > BATCH_BYTES as u64 {
+            let intact = checksum_matches(reader, prefix, len as usize).await?;
+            reader.seek(SeekFrom::Start(offset + 4)).await?;
+            if !intact {
+                batch.incomplete = true;
+                break;
+            }
+        }
+        let mut bytes = vec![0u8; len as usize];
+        bytes[..4].copy_from_slice(&prefix);
+        if !read_fully(reader, &mut bytes[4..]).await? {
+            batch.incomplete = true;
+            break;
+        }
+        batch.frames.push(RawFrame { offset, bytes });
+        batch.bytes += len as usize;
+        offset += len;
+    }
+    Ok(batch)
+}
+
+/// `read_exact` that reports a file shorter than expected as `false`.
+async fn read_fully<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buf).await {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn decode_frames(
+    frames: Vec<RawFrame>,
+    framing: &Framing,
+    layout: Layout,
+) -> Vec<(u64, u64, Decoded)> {
+    frames
+        .into_iter()
+        .map(|frame| {
+            let decoded = match parse_record(&frame.bytes, framing.checksummed, layout) {
+                Frame::Valid {
+                    op,
+                    id,
+                    stamp,
+                    payload,
+                    ..
+                } => match decode_payload(op, id, payload, framing) {
+                    Some(payload) => Decoded::Record(Record {
+                        op,
+                        id,
+                        payload,
+                        stamp,
+                    }),
+                    None => Decoded::Corrupt,
+                },
+                Frame::Truncated | Frame::Invalid(_) => Decoded::Damaged,
+            };
+            (frame.offset, frame.bytes.len() as u64, decoded)
+        })
+        .collect()
+}
+
+/// Decode a batch, in parallel if it is large enough to pay for it: split
+/// into one chunk per core, each decoded by a task on the blocking pool. The
+/// results keep the order of the frames.
+async fn decode_batch(
+    batch: Batch,
+    framing: &Framing,
+    layout: Layout,
+) -> Result<Vec<(u64, u64, Decoded)>> {
+    if batch.bytes < PARALLEL_BYTES {
+        return Ok(decode_frames(batch.frames, framing, layout));
+    }
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let per_task = batch.frames.len().div_ceil(threads).max(1);
+    let mut frames = batch.frames;
+    let mut tasks = Vec::new();
+    while !frames.is_empty() {
+        let rest = frames.split_off(per_task.min(frames.len()));
+        let chunk = std::mem::replace(&mut frames, rest);
+        let framing = framing.clone();
+        tasks.push(tokio::task::spawn_blocking(move || {
+            decode_frames(chunk, &framing, layout)
+        }));
+    }
+    let mut decoded = Vec::new();
+    for task in tasks {
+        decoded.extend(
+            task.await
+                .map_err(|e| RedDbError::Io(std::io::Error::other(e)))?,
+        );
+    }
+    Ok(decoded)
+}
+
+/// What [`stream`] hands the records it decodes to. Any
+/// `FnMut(Record, Extent) -> Result<()>` is one.
+pub(super) trait Visitor {
+    fn visit(&mut self, record: Record, extent: Extent) -> Result<()>;
+
+    /// Called once every record of a batch has been visited, before the
+    /// records of the next one, so that what was made of them can be written
+    /// out instead of piling up.
+    async fn batch_done(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(Record, Extent) -> Result<()>> Visitor for F {
+    fn visit(&mut self, record: Record, extent: Extent) -> Result<()> {
+        self(record, extent)
+    }
+}
+
+/// Decode the records between byte offsets `start`, a record boundary, and
+/// `end`, handing each to `visitor` in order, and return the offset just past
+/// the last intact one.
+///
+/// A damaged record at the end of the file is the footprint of a crash during
+/// a write; decoding stops there and the returned offset excludes it. A
+/// damaged record followed by intact ones means the file itself is corrupted,
+/// which is reported with the record's absolute byte offset; see
+/// [`torn_tail`].
+///
+/// Frames are read through a buffer in batches of [`BATCH_BYTES`], and the
+/// next batch is read while the previous one is decoded, so memory stays
+/// bounded whatever the size of the file. Past a damaged frame the rest of
+/// the file is walked in batches too.
+async fn stream(
+    file: &mut File,
+    start: u64,
+    end: u64,
+    framing: &Framing,
+    layout: Layout,
+    mut visitor: impl Visitor,
+) -> Result<u64> {
+    file.seek(SeekFrom::Start(start)).await?;
+    let overhead = layout_overhead(layout, framing.checksummed);
+    let mut reader = BufReader::new(&mut *file);
+    let mut batch = read_batch(&mut reader, start, end, overhead, framing.checksummed).await?;
+    let mut valid = start;
+    let tail_at = 'replay: loop {
+        let incomplete = batch.incomplete;
+        let read_to = batch
+            .frames
+            .last()
+            .map_or(start, |f| f.offset + f.bytes.len() as u64);
+        let done = incomplete || batch.frames.is_empty() || read_to >= end;
+        let (decoded, next) = if done {
+            (decode_batch(batch, framing, layout).await?, None)
+        } else {
+            let (decoded, next) = tokio::join!(
+                decode_batch(batch, framing, layout),
+                read_batch(&mut reader, read_to, end, overhead, framing.checksummed)
+            );
+            (decoded?, Some(next?))
+        };
+        for (offset, len, decoded) in decoded {
+            match decoded {
+                Decoded::Record(record) => {
+                    visitor.visit(record, Extent { offset, len })?;
+                    valid = offset + len;
+                }
+                Decoded::Damaged => break 'replay Some(offset),
+                Decoded::Corrupt => return Err(RedDbError::CorruptRecord(offset)),
+            }
+        }
+        visitor.batch_done().await?;
+        match next {
+            Some(next) => batch = next,
+            None if incomplete => break Some(valid),
+            None => break None,
+        }
+    };
+    drop(reader);
+    match tail_at {
+        Some(offset) => torn_tail(file, offset, end, framing, layout).await,
+        None => Ok(valid),
+    }
+}
+
+/// Decode every record of a file, handing each to `visit` in order, and
+/// return the offset just past the last intact one. Memory use does not grow
+/// with the size of the file; see [`stream`].
+pub(super) async fn replay_records(
+    file: &mut File,
+    framing: &Framing,
+    mut visit: impl FnMut(Record) -> Result<()>,
+) -> Result<u64> {
+    replay_extents(file, framing, |record, _| visit(record)).await
+}
+
+/// [`replay_records`], also telling `visit` where each record sits.
+pub(super) async fn replay_extents(
+    file: &mut File,
+    framing: &Framing,
+    visit: impl FnMut(Record, Extent) -> Result<()>,
+) -> Result<u64> {
+    let end = file.metadata().await?.len();
+    stream(file, HEADER_LEN, end, framing, Layout::V3, visit).await
+}
+
+/// Decode the frame a file holds at some [`Extent`]. `None` if it is damaged.
+#[cfg(feature = "paged")]
+pub(super) fn decode_frame(frame: &[u8], framing: &Framing) -> Option<Record> {
+    match parse_record(frame, framing.checksummed, Layout::V3) {
+        Frame::Valid {
+            op,
+            id,
+            stamp,
+            payload,
+            len,
+        } if len == frame.len() => Some(Record {
+            op,
+            id,
+            payload: decode_payload(op, id, payload, framing)?,
+            stamp,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(super) async fn read_records(file: &mut File, framing: &Framing) -> Result<Scan> {
+    read_records_from(file, HEADER_LEN, framing).await
+}
+
+/// Decode the records from byte offset `start` (a record boundary) to the end of the file.
+pub(super) async fn read_records_from(
+    file: &mut File,
+    start: u64,
+    framing: &Framing,
+) -> Result<Scan> {
+    let end = file.metadata().await?.len();
+    read_records_range(file, start, end, framing).await
+}
+
+/// Decode the records between byte offsets `start` and `end`, both record
+/// boundaries. Bytes appended past `end` meanwhile are left alone.
+pub(super) async fn read_records_range(
+    file: &mut File,
+    start: u64,
+    end: u64,
+    framing: &Framing,
+) -> Result<Scan> {
+    let mut records = Vec::new();
+    let valid_len = stream(file, start, end, framing, Layout::V3, |record, _| {
+        records.push(record);
+        Ok(())
+    })
+    .await?;
+    Ok(Scan { records, valid_len })
+}
+
+/// Associated data sealed with an encrypted payload: the record's op and id.
+#[cfg(feature = "encryption")]
+fn payload_aad(op: WalOp, id: Uuid) -> [u8; 17] {
+    let mut aad = [0u8; 17];
+    aad[0] = encode_op(op);
+    aad[1..].copy_from_slice(id.as_bytes());
+    aad
+}
+
+/// Compress, then encrypt a payload. Empty payloads (delete records) stay empty.
+fn encode_payload(op: WalOp, id: Uuid, payload: &[u8], framing: &Framing) -> Result<Vec<u8>> {
+    let compressed = compress(framing.compression, payload)?;
+    #[cfg(feature = "encryption")]
+    if let Some(cipher) = framing.cipher.as_ref().filter(|_| !payload.is_empty()) {
+        return cipher.seal(&payload_aad(op, id), &compressed);
+    }
+    #[cfg(not(feature = "encryption"))]
+    let _ = (op, id);
+    Ok(compressed.into_owned())
+}
+
+/// Reverse [`encode_payload`]. `None` means the payload fails to decrypt or decompress.
+fn decode_payload(op: WalOp, id: Uuid, stored: &[u8], framing: &Framing) -> Option<Vec<u8>> {
+    #[cfg(feature = "encryption")]
+    if let Some(cipher) = framing.cipher.as_ref().filter(|_| !stored.is_empty()) {
+        let opened = cipher.open(&payload_aad(op, id), stored)?;
+        return decompress(framing.compression, &opened);
+    }
+    #[cfg(not(feature = "encryption"))]
+    let _ = (op, id);
+    decompress(framing.compression, stored)
+}
+
+fn encode_record(
+    op: WalOp,
+    id: Uuid,
+    payload: &[u8],
+    stamp: Stamp,
+    framing: &Framing,
+) -> Result<Vec<u8>> {
+    let mut frame = Vec::with_capacity(record_overhead(framing.checksummed) + payload.len());
+    encode_record_into(&mut frame, op, id, stamp, framing, |buf| {
+        copy_payload(payload, buf)
+    })?;
+    Ok(frame)
+}
+
+/// Capacity of a batch's frame buffer kept for the next batch; the excess
+/// of larger batches is freed.
+pub(super) const KEPT_FRAMES: usize = 1 << 20;
+
+/// Append the frame of one record to `buf`. `write_payload` appends the
+/// plain payload to the buffer it is given: `buf` itself, unless the framing
+/// compresses or encrypts payloads, which needs them apart first.
+pub(super) fn encode_record_into(
+    buf: &mut Vec<u8>,
+    op: WalOp,
+    id: Uuid,
+    stamp: Stamp,
+    framing: &Framing,
+    write_payload: impl FnOnce(&mut Vec<u8>) -> Result<()>,
+) -> Result<()> {
+    let start = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+    buf.push(encode_op(op));
+    buf.extend_from_slice(id.as_bytes());
+    buf.extend_from_slice(&stamp.lsn.to_le_bytes());
+    buf.extend_from_slice(&stamp.timestamp.to_le_bytes());
+    let payload_start = buf.len();
+    if framing.compression == Compression::None && !framing.encrypted() {
+        write_payload(buf)?;
+    } else {
+        let mut plain = Vec::new();
+        write_payload(&mut plain)?;
+        buf.extend_from_slice(&encode_payload(op, id, &plain, framing)?);
+    }
+    let len = (buf.len() - payload_start) as u32;
+    buf[start..start + 4].copy_from_slice(&len.to_le_bytes());
+    if framing.checksummed {
+        let crc = crc32c::crc32c(&buf[start..]);
+        buf.extend_from_slice(&crc.to_le_bytes());
+    }
+    Ok(())
+}
+
+/// The `write_payload` of [`encode_record_into`] for a payload already encoded.
+pub(super) fn copy_payload(payload: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+    buf.extend_from_slice(payload);
+    Ok(())
+}
+
+/// Append one record and return the number of bytes written.
+pub(super) async fn write_record(
+    file: &mut File,
+    op: WalOp,
+    id: Uuid,
+    payload: &[u8],
+    stamp: Stamp,
+    framing: &Framing,
+) -> Result<u64> {
+    let frame = encode_record(op, id, payload, stamp, framing)?;
+    file.write_all(&frame).await?;
+    Ok(frame.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::fs::OpenOptions;
+
+    /// [`stream`] over a file holding `buf` from offset `start`.
+    async fn scan_all(buf: &[u8], start: u64, framing: &Framing, layout: Layout) -> Result<Scan> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scan.bin");
+        let mut bytes = vec![0u8; start as usize];
+        bytes.extend_from_slice(buf);
+        std::fs::write(&path, bytes).unwrap();
+        let mut file = File::open(&path).await.unwrap();
+        let end = start + buf.len() as u64;
+        let mut records = Vec::new();
+        let valid_len = stream(&mut file, start, end, framing, layout, |record, _| {
+            records.push(record);
+            Ok(())
+        })
+        .await?;
+        Ok(Scan { records, valid_len })
+    }
+
+    async fn scan_records(buf: &[u8], start: u64, framing: &Framing) -> Result<Scan> {
+        scan_all(buf, start, framing, Layout::V3).await
+    }
+
+    fn plain(checksummed: bool) -> Framing {
+        Framing {
+            checksummed,
+            compression: Compression::None,
+            #[cfg(feature = "encryption")]
+            cipher: None,
+        }
+    }
+
+    #[test]
+    fn build_header_magic_and_version() {
+        let h = build_header(FormatId::Ron, &plain(true), 0);
+        assert_eq!(&h[0..8], MAGIC);
+        assert_eq!(u16::from_le_bytes(h[8..10].try_into().unwrap()), VERSION);
+        assert_eq!(h[10], 1);
+        assert_eq!(h[11], FLAG_CHECKSUM);
+        assert!(h[12..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn build_header_records_base_lsn() {
+        let h = build_header(FormatId::Ron, &plain(true), 42);
+        assert_eq!(base_lsn(&h), 42);
+    }
+
+    #[cfg(feature = "lz4_compression")]
+    #[test]
+    fn build_header_records_codec() {
+        let framing = Framing {
+            compression: Compression::Lz4,
+            ..plain(true)
+        };
+        let h = build_header(FormatId::Json, &framing, 0);
+        assert_eq!(h[12], codec_id(Compression::Lz4));
+        assert!(h[13..].iter().all(|&b| b == 0));
+    }
+
+    #[cfg(feature = "zstd_compression")]
+    #[test]
+    fn parse_framing_keeps_configured_level_for_same_codec() {
+        let zstd = |level| Framing {
+            compression: Compression::Zstd(level),
+            ..plain(true)
+        };
+        let header = build_header(FormatId::Json, &zstd(3), 0);
+        let reopened = parse_framing(&header, &zstd(19)).unwrap();
+        assert_eq!(reopened.compression, Compression::Zstd(19));
+        let reopened = parse_framing(&header, &plain(true)).unwrap();
+        assert!(matches!(reopened.compression, Compression::Zstd(_)));
+    }
+
+    #[test]
+    fn verify_header_succeeds_for_matching_format() {
+        let h = build_header(FormatId::Json, &plain(true), 0);
+        assert!(verify_header(&h, FormatId::Json).is_ok());
+    }
+
+    #[test]
+    fn verify_header_fails_for_wrong_magic() {
+        let mut h = build_header(FormatId::Bin, &plain(true), 0);
+        h[0] = 0xFF;
+        assert!(matches!(
+            verify_header(&h, FormatId::Bin),
+            Err(RedDbError::NotADatabase)
+        ));
+    }
+
+    #[test]
+    fn verify_header_fails_for_wrong_format() {
+        let h = build_header(FormatId::Bin, &plain(true), 0);
+        assert!(matches!(
+            verify_header(&h, FormatId::Json),
+            Err(RedDbError::FormatMismatch {
+                expected: FormatId::Json,
+                found: FormatId::Bin
+            })
+        ));
+    }
+
+    #[test]
+    fn verify_header_fails_for_wrong_version() {
+        let mut h = build_header(FormatId::Yaml, &plain(true), 0);
+        h[8] = 0xFF;
+        assert!(matches!(
+            verify_header(&h, FormatId::Yaml),
+            Err(RedDbError::UnsupportedVersion(0xFF))
+        ));
+        h[8..10].copy_from_slice(&2u16.to_le_bytes());
+        assert!(matches!(
+            verify_header(&h, FormatId::Yaml),
+            Err(RedDbError::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn format_bytes() {
+        let formats = [
+            FormatId::Json,
+            FormatId::Ron,
+            FormatId::Yaml,
+            FormatId::Bin,
+            FormatId::MsgPack,
+            FormatId::Cbor,
+            FormatId::Postcard,
+        ];
+        for (byte, format) in formats.into_iter().enumerate() {
+            let h = build_header(format, &plain(true), 0);
+            assert_eq!(h[10], byte as u8);
+            assert_eq!(h[14], 0);
+            assert_eq!(header_format(&h).unwrap(), format);
+        }
+    }
+
+    #[test]
+    fn custom_format_stores_its_tag() {
+        let h = build_header(FormatId::Custom(7), &plain(true), 0);
+        assert_eq!(h[10], FORMAT_CUSTOM);
+        assert_eq!(h[14], 7);
+        assert_eq!(header_format(&h).unwrap(), FormatId::Custom(7));
+        assert!(verify_header(&h, FormatId::Custom(7)).is_ok());
+        assert!(matches!(
+            verify_header(&h, FormatId::Custom(8)),
+            Err(RedDbError::FormatMismatch {
+                expected: FormatId::Custom(8),
+                found: FormatId::Custom(7)
+            })
+        ));
+        assert!(matches!(
+            verify_header(&h, FormatId::Bin),
+            Err(RedDbError::FormatMismatch {
+                found: FormatId::Custom(7),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn unknown_format_byte_is_reported() {
+        let mut h = build_header(FormatId::Json, &plain(true), 0);
+        h[10] = 42;
+        assert!(matches!(
+            verify_header(&h, FormatId::Json),
+            Err(RedDbError::UnknownFormat(42))
+        ));
+    }
+
+    fn encode(record: &Record, framing: &Framing) -> Vec<u8> {
+        encode_record(record.op, record.id, &record.payload, record.stamp, framing).unwrap()
+    }
+
+    fn encode_all(records: &[Record], framing: &Framing) -> Vec<u8> {
+        records.iter().flat_map(|r| encode(r, framing)).collect()
+    }
+
+    fn frames(records: &[Record], checksummed: bool) -> Vec<u8> {
+        encode_all(records, &plain(checksummed))
+    }
+
+    fn sample() -> Vec<Record> {
+        let mut stamp = Stamp::default();
+        [
+            (WalOp::Insert, b"first".to_vec()),
+            (WalOp::Insert, b"second".to_vec()),
+            (WalOp::Delete, Vec::new()),
+        ]
+        .into_iter()
+        .map(|(op, payload)| {
+            stamp = stamp.next();
+            Record {
+                op,
+                id: Uuid::new_v4(),
+                payload,
+                stamp,
+            }
+        })
+        .collect()
+    }
+
+    #[test]
+    fn stamps_increase() {
+        let first = Stamp::default().next();
+        let second = first.next();
+        assert_eq!((first.lsn, second.lsn), (1, 2));
+        assert!(first.timestamp > 0 && second.timestamp >= first.timestamp);
+        let future = Stamp {
+            lsn: 7,
+            timestamp: u64::MAX,
+        };
+        assert_eq!(future.next().timestamp, u64::MAX);
+    }
+
+    #[test]
+    fn replay_until_stops_after_the_point() {
+        use std::time::Duration;
+
+        let at = |micros| UNIX_EPOCH + Duration::from_micros(micros);
+        // A snapshot at LSN 4, written at time 40, then records 5 and 6.
+        let records: Vec<Record> = [(4, 40), (4, 40), (5, 50), (6, 60)]
+            .into_iter()
+            .map(|(lsn, timestamp)| Record {
+                op: WalOp::Insert,
+                id: Uuid::new_v4(),
+                payload: Vec::new(),
+                stamp: Stamp { lsn, timestamp },
+            })
+            .collect();
+        let replay_until = |base, records: &[Record], point| -> Result<usize> {
+            let mut cutoff = Cutoff::new(base, point)?;
+            let mut kept = 0;
+            for record in records {
+                kept += usize::from(cutoff.admits(record.stamp)?);
+            }
+            cutoff.finish()?;
+            Ok(kept)
+        };
+        let kept = |point| replay_until(4, &records, point);
+
+        assert_eq!(kept(AsOf::Lsn(4)).unwrap(), 2);
+        assert_eq!(kept(AsOf::Lsn(5)).unwrap(), 3);
+        assert_eq!(kept(AsOf::Lsn(99)).unwrap(), 4);
+        assert_eq!(kept(AsOf::Time(at(40))).unwrap(), 2);
+        assert_eq!(kept(AsOf::Time(at(59))).unwrap(), 3);
+        assert!(matches!(
+            kept(AsOf::Lsn(3)),
+            Err(RedDbError::HistoryUnavailable(4))
+        ));
+        assert!(matches!(
+            kept(AsOf::Time(at(39))),
+            Err(RedDbError::HistoryUnavailable(4))
+        ));
+        // With no compaction behind it, the log covers all of history.
+        assert_eq!(replay_until(0, &[], AsOf::Time(at(0))).unwrap(), 0);
+        assert!(replay_until(4, &[], AsOf::Time(at(99))).is_err());
+    }
+
+    #[test]
+    fn records_apply_in_order() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        let mut replay = Replay::default();
+        for (op, id, payload) in [
+            (WalOp::Insert, a, b"a1".to_vec()),
+            (WalOp::Insert, b, b"b1".to_vec()),
+            (WalOp::Update, a, b"a2".to_vec()),
+            (WalOp::Delete, b, Vec::new()),
+        ] {
+            replay.apply(Record {
+                op,
+                id,
+                payload,
+                stamp: Stamp::default(),
+            });
+        }
+        let map = replay.finish();
+        assert_eq!(map.len(), 1);
+        assert_eq!(map[&a], b"a2");
+    }
+
+    #[test]
+    fn replay_keeps_insertion_order() {
+        let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        let record = |op, i: usize| Record {
+            op,
+            id: ids[i],
+            payload: vec![i as u8],
+            stamp: Stamp::default(),
+        };
+        let log = [
+            record(WalOp::Insert, 0),
+            record(WalOp::Insert, 1),
+            record(WalOp::Insert, 2),
+            record(WalOp::Update, 0),
+            record(WalOp::Delete, 1),
+            record(WalOp::Insert, 3),
+            record(WalOp::Delete, 2),
+            record(WalOp::Insert, 2),
+        ];
+        let mut replay = Replay::default();
+        for r in log {
+            replay.apply(r);
+        }
+        let map = replay.finish();
+        // Updates keep their place; a document written again after its
+        // delete moves to the end.
+        assert!(map.keys().eq([&ids[0], &ids[3], &ids[2]]));
+        assert_eq!(map[&ids[0]], [0]);
+    }
+
+    /// A file holding `count` records of `size`-byte payloads, enough to
+    /// span several batches, and the records themselves.
+    async fn large_file(dir: &std::path::Path, count: usize, size: usize) -> (File, Vec<Record>) {
+        let mut stamp = Stamp::default();
+        let records: Vec<Record> = (0..count)
+            .map(|i| {
+                stamp = stamp.next();
+                Record {
+                    op: WalOp::Insert,
+                    id: Uuid::new_v4(),
+                    payload: vec![i as u8; size],
+                    stamp,
+                }
+            })
+            .collect();
+        let path = dir.join("large.bin");
+        let mut buf = build_header(FormatId::Bin, &plain(true), 0).to_vec();
+        buf.extend(encode_all(&records, &plain(true)));
+        std::fs::write(&path, buf).unwrap();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .await
+            .unwrap();
+        (file, records)
+    }
+
+    #[tokio::test]
+    async fn replay_streams_records_across_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut file, records) = large_file(dir.path(), 300, 40_000).await;
+        let len = file.metadata().await.unwrap().len();
+        assert!(len > 2 * BATCH_BYTES as u64);
+
+        let mut replayed = Vec::new();
+        let valid_len = replay_records(&mut file, &plain(true), |record| {
+            replayed.push(record);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(valid_len, len);
+        assert_eq!(replayed, records);
+    }
+
+    #[tokio::test]
+    async fn replay_stops_before_a_torn_tail_of_a_large_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut file, records) = large_file(dir.path(), 300, 40_000).await;
+        let len = file.metadata().await.unwrap().len();
+        file.set_len(len - 10).await.unwrap();
+
+        let scan = read_records(&mut file, &plain(true)).await.unwrap();
+        assert_eq!(scan.records, records[..299]);
+        assert_eq!(
+            scan.valid_len,
+            len - encode(&records[299], &plain(true)).len() as u64
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_reports_corruption_in_a_later_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut file, records) = large_file(dir.path(), 300, 40_000).await;
+        let frame_len = encode(&records[0], &plain(true)).len() as u64;
+        let offset = HEADER_LEN + 200 * frame_len;
+        file.seek(SeekFrom::Start(offset + 50)).await.unwrap();
+        file.write_all(b"garbage").await.unwrap();
+
+        let mut replayed = 0;
+        let result = replay_records(&mut file, &plain(true), |_| {
+            replayed += 1;
+            Ok(())
+        })
+        .await;
+        assert!(matches!(result, Err(RedDbError::CorruptRecord(o)) if o == offset));
+        assert_eq!(replayed, 200);
+    }
+
+    #[tokio::test]
+    async fn replay_reports_damage_longer_than_a_batch_before_intact_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut file, records) = large_file(dir.path(), 300, 40_000).await;
+        let len = file.metadata().await.unwrap().len();
+        let frame_len = encode(&records[0], &plain(true)).len() as u64;
+        let offset = HEADER_LEN + 10 * frame_len;
+        file.seek(SeekFrom::Start(offset)).await.unwrap();
+        file.write_all(&vec![0xAB; 2 * BATCH_BYTES]).await.unwrap();
+
+        let result = read_records(&mut file, &plain(true)).await;
+        assert!(matches!(result, Err(RedDbError::CorruptRecord(o)) if o == offset));
+        assert_eq!(file.metadata().await.unwrap().len(), len);
+    }
+
+    #[tokio::test]
+    async fn replay_stops_before_a_torn_tail_longer_than_a_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut file, records) = large_file(dir.path(), 10, 40_000).await;
+        let len = file.metadata().await.unwrap().len();
+        file.seek(SeekFrom::End(0)).await.unwrap();
+        file.write_all(&vec![0xAB; 2 * BATCH_BYTES]).await.unwrap();
+
+        let scan = read_records(&mut file, &plain(true)).await.unwrap();
+        assert_eq!(scan.records, records);
+        assert_eq!(scan.valid_len, len);
+    }
+
+    /// Every record [`check`] finds in `file`, and every damaged stretch.
+    async fn check_file(file: &mut File) -> (Vec<Uuid>, Vec<Damage>) {
+        let end = file.metadata().await.unwrap().len();
+        let (mut ids, mut damage) = (Vec::new(), Vec::new());
+        check(file, HEADER_LEN, end, &plain(true), |found| match found {
+            Ok((record, _)) => ids.push(record.id),
+            Err(d) => damage.push(d),
+        })
+        .await
+        .unwrap();
+        (ids, damage)
+    }
+
+    #[tokio::test]
+    async fn records_larger_than_a_batch_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut file, records) = large_file(dir.path(), 3, BATCH_BYTES + 1).await;
+
+        let scan = read_records(&mut file, &plain(true)).await.unwrap();
+        assert_eq!(scan.records, records);
+        let (ids, damage) = check_file(&mut file).await;
+        assert_eq!(ids, records.iter().map(|r| r.id).collect::<Vec<_>>());
+        assert!(damage.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_damaged_record_larger_than_a_batch_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut file, records) = large_file(dir.path(), 3, BATCH_BYTES + 1).await;
+        let frame_len = encode(&records[0], &plain(true)).len() as u64;
+        let offset = HEADER_LEN + frame_len;
+        file.seek(SeekFrom::Start(offset + frame_len / 2))
+            .await
+            .unwrap();
+        file.write_all(b"garbage").await.unwrap();
+
+        let result = read_records(&mut file, &plain(true)).await;
+        assert!(matches!(result, Err(RedDbError::CorruptRecord(o)) if o == offset));
+        let (ids, damage) = check_file(&mut file).await;
+        assert_eq!(ids, [records[0].id, records[2].id]);
+        assert_eq!(
+            damage,
+            [Damage {
+                extent: Extent {
+                    offset,
+                    len: frame_len,
+                },
+                kind: ProblemKind::ChecksumMismatch,
+                id: None,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn scan_reads_version_2_records_without_stamps() {
+        let records = sample();
+        let mut buf = Vec::new();
+        for r in &records {
+            // A v2 frame is the v3 one without the stamp.
+            let v3 = encode(r, &plain(false));
+            buf.extend_from_slice(&v3[..V2_RECORD_OVERHEAD]);
+            buf.extend_from_slice(&v3[RECORD_OVERHEAD..]);
+        }
+        let scan = scan_all(&buf, HEADER_LEN, &plain(false), Layout::V2)
+            .await
+            .unwrap();
+        let unstamped: Vec<Record> = records
+            .into_iter()
+            .map(|r| Record {
+                stamp: Stamp::default(),
+                ..r
+            })
+            .collect();
+        assert_eq!(scan.records, unstamped);
+    }
+
+    #[tokio::test]
+    async fn scan_round_trips_checksummed_records() {
+        let records = sample();
+        let buf = frames(&records, true);
+        let scan = scan_records(&buf, HEADER_LEN, &plain(true)).await.unwrap();
+        assert_eq!(scan.records, records);
+        assert_eq!(scan.valid_len, HEADER_LEN + buf.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn scan_reads_files_without_checksums() {
+        let records = sample();
+        let buf = frames(&records, false);
+        let scan = scan_records(&buf, HEADER_LEN, &plain(false)).await.unwrap();
+        assert_eq!(scan.records, records);
+    }
+
+    #[tokio::test]
+    async fn scan_stops_before_partial_trailing_record() {
+        let records = sample();
+        let mut buf = frames(&records[..2], true);
+        let intact = buf.len();
+        let last = encode_record(
+            WalOp::Update,
+            Uuid::new_v4(),
+            b"torn",
+            Stamp::default(),
+            &plain(true),
+        )
+        .unwrap();
+        buf.extend_from_slice(&last[..last.len() - 3]);
+
+        let scan = scan_records(&buf, HEADER_LEN, &plain(true)).await.unwrap();
+        assert_eq!(scan.records, records[..2]);
+        assert_eq!(scan.valid_len, HEADER_LEN + intact as u64);
+    }
+
+    #[tokio::test]
+    async fn scan_stops_before_trailing_record_with_bad_checksum() {
+        let records = sample();
+        let mut buf = frames(&records, true);
+        let last_len = encode(&records[2], &plain(true)).len();
+        let intact = buf.len() - last_len;
+        let n = buf.len();
+        buf[n - 1] ^= 0xFF;
+
+        let scan = scan_records(&buf, HEADER_LEN, &plain(true)).await.unwrap();
+        assert_eq!(scan.records, records[..2]);
+        assert_eq!(scan.valid_len, HEADER_LEN + intact as u64);
+    }
+
+    #[tokio::test]
+    async fn scan_reports_offset_of_corruption_in_the_middle() {
+        let records = sample();
+        let mut buf = frames(&records, true);
+        let first_len = encode(&records[0], &plain(true)).len();
+        // Flip a payload byte of the second record.
+        buf[first_len + RECORD_OVERHEAD] ^= 0xFF;
+
+        match scan_records(&buf, HEADER_LEN, &plain(true)).await {
+            Err(RedDbError::CorruptRecord(offset)) => {
+                assert_eq!(offset, HEADER_LEN + first_len as u64)
+            }
+            other => panic!("expected CorruptRecord, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_reports_unknown_op_in_unchecksummed_file() {
+        let records = sample();
+        let mut buf = frames(&records, false);
+        buf[4] = 0x7F;
+        assert!(matches!(
+            scan_records(&buf, HEADER_LEN, &plain(false)).await,
+            Err(RedDbError::CorruptRecord(HEADER_LEN))
+        ));
+    }
+
+    /// What [`check`] finds in `buf`: the ids of the intact records and the
+    /// damaged stretches.
+    fn check_all(buf: &[u8], framing: &Framing) -> (Vec<Uuid>, Vec<Damage>) {
+        check_in_pieces(buf, buf.len(), framing)
+    }
+
+    /// [`check_all`], feeding the walk `piece` bytes at a time.
+    fn check_in_pieces(buf: &[u8], piece: usize, framing: &Framing) -> (Vec<Uuid>, Vec<Damage>) {
+        let (mut ids, mut damage) = (Vec::new(), Vec::new());
+        let mut visit = |found: Found| {
+            match found {
+                Ok((record, _)) => ids.push(record.id),
+                Err(d) => damage.push(d),
+            }
+            ControlFlow::Continue(())
+        };
+        let mut walk = Walk::new(Layout::V3, HEADER_LEN, HEADER_LEN + buf.len() as u64);
+        let mut pending = Vec::new();
+        for bytes in buf.chunks(piece) {
+            pending.extend_from_slice(bytes);
+            let (walked, _) = walk.feed(&pending, false, framing, &mut visit);
+            pending.drain(..walked);
+        }
+        walk.feed(&pending, true, framing, &mut visit);
+        (ids, damage)
+    }
+
+    #[test]
+    fn check_skips_a_record_with_a_bad_checksum() {
+        let records = sample();
+        let mut buf = frames(&records, true);
+        let first_len = encode(&records[0], &plain(true)).len();
+        let second_len = encode(&records[1], &plain(true)).len();
+        buf[first_len + RECORD_OVERHEAD] ^= 0xFF;
+
+        let (ids, damage) = check_all(&buf, &plain(true));
+        assert_eq!(ids, [records[0].id, records[2].id]);
+        assert_eq!(
+            damage,
+            [Damage {
+                extent: Extent {
+                    offset: HEADER_LEN + first_len as u64,
+                    len: second_len as u64,
+                },
+                kind: ProblemKind::ChecksumMismatch,
+                id: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn check_resyncs_after_a_damaged_length() {
+        let records = sample();
+        let mut buf = frames(&records, true);
+        let first_len = encode(&records[0], &plain(true)).len();
+        let second_len = encode(&records[1], &plain(true)).len();
+        buf[first_len + 3] = 0x7F;
+
+        let (ids, damage) = check_all(&buf, &plain(true));
+        assert_eq!(ids, [records[0].id, records[2].id]);
+        assert_eq!(damage.len(), 1);
+        assert_eq!(damage[0].kind, ProblemKind::TruncatedFrame);
+        assert_eq!(damage[0].extent.len, second_len as u64);
+    }
+
+    #[test]
+    fn check_names_the_document_of_an_unknown_op() {
+        let records = sample();
+        let mut buf = frames(&records, false);
+        buf[4] = 0x7F;
+
+        let (ids, damage) = check_all(&buf, &plain(false));
+        assert_eq!(ids, [records[1].id, records[2].id]);
+        assert_eq!(damage[0].kind, ProblemKind::UnknownOp(0x7F));
+        assert_eq!(damage[0].id, Some(records[0].id));
+    }
+
+    #[test]
+    fn check_reports_a_torn_tail() {
+        let records = sample();
+        let mut buf = frames(&records, true);
+        buf.truncate(buf.len() - 3);
+        let last_len = encode(&records[2], &plain(true)).len();
+
+        let (ids, damage) = check_all(&buf, &plain(true));
+        assert_eq!(ids, [records[0].id, records[1].id]);
+        assert_eq!(damage[0].kind, ProblemKind::TruncatedFrame);
+        assert_eq!(damage[0].extent.len, last_len as u64 - 3);
+    }
+
+    #[test]
+    fn check_finds_the_same_when_fed_in_pieces() {
+        let records = sample();
+        let first_len = encode(&records[0], &plain(true)).len();
+        let mut buf = frames(&records, true);
+        let copy = buf.len();
+        buf[first_len + 3] = 0x7F;
+        buf.extend_from_slice(&frames(&records, true));
+        buf[copy + RECORD_OVERHEAD] ^= 0xFF;
+        buf.truncate(buf.len() - 3);
+
+        let whole = check_all(&buf, &plain(true));
+        assert_eq!(whole.1.len(), 3);
+        for piece in [1, 7, 64] {
+            assert_eq!(check_in_pieces(&buf, piece, &plain(true)), whole);
+        }
+    }
+
+    #[cfg(feature = "zstd_compression")]
+    #[tokio::test]
+    async fn scan_decompresses_payloads() {
+        let framing = Framing {
+            compression: Compression::Zstd(3),
+            ..plain(true)
+        };
+        let records = sample();
+        let buf = encode_all(&records, &framing);
+        let scan = scan_records(&buf, HEADER_LEN, &framing).await.unwrap();
+        assert_eq!(scan.records, records);
+    }
+
+    #[cfg(feature = "encryption")]
+    fn sealed(byte: u8) -> Framing {
+        Framing {
+            cipher: Some(Cipher::new(&crate::config::EncryptionKey::new([byte; 32]))),
+            ..plain(true)
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn build_header_records_cipher_and_key_check() {
+        let framing = sealed(1);
+        let h = build_header(FormatId::Bin, &framing, 0);
+        assert_eq!(h[13], CIPHER_ID);
+        assert_eq!(h[16..24], framing.cipher.unwrap().kcv());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn parse_framing_checks_the_key() {
+        let header = build_header(FormatId::Bin, &sealed(1), 0);
+        assert!(parse_framing(&header, &sealed(1)).unwrap().encrypted());
+        assert!(matches!(
+            parse_framing(&header, &sealed(2)),
+            Err(RedDbError::InvalidKey)
+        ));
+        assert!(matches!(
+            parse_framing(&header, &plain(true)),
+            Err(RedDbError::EncryptionKeyRequired)
+        ));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn plaintext_header_reads_as_unencrypted_even_with_a_key() {
+        let header = build_header(FormatId::Bin, &plain(true), 0);
+        assert!(!parse_framing(&header, &sealed(1)).unwrap().encrypted());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[tokio::test]
+    async fn scan_round_trips_encrypted_records() {
+        let framing = sealed(1);
+        let records = sample();
+        let buf = encode_all(&records, &framing);
+        assert!(!buf.windows(5).any(|w| w == b"first"));
+        let scan = scan_records(&buf, HEADER_LEN, &framing).await.unwrap();
+        assert_eq!(scan.records, records);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[tokio::test]
+    async fn payload_moved_to_another_record_fails_authentication() {
+        let framing = sealed(1);
+        let mut buf = encode_record(
+            WalOp::Insert,
+            Uuid::new_v4(),
+            b"secret",
+            Stamp::default(),
+            &framing,
+        )
+        .unwrap();
+        // Point the record at a different id and fix up the checksum.
+        buf[5] ^= 0xFF;
+        let body = buf.len() - CHECKSUM_LEN;
+        let crc = crc32c::crc32c(&buf[..body]);
+        buf[body..].copy_from_slice(&crc.to_le_bytes());
+        assert!(matches!(
+            scan_records(&buf, HEADER_LEN, &framing).await,
+            Err(RedDbError::CorruptRecord(HEADER_LEN))
+        ));
+    }
+}
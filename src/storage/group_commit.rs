@@ -0,0 +1,133 @@
+//! Group commit for the file-backed engines.
+//!
+//! Writers append their records under the log mutex, note how many records
+//! the log held afterwards, release the mutex and then wait here for an fsync
+//! that covers that count. Only one fsync runs at a time; a writer that finds
+//! its records already covered by someone else's fsync returns without
+//! issuing another one, so concurrent writers share a single sync.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::fs::File;
+use tokio::sync::Mutex;
+
+use crate::config::SyncMode;
+use crate::error::Result;
+
+#[derive(Debug)]
+pub(super) struct GroupCommit {
+    mode: SyncMode,
+    /// Number of records known to be on stable storage.
+    synced: AtomicU64,
+    /// Held by the writer currently running an fsync.
+    leader: Mutex<()>,
+}
+
+impl GroupCommit {
+    pub(super) fn new(mode: SyncMode) -> Self {
+        GroupCommit {
+            mode,
+            synced: AtomicU64::new(0),
+            leader: Mutex::new(()),
+        }
+    }
+
+    /// Whether a writer that brought the record count to `written` has to
+    /// wait for an fsync before returning.
+    pub(super) fn due(&self, written: u64) -> bool {
+        match self.mode {
+            SyncMode::Always => true,
+            // Another writer may have synced past `written` since it was read.
+            SyncMode::EveryN(n) => written.saturating_sub(self.synced.load(Ordering::Acquire)) >= n,
+            SyncMode::Interval(_) | SyncMode::Never => false,
+        }
+    }
+
+    /// Make the first `target` records durable. `current` returns the record
+    /// count and a handle to the file that holds them; it is only called when
+    /// no fsync since `target` was reached has covered it yet.
+    pub(super) async fn sync_to<F, Fut>(&self, target: u64, current: F) -> Result<()>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<(u64, File)>>,
+    {
+        let _leader = self.leader.lock().await;
+        if self.synced.load(Ordering::Acquire) >= target {
+            return Ok(());
+        }
+        let (written, file) = current().await?;
+        file.sync_data().await?;
+        self.mark_synced(written);
+        Ok(())
+    }
+
+    /// Record that the first `written` records reached stable storage by other
+    /// means, e.g. a compaction that synced its output.
+    pub(super) fn mark_synced(&self, written: u64) {
+        self.synced.fetch_max(written, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RedDbError;
+    use std::time::Duration;
+
+    #[test]
+    fn always_syncs_every_write() {
+        let gc = GroupCommit::new(SyncMode::Always);
+        assert!(gc.due(1));
+        gc.mark_synced(1);
+        assert!(gc.due(2));
+    }
+
+    #[test]
+    fn every_n_waits_for_n_unsynced_records() {
+        let gc = GroupCommit::new(SyncMode::EveryN(3));
+        assert!(!gc.due(2));
+        assert!(gc.due(3));
+        gc.mark_synced(3);
+        assert!(!gc.due(5));
+        assert!(gc.due(6));
+    }
+
+    #[test]
+    fn interval_and_never_leave_syncing_to_flush() {
+        let interval = GroupCommit::new(SyncMode::Interval(Duration::from_millis(10)));
+        let never = GroupCommit::new(SyncMode::Never);
+        assert!(!interval.due(1_000));
+        assert!(!never.due(1_000));
+    }
+
+    #[test]
+    fn mark_synced_never_moves_backwards() {
+        let gc = GroupCommit::new(SyncMode::EveryN(2));
+        gc.mark_synced(10);
+        gc.mark_synced(4);
+        assert!(!gc.due(11));
+    }
+
+    #[test]
+    fn a_writer_overtaken_by_a_later_sync_is_not_due() {
+        let gc = GroupCommit::new(SyncMode::EveryN(2));
+        gc.mark_synced(6);
+        assert!(!gc.due(5));
+    }
+
+    #[tokio::test]
+    async fn covered_writers_skip_the_fsync() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let gc = GroupCommit::new(SyncMode::Always);
+        gc.sync_to(2, || async { Ok((5, File::create(&path).await?)) })
+            .await
+            .unwrap();
+        // Records 1..=5 were covered by the first sync.
+        gc.sync_to(4, || async {
+            Err(RedDbError::PersistFailed("second fsync".into()))
+        })
+        .await
+        .unwrap();
+    }
+}
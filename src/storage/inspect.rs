@@ -0,0 +1,201 @@
+//! Untyped access to a [`FileStorage`](super::FileStorage) file, for tools
+//! that only have its path, such as the `reddb` command-line tool.
+//!
+//! Nothing here needs the document type or the serializer: the format is read
+//! from the header and payloads are handed out as stored, after checksum
+//! verification, decryption and decompression.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use tokio::fs::File;
+use uuid::Uuid;
+
+use super::format::{read_any_header, read_format, replay_extents, Framing, HEADER_LEN};
+use super::verify::Verifier;
+use super::{PayloadCheck, VerifyReport};
+use crate::config::{Compression, DbConfig};
+use crate::error::Result;
+use crate::serializer::FormatId;
+use crate::wal::WalOp;
+
+/// What the header and the records of a database file say about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    pub format: FormatId,
+    pub checksummed: bool,
+    pub compression: Compression,
+    pub encrypted: bool,
+    /// Sequence number of the last record written before the file was started.
+    pub base_lsn: u64,
+    /// Sequence number of the last record, or `base_lsn` if there is none.
+    pub last_lsn: u64,
+    /// Records in the file, including updates and deletes.
+    pub records: u64,
+    /// Documents alive after replaying every record.
+    pub documents: u64,
+    pub file_bytes: u64,
+    /// The header plus the latest record of every live document: what the
+    /// file would shrink to if compacted with its current framing.
+    pub live_bytes: u64,
+    /// Bytes taken by superseded and deleted records, and by a torn tail.
+    pub dead_bytes: u64,
+    /// Bytes of a torn trailing record, which opening the database discards.
+    pub truncated_tail_bytes: u64,
+}
+
+/// One record of a database file, as stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawRecord {
+    /// Byte offset of the record in the file.
+    pub offset: u64,
+    pub op: WalOp,
+    pub id: Uuid,
+    pub lsn: u64,
+    /// Microseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The serialized document; empty for deletes.
+    pub payload: Vec<u8>,
+}
+
+/// The format recorded in the header of the file at `path`. Reads nothing
+/// else, so it needs no encryption key.
+pub async fn format(path: impl AsRef<Path>) -> Result<FormatId> {
+    read_format(&mut File::open(path.as_ref()).await?).await
+}
+
+/// Read the file at `path` and describe it. `config` supplies the encryption
+/// key, if the file is encrypted; its name and directory are not used.
+///
+/// Fails like opening the database does: with
+/// [`RedDbError::CorruptRecord`](crate::RedDbError::CorruptRecord) if a
+/// damaged record is followed by intact ones, and with
+/// [`RedDbError::UnsupportedVersion`](crate::RedDbError::UnsupportedVersion)
+/// for files that need [`upgrade_v2`](crate::migrate) first.
+pub async fn info(path: impl AsRef<Path>, config: &DbConfig) -> Result<FileInfo> {
+    scan(path, config, |_| Ok(())).await
+}
+
+/// Hand every record of the file at `path` to `visit`, in log order, and
+/// describe the file as [`info`] does. Records are read in batches, so
+/// memory use does not grow with the size of the file.
+pub async fn scan(
+    path: impl AsRef<Path>,
+    config: &DbConfig,
+    mut visit: impl FnMut(RawRecord) -> Result<()>,
+) -> Result<FileInfo> {
+    let mut file = File::open(path.as_ref()).await?;
+    let (format, framing, base_lsn) =
+        read_any_header(&mut file, &Framing::from_config(config)).await?;
+    let mut records = 0;
+    let mut last_lsn = base_lsn;
+    let mut live: HashMap<Uuid, u64> = HashMap::new();
+    let valid_len = replay_extents(&mut file, &framing, |record, extent| {
+        records += 1;
+        last_lsn = record.stamp.lsn;
+        if record.op == WalOp::Delete {
+            live.remove(&record.id);
+        } else {
+            live.insert(record.id, extent.len);
+        }
+        visit(RawRecord {
+            offset: extent.offset,
+            op: record.op,
+            id: record.id,
+            lsn: record.stamp.lsn,
+            timestamp: record.stamp.timestamp,
+            payload: record.payload,
+        })
+    })
+    .await?;
+    let file_bytes = file.metadata().await?.len();
+    let live_bytes = HEADER_LEN + live.values().sum::<u64>();
+    Ok(FileInfo {
+        format,
+        checksummed: framing.checksummed,
+        compression: framing.compression,
+        encrypted: framing.encrypted(),
+        base_lsn,
+        last_lsn,
+        records,
+        documents: live.len() as u64,
+        file_bytes,
+        live_bytes,
+        dead_bytes: file_bytes.saturating_sub(live_bytes),
+        truncated_tail_bytes: file_bytes - valid_len,
+    })
+}
+
+/// Verify the file at `path` like [`RedDb::verify`](crate::RedDb::verify)
+/// does, without knowing the document type: payloads are not deserialized.
+/// `config` supplies the encryption key, if the file is encrypted.
+pub async fn verify_file(path: impl AsRef<Path>, config: &DbConfig) -> Result<VerifyReport> {
+    verify_file_with(path, config, &|_| Ok(())).await
+}
+
+/// [`verify_file`], also handing every insert and update payload to `check`.
+pub async fn verify_file_with(
+    path: impl AsRef<Path>,
+    config: &DbConfig,
+    check: &PayloadCheck<'_>,
+) -> Result<VerifyReport> {
+    let path = path.as_ref();
+    let file = File::open(path).await?;
+    let end = file.metadata().await?.len();
+    let configured = Framing::from_config(config);
+    let mut verifier = Verifier::new(&configured, check);
+    verifier.file(path, file, None, end).await?;
+    Ok(verifier.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::format::{build_header, record_overhead, write_record, Stamp};
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn info_counts_live_and_dead_bytes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("db.json");
+        let framing = Framing::from_config(&DbConfig::new("db"));
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut file = File::create(&path).await.unwrap();
+        file.write_all(&build_header(FormatId::Json, &framing, 4))
+            .await
+            .unwrap();
+        let mut stamp = Stamp {
+            lsn: 4,
+            timestamp: 0,
+        };
+        for (op, id, payload) in [
+            (WalOp::Insert, a, "1"),
+            (WalOp::Insert, b, "2"),
+            (WalOp::Update, a, "10"),
+            (WalOp::Delete, b, ""),
+        ] {
+            stamp = stamp.next();
+            write_record(&mut file, op, id, payload.as_bytes(), stamp, &framing)
+                .await
+                .unwrap();
+        }
+        drop(file);
+
+        let mut seen = Vec::new();
+        let info = scan(&path, &DbConfig::new("db"), |r| {
+            seen.push((r.op, r.id, r.lsn, r.payload));
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(info.format, FormatId::Json);
+        assert_eq!(format(&path).await.unwrap(), FormatId::Json);
+        assert_eq!((info.base_lsn, info.last_lsn), (4, 8));
+        assert_eq!((info.records, info.documents), (4, 1));
+        assert_eq!(seen[2], (WalOp::Update, a, 7, b"10".to_vec()));
+        let overhead = record_overhead(true) as u64;
+        assert_eq!(info.live_bytes, HEADER_LEN + overhead + 2);
+        assert_eq!(info.dead_bytes, 3 * overhead + 2);
+        assert_eq!(info.truncated_tail_bytes, 0);
+    }
+}
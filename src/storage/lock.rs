@@ -0,0 +1,185 @@
+//! Advisory locks coordinating the handles open on a database.
+//!
+//! A writer locks `<path>.lock` exclusively for as long as it is open and
+//! writes the owning process id into it, so a refused opener can report who
+//! holds the database. Read-only handles skip that lock.
+//!
+//! A single-file database is replaced by one atomic rename, which readers
+//! cannot observe half done. A segmented database replaces several files in
+//! a checkpoint, so its readers hold `<path>.swap.lock` shared while loading
+//! and the writer holds it exclusively while it renames and deletes files.
+//!
+//! The OS releases the locks when the files are closed, including when the
+//! process dies, so lock files left behind by a crash do not block anyone.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{RedDbError, Result};
+
+/// Path of the lock file guarding the database at `path`.
+pub(super) fn lock_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".lock")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// An exclusive lock, released when dropped.
+#[derive(Debug)]
+pub(super) struct LockFile {
+    file: File,
+}
+
+impl LockFile {
+    /// Lock the database at `path` for writing, or fail with
+    /// [`RedDbError::Locked`] if another handle already holds it.
+    pub(super) fn acquire(path: &Path) -> Result<Self> {
+        let path = lock_path(path);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                file.read_to_string(&mut holder)?;
+                return Err(RedDbError::Locked {
+                    path: path.display().to_string(),
+                    pid: holder.trim().parse().ok(),
+                });
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        file.set_len(0)?;
+        file.write_all(std::process::id().to_string().as_bytes())?;
+        Ok(LockFile { file })
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        // The id would be stale once the lock is gone.
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+/// Lock ordering file swaps by the writer against loads by readers.
+#[derive(Debug)]
+pub(super) struct SwapLock {
+    file: File,
+}
+
+/// A held [`SwapLock`], released when dropped.
+#[derive(Debug)]
+pub(super) struct SwapGuard<'a> {
+    file: &'a File,
+}
+
+impl SwapLock {
+    /// Open the swap lock of the database at `path`, creating it if needed.
+    /// An existing lock file is opened read-only, so readers also work where
+    /// they may not write.
+    pub(super) fn open(path: &Path) -> Result<Self> {
+        let path = with_suffix(path, ".swap.lock");
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(SwapLock { file })
+    }
+
+    /// Held by a reader while it loads; waits for a swap in progress.
+    pub(super) async fn shared(&self) -> Result<SwapGuard<'_>> {
+        self.acquire(File::lock_shared).await
+    }
+
+    /// Held by the writer while it swaps files; waits for loading readers.
+    pub(super) async fn exclusive(&self) -> Result<SwapGuard<'_>> {
+        self.acquire(File::lock).await
+    }
+
+    async fn acquire(&self, lock: fn(&File) -> std::io::Result<()>) -> Result<SwapGuard<'_>> {
+        // The clone shares the lock with `self.file`; it only exists so the
+        // blocking call can run off the async runtime.
+        let file = self.file.try_clone()?;
+        tokio::task::spawn_blocking(move || lock(&file))
+            .await
+            .map_err(|e| RedDbError::PersistFailed(e.to_string()))??;
+        Ok(SwapGuard { file: &self.file })
+    }
+}
+
+impl Drop for SwapGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_path_appends_suffix() {
+        assert_eq!(
+            lock_path(Path::new("dir/db.json")),
+            PathBuf::from("dir/db.json.lock")
+        );
+    }
+
+    #[test]
+    fn second_acquire_reports_the_holder() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("db.bin");
+        let _held = LockFile::acquire(&db).unwrap();
+        match LockFile::acquire(&db) {
+            Err(RedDbError::Locked { pid, .. }) => assert_eq!(pid, Some(std::process::id())),
+            other => panic!("expected Locked, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn readers_share_the_swap_lock_and_exclude_the_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("db.bin");
+        let (reader, other_reader, writer) = (
+            SwapLock::open(&db).unwrap(),
+            SwapLock::open(&db).unwrap(),
+            SwapLock::open(&db).unwrap(),
+        );
+        let loading = reader.shared().await.unwrap();
+        let _also_loading = other_reader.shared().await.unwrap();
+        assert!(matches!(
+            writer.file.try_lock(),
+            Err(TryLockError::WouldBlock)
+        ));
+        drop((loading, _also_loading));
+        let _swapping = writer.exclusive().await.unwrap();
+        assert!(matches!(
+            reader.file.try_lock_shared(),
+            Err(TryLockError::WouldBlock)
+        ));
+    }
+
+    #[test]
+    fn dropping_the_lock_releases_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("db.bin");
+        drop(LockFile::acquire(&db).unwrap());
+        let _again = LockFile::acquire(&db).unwrap();
+    }
+}
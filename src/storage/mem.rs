@@ -1,11 +1,15 @@
 use async_trait::async_trait;
 use core::fmt::Debug;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 use uuid::Uuid;
 
 use super::Storage;
+use crate::config::DbConfig;
+#[cfg(feature = "encryption")]
+use crate::config::EncryptionKey;
 use crate::document::Document;
+#[cfg(feature = "paged")]
+use crate::error::RedDbError;
 use crate::error::Result;
 use crate::wal::WalOp;
 use crate::RedDbHM;
@@ -17,7 +21,13 @@ pub struct MemStorage;
 
 #[async_trait]
 impl Storage for MemStorage {
-    async fn new(_db_name: &str, _compaction_ratio: f64) -> Result<Self> {
+    async fn new(config: &DbConfig) -> Result<Self> {
+        #[cfg(feature = "paged")]
+        if config.paged.is_some() {
+            return Err(RedDbError::Unsupported("paged mode"));
+        }
+        #[cfg(not(feature = "paged"))]
+        let _ = config;
         Ok(MemStorage)
     }
 
@@ -26,7 +36,7 @@ impl Storage for MemStorage {
     where
         for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq + Send + Sync,
     {
-        Ok(HashMap::new())
+        Ok(RedDbHM::new())
     }
 
     async fn persist<T>(&self, _data: &[Document<T>], _op: WalOp) -> Result<()>
@@ -47,6 +57,16 @@ impl Storage for MemStorage {
     async fn persist_raw(&self, _records: &[(WalOp, Uuid, Vec<u8>)]) -> Result<()> {
         Ok(())
     }
+
+    /// Nothing is stored, so there is nothing to re-encrypt.
+    #[cfg(feature = "encryption")]
+    async fn rotate_key(
+        &self,
+        _key: &EncryptionKey,
+        _data: &tokio::sync::RwLock<RedDbHM>,
+    ) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -77,7 +97,7 @@ mod tests {
 
     #[tokio::test]
     async fn new_ignores_db_name() {
-        let storage = MemStorage::new("any_path", 2.0).await.unwrap();
+        let storage = MemStorage::new(&DbConfig::new("any_path")).await.unwrap();
         let map = storage.load::<S>().await.unwrap();
         assert!(map.is_empty());
     }
@@ -90,9 +110,8 @@ mod tests {
 
     #[tokio::test]
     async fn compact_is_noop() {
-        use std::collections::HashMap;
         let storage = MemStorage;
-        let data: RedDbHM = HashMap::new();
+        let data: RedDbHM = RedDbHM::new();
         assert!(storage.compact(&data).await.is_ok());
     }
 }
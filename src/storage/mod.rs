@@ -1,27 +1,110 @@
+//! Storage backends and the [`Storage`] trait they implement.
+//!
+//! `RedDb` keeps every live document in memory and hands each write to its
+//! backend as a WAL record: an operation, the document id and the serialized
+//! payload. A backend's job is to make those records durable and to rebuild
+//! the in-memory map from them on open. In paged mode the map only lists the
+//! ids and the backend reads payloads back on demand with `fetch`.
+
+use crate::config::DbConfig;
+#[cfg(feature = "encryption")]
+use crate::config::EncryptionKey;
 use crate::document::Document;
-use crate::error::Result;
+use crate::error::{RedDbError, Result};
 use crate::wal::WalOp;
 use crate::RedDbHM;
 use core::fmt::Debug;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
 use uuid::Uuid;
 
+mod backup;
+mod codec;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+#[cfg(feature = "encryption")]
+mod crypto;
 mod file;
+mod format;
+mod group_commit;
+pub mod inspect;
+mod lock;
 mod mem;
+#[cfg(feature = "paged")]
+mod paged;
+mod salvage;
+mod segmented;
+#[cfg(feature = "migrate")]
+mod upgrade;
+mod verify;
 
+pub(crate) use backup::read_backup;
+pub use backup::BackupReport;
 pub use file::FileStorage;
 pub use mem::MemStorage;
+pub use salvage::SalvageReport;
+pub use segmented::SegmentedStorage;
+#[cfg(feature = "migrate")]
+pub(crate) use upgrade::upgrade_v2;
+pub use verify::{PayloadCheck, Problem, ProblemKind, VerifyReport};
+
+/// Whether a store of `file_size` bytes holding `live_size` bytes of live
+/// records has grown past `ratio` and should be compacted.
+pub(crate) fn should_compact(file_size: u64, live_size: u64, ratio: f64) -> bool {
+    live_size > 0 && (file_size as f64) >= (live_size as f64) * ratio
+}
 
+/// A persistence backend for [`RedDb`](crate::RedDb).
+///
+/// Implement it with `#[async_trait::async_trait]` and plug the type in as the
+/// second parameter of `RedDb<SE, ST>`. The
+/// [`conformance`](crate::storage::conformance) module (`conformance`
+/// feature) checks an implementation against the invariants below.
+///
+/// # Invariants
+///
+/// - **Replay.** `load` returns the map obtained by applying, in order, every
+///   record accepted by `persist`, `persist_raw` and `compact` since the store
+///   was created: `Insert` and `Update` set the payload for the id and
+///   `Delete` removes it. A `Delete` of an unknown id is a no-op. Documents
+///   are in the order they were first written; one written again after its
+///   delete goes to the end.
+/// - **Payload encoding.** `persist` must store `doc.data` encoded with the
+///   same serializer as the owning `RedDb`, because queries such as `find`
+///   compare stored payloads byte for byte. `persist_raw` payloads are already
+///   encoded and must be stored unchanged.
+/// - **Batches.** The records of a single `persist` or `persist_raw` call are
+///   applied in slice order and are not interleaved with records of a
+///   concurrent call. Once the returned future resolves the batch is durable
+///   to the degree the configured [`SyncMode`](crate::SyncMode) promises, and
+///   fully durable once a later `flush` resolves.
+/// - **Compaction.** `compact(data)` replaces the stored history with one
+///   `Insert` per entry of `data`, in the order of `data`. It is atomic: after a crash, `load` returns
+///   either the state before or the state after the compaction. Records
+///   persisted after `compact` returns are applied on top of `data`.
+/// - **Online compaction.** `compact_online(data)` must produce the same
+///   result as `compact` on the map `data` holds at some instant, followed by
+///   every record persisted after that instant. It may only hold the read lock
+///   on `data` briefly; writers keep running while the snapshot is written.
+/// - **Single writer.** `RedDb` calls `load` once, right after `new` and before
+///   any write. Methods take `&self` and may be called concurrently.
 #[async_trait::async_trait]
-pub(crate) trait Storage {
-    async fn new(db_name: &str, compaction_ratio: f64) -> Result<Self>
+pub trait Storage {
+    /// Open the store described by `config`, creating it if it does not exist.
+    /// Must not read the records yet; that is what `load` is for.
+    async fn new(config: &DbConfig) -> Result<Self>
     where
         Self: Sized;
 
+    /// Replay the stored records into a fresh map. `T` is the document type the
+    /// database was opened with.
     async fn load<T>(&self) -> Result<RedDbHM>
     where
         for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq + Send + Sync;
 
+    /// Append one `op` record per document. Delete records carry an empty payload.
     async fn persist<T>(&self, records: &[Document<T>], op: WalOp) -> Result<()>
     where
         for<'de> T: Serialize + Deserialize<'de> + Debug + Send + Sync + Clone;
@@ -29,10 +112,125 @@ pub(crate) trait Storage {
     /// Rewrite the storage with exactly one Insert record per live document.
     async fn compact(&self, data: &RedDbHM) -> Result<()>;
 
+    /// Compact while the database keeps accepting writes. The default holds
+    /// the read lock for the whole `compact`, which blocks writers until it
+    /// finishes; file-backed engines override it to snapshot `data` and only
+    /// block writers while catching up with records appended meanwhile.
+    async fn compact_online(&self, data: &RwLock<RedDbHM>) -> Result<()> {
+        let data = data.read().await;
+        self.compact(&data).await
+    }
+
+    /// Size in bytes the store would have right after `compact(data)`, used
+    /// with `file_size` to decide when compaction pays off. 0 (the default)
+    /// means the backend has nothing to reclaim.
+    fn compacted_size(&self, _data: &RedDbHM) -> u64 {
+        0
+    }
+
     /// Size of the backing store in bytes (0 for in-memory backends).
     async fn file_size(&self) -> Result<u64>;
 
     /// Write a batch of raw (op, id, payload) records directly, bypassing
     /// type-aware serialization. Used by `Transaction::commit`.
     async fn persist_raw(&self, records: &[(WalOp, Uuid, Vec<u8>)]) -> Result<()>;
+
+    /// Make every record persisted so far durable, whatever the configured
+    /// [`SyncMode`](crate::SyncMode). No-op for in-memory backends.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Re-encrypt the store under `key` by compacting it online, and keep
+    /// using `key` for new records. If it fails the old key stays in effect.
+    /// The default reports rotation as unsupported.
+    #[cfg(feature = "encryption")]
+    async fn rotate_key(&self, _key: &EncryptionKey, _data: &RwLock<RedDbHM>) -> Result<()> {
+        Err(RedDbError::Unsupported("key rotation"))
+    }
+
+    /// Write a compacted copy of the store to `dest` while writers keep
+    /// running, like `compact_online` does. With `incremental` set and the
+    /// last backup of this handle already at `dest` and unchanged, append
+    /// only the records persisted since then instead. The default reports
+    /// backups as unsupported.
+    async fn backup(
+        &self,
+        _dest: &Path,
+        _data: &RwLock<RedDbHM>,
+        _incremental: bool,
+    ) -> Result<BackupReport> {
+        Err(RedDbError::Unsupported("backup"))
+    }
+
+    /// Replace the store described by `config` with one holding exactly
+    /// `data`, which the caller has read and checked. Fails with
+    /// [`RedDbError::Locked`] while the store is open for writing.
+    #[allow(clippy::extra_unused_type_parameters)]
+    async fn restore(_config: &DbConfig, _data: &RedDbHM) -> Result<()>
+    where
+        Self: Sized + 'static,
+    {
+        Err(RedDbError::Unsupported("restore"))
+    }
+
+    /// Rebuild the store described by `config` from every record that can
+    /// still be read, carrying on past damage that would stop `load`, and
+    /// compact it. Insert and update payloads that `check` rejects are left
+    /// out like damaged records are, and the bytes of everything left out
+    /// are appended to a quarantine file next to the store. Fails with
+    /// [`RedDbError::Locked`] while the store is open for writing. The
+    /// default reports salvage as unsupported.
+    #[allow(clippy::extra_unused_type_parameters)]
+    async fn salvage(_config: &DbConfig, _check: &PayloadCheck<'_>) -> Result<SalvageReport>
+    where
+        Self: Sized + 'static,
+    {
+        Err(RedDbError::Unsupported("salvage"))
+    }
+
+    /// Bytes of a torn trailing record discarded by the last `load`.
+    fn truncated_tail_bytes(&self) -> u64 {
+        0
+    }
+
+    /// Read the payload of document `id` back from the store. A backend
+    /// opened in paged mode (`paged` feature) leaves payloads out of the map
+    /// `load` returns, with an empty entry per document, and `RedDb` calls
+    /// this whenever it needs one. The default reports paged mode as
+    /// unsupported.
+    fn fetch(&self, _id: &Uuid) -> Result<Arc<[u8]>> {
+        Err(RedDbError::Unsupported("paged mode"))
+    }
+
+    /// Read every stored record back and report what is wrong with them,
+    /// carrying on past damage that would stop `load`. Each insert and
+    /// update payload that decodes is handed to `check`. Must not block
+    /// writers for longer than it takes to find the files to read. The
+    /// default reports verification as unsupported.
+    async fn verify(&self, _check: &PayloadCheck<'_>) -> Result<VerifyReport> {
+        Err(RedDbError::Unsupported("verify"))
+    }
+
+    /// Sequence number of the last record persisted, or replayed by `load`
+    /// for a handle that does not write. 0 (the default) for backends that
+    /// do not number their records.
+    async fn last_lsn(&self) -> u64 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_compact_triggers_when_file_exceeds_threshold() {
+        // file_size 200, live_size 50, ratio 2.0 → 200 >= 100 → compact
+        assert!(should_compact(200, 50, 2.0));
+        // file_size 80, live_size 50, ratio 2.0 → 80 < 100 → no compact
+        assert!(!should_compact(80, 50, 2.0));
+        // live_size 0 → never compact (empty db)
+        assert!(!should_compact(32, 0, 2.0));
+    }
 }
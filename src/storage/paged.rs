@@ -0,0 +1,253 @@
+//! Paged mode of [`FileStorage`](super::FileStorage), enabled with
+//! [`DbConfig::paged`](crate::DbConfig::paged).
+//!
+//! The map `load` returns holds an empty placeholder per document, and
+//! [`Paged`] remembers the extent of each document's latest record instead.
+//! Payloads are decoded from a memory mapping of the log when asked for, and
+//! the hottest ones are kept in an LRU cache bounded by their total size.
+
+use lru::LruCache;
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::{Arc, Mutex, RwLock};
+use uuid::Uuid;
+
+use super::format::{decode_frame, Extent, Framing, HEADER_LEN};
+use crate::config::Paging;
+use crate::error::{RedDbError, Result};
+use crate::wal::WalOp;
+
+/// Extent of the latest record of every live document.
+pub(super) type PageIndex = HashMap<Uuid, Extent>;
+
+/// Replay a record found at `extent` onto `index`.
+pub(super) fn place(index: &mut PageIndex, op: WalOp, id: Uuid, extent: Extent) {
+    if op == WalOp::Delete {
+        index.remove(&id);
+    } else {
+        index.insert(id, extent);
+    }
+}
+
+/// The log file, mapped, and where the live records sit in it.
+struct Pages {
+    index: PageIndex,
+    file: File,
+    framing: Framing,
+    /// Mapped on first use and again whenever a record lies past its end.
+    map: Option<Mmap>,
+}
+
+impl Pages {
+    /// The frame at `extent`, or `None` if it lies past the mapping.
+    fn frame(&self, extent: Extent) -> Option<&[u8]> {
+        let map = self.map.as_ref()?;
+        map.get(extent.offset as usize..extent.end() as usize)
+    }
+
+    fn remap(&mut self) -> Result<()> {
+        // SAFETY: the log is only ever appended to. Compaction replaces it
+        // with a rename, leaving the mapped file intact, and the only
+        // truncation, of a torn tail on open, cuts bytes no extent covers.
+        self.map = Some(unsafe { Mmap::map(&self.file)? });
+        Ok(())
+    }
+}
+
+/// Recently read payloads, evicted least recently used first once they
+/// add up to more than `capacity` bytes.
+struct Cache {
+    entries: LruCache<Uuid, Arc<[u8]>>,
+    bytes: usize,
+    capacity: usize,
+}
+
+impl Cache {
+    fn insert(&mut self, id: Uuid, payload: Arc<[u8]>) {
+        if payload.len() > self.capacity {
+            return;
+        }
+        self.bytes += payload.len();
+        if let Some(old) = self.entries.put(id, payload) {
+            self.bytes -= old.len();
+        }
+        while self.bytes > self.capacity {
+            let Some((_, evicted)) = self.entries.pop_lru() else {
+                break;
+            };
+            self.bytes -= evicted.len();
+        }
+    }
+
+    fn remove(&mut self, id: &Uuid) {
+        if let Some(old) = self.entries.pop(id) {
+            self.bytes -= old.len();
+        }
+    }
+}
+
+/// Record extents and cached payloads of a paged [`FileStorage`](super::FileStorage).
+pub(super) struct Paged {
+    pages: RwLock<Pages>,
+    cache: Mutex<Cache>,
+}
+
+impl std::fmt::Debug for Paged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cache = self.cache.lock().unwrap();
+        f.debug_struct("Paged")
+            .field("documents", &self.pages.read().unwrap().index.len())
+            .field("cached_bytes", &cache.bytes)
+            .field("cache_capacity", &cache.capacity)
+            .finish()
+    }
+}
+
+impl Paged {
+    /// Start with nothing indexed in `file`, which is framed with `framing`.
+    pub(super) fn new(paging: Paging, file: File, framing: Framing) -> Self {
+        Paged {
+            pages: RwLock::new(Pages {
+                index: HashMap::new(),
+                file,
+                framing,
+                map: None,
+            }),
+            cache: Mutex::new(Cache {
+                entries: LruCache::unbounded(),
+                bytes: 0,
+                capacity: paging.cache_bytes,
+            }),
+        }
+    }
+
+    /// Switch to `index` into `file`, after `load` or compaction. Cached
+    /// payloads stay: they belong to the same documents either way.
+    pub(super) fn reset(&self, index: PageIndex, file: File, framing: Framing) {
+        *self.pages.write().unwrap() = Pages {
+            index,
+            file,
+            framing,
+            map: None,
+        };
+    }
+
+    /// Take in records just appended to the log, in order.
+    pub(super) fn apply(&self, written: &[(WalOp, Uuid, Extent)]) {
+        let mut pages = self.pages.write().unwrap();
+        let mut cache = self.cache.lock().unwrap();
+        for (op, id, extent) in written {
+            place(&mut pages.index, *op, *id, *extent);
+            cache.remove(id);
+        }
+    }
+
+    /// The payload of `id`, from the cache or else from the log.
+    pub(super) fn fetch(&self, id: &Uuid) -> Result<Arc<[u8]>> {
+        if let Some(payload) = self.cache.lock().unwrap().entries.get(id) {
+            return Ok(Arc::clone(payload));
+        }
+        // Cached while the extent is current, so a record appended
+        // meanwhile cannot be shadowed by the payload it replaces.
+        self.with_frame(id, |frame, extent, framing| {
+            let payload: Arc<[u8]> = decode(id, frame, extent, framing)?.into();
+            self.cache.lock().unwrap().insert(*id, Arc::clone(&payload));
+            Ok(payload)
+        })?
+        .ok_or(RedDbError::NotFound(*id))
+    }
+
+    /// The payload of `id` read from the log without touching the cache, or
+    /// `None` if the document has no live record.
+    pub(super) fn read(&self, id: &Uuid) -> Result<Option<Vec<u8>>> {
+        self.with_frame(id, |frame, extent, framing| {
+            decode(id, frame, extent, framing)
+        })
+    }
+
+    /// Size of the log once compacted: its header and the live records, in
+    /// the framing they have now.
+    pub(super) fn compacted_size(&self) -> u64 {
+        let pages = self.pages.read().unwrap();
+        HEADER_LEN + pages.index.values().map(|e| e.len).sum::<u64>()
+    }
+
+    /// Run `read` on the frame of the live record of `id`, under the read
+    /// lock. `None` if there is no such record.
+    fn with_frame<R>(
+        &self,
+        id: &Uuid,
+        read: impl Fn(&[u8], Extent, &Framing) -> Result<R>,
+    ) -> Result<Option<R>> {
+        loop {
+            {
+                let pages = self.pages.read().unwrap();
+                let Some(&extent) = pages.index.get(id) else {
+                    return Ok(None);
+                };
+                if let Some(frame) = pages.frame(extent) {
+                    return read(frame, extent, &pages.framing).map(Some);
+                }
+            }
+            // The record was appended after the file was last mapped.
+            let mut pages = self.pages.write().unwrap();
+            let Some(&extent) = pages.index.get(id) else {
+                return Ok(None);
+            };
+            if pages.frame(extent).is_none() {
+                pages.remap()?;
+                if pages.frame(extent).is_none() {
+                    return Err(RedDbError::CorruptRecord(extent.offset));
+                }
+            }
+        }
+    }
+}
+
+fn decode(id: &Uuid, frame: &[u8], extent: Extent, framing: &Framing) -> Result<Vec<u8>> {
+    decode_frame(frame, framing)
+        .filter(|record| record.id == *id)
+        .map(|record| record.payload)
+        .ok_or(RedDbError::CorruptRecord(extent.offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(capacity: usize) -> Cache {
+        Cache {
+            entries: LruCache::unbounded(),
+            bytes: 0,
+            capacity,
+        }
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used_past_capacity() {
+        let mut cache = cache(10);
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        cache.insert(a, vec![0; 4].into());
+        cache.insert(b, vec![0; 4].into());
+        cache.entries.get(&a);
+        cache.insert(c, vec![0; 4].into());
+        assert!(cache.entries.contains(&a));
+        assert!(!cache.entries.contains(&b));
+        assert!(cache.entries.contains(&c));
+        assert_eq!(cache.bytes, 8);
+    }
+
+    #[test]
+    fn cache_skips_payloads_larger_than_capacity() {
+        let mut cache = cache(10);
+        let id = Uuid::new_v4();
+        cache.insert(id, vec![0; 11].into());
+        assert!(cache.entries.is_empty());
+        cache.insert(id, vec![0; 6].into());
+        cache.insert(id, vec![0; 2].into());
+        assert_eq!(cache.bytes, 2);
+        cache.remove(&id);
+        assert_eq!(cache.bytes, 0);
+    }
+}
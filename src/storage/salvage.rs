@@ -0,0 +1,165 @@
+//! Salvage of damaged file-backed stores: replay every record that can be
+//! read, past any damage, and set the rest aside in a quarantine file.
+
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+
+use super::format::{check, Framing, Replay, Stamp, HEADER_LEN};
+use super::verify::{read_file_header, PayloadCheck, Problem, ProblemKind};
+use crate::error::Result;
+use crate::serializer::FormatId;
+use crate::wal::WalOp;
+use crate::RedDbHM;
+
+/// The outcome of salvaging a database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SalvageReport {
+    /// Intact records replayed into the salvaged database.
+    pub records: u64,
+    /// Documents in the salvaged database.
+    pub documents: u64,
+    /// Every record left out, in log order. Updates of documents whose
+    /// insert was lost are kept as inserts, and deletes of them ignored, so
+    /// [`ProblemKind::UnknownDocument`] does not appear here.
+    pub quarantined: Vec<Problem>,
+    /// The file the bytes of every quarantined record were appended to, in
+    /// the order of [`quarantined`](Self::quarantined); `None` if there were
+    /// none.
+    pub quarantine_file: Option<PathBuf>,
+}
+
+impl SalvageReport {
+    /// Whether every record was recovered.
+    pub fn is_clean(&self) -> bool {
+        self.quarantined.is_empty()
+    }
+}
+
+/// Salvages the files of a store one after the other, in log order.
+pub(super) struct Salvager<'a> {
+    check: &'a PayloadCheck<'a>,
+    configured: &'a Framing,
+    /// The store, whose quarantine file is `<store>.quarantine`.
+    store: &'a Path,
+    data: Replay,
+    /// Stamp of the last record replayed.
+    last: Stamp,
+    /// The quarantine file, opened once there is something to put in it.
+    quarantine: Option<File>,
+    report: SalvageReport,
+}
+
+impl<'a> Salvager<'a> {
+    pub(super) fn new(
+        store: &'a Path,
+        configured: &'a Framing,
+        check: &'a PayloadCheck<'a>,
+    ) -> Self {
+        Salvager {
+            check,
+            configured,
+            store,
+            data: Replay::default(),
+            last: Stamp::default(),
+            quarantine: None,
+            report: SalvageReport::default(),
+        }
+    }
+
+    /// Replay what can be read of `file`, found at `path`, up to byte offset
+    /// `end`. A header that is unreadable or not in `format` fails the
+    /// salvage: without it nothing after it can be trusted.
+    pub(super) async fn file(
+        &mut self,
+        path: &Path,
+        mut file: File,
+        format: FormatId,
+        end: u64,
+    ) -> Result<()> {
+        let (framing, base) = read_file_header(&mut file, Some(format), self.configured).await?;
+        if self.last.lsn < base {
+            self.last = Stamp {
+                lsn: base,
+                timestamp: self.last.timestamp,
+            };
+        }
+        let first = self.report.quarantined.len();
+        check(&mut file, HEADER_LEN, end, &framing, |found| {
+            let quarantined = match found {
+                Ok((record, extent)) => {
+                    let rejected = match record.op {
+                        WalOp::Delete => None,
+                        _ => (self.check)(&record.payload).err(),
+                    };
+                    match rejected {
+                        Some(reason) => Problem {
+                            file: path.to_path_buf(),
+                            offset: extent.offset,
+                            len: extent.len,
+                            kind: ProblemKind::Undeserializable(reason),
+                            id: Some(record.id),
+                        },
+                        None => {
+                            self.report.records += 1;
+                            self.last = self.last.max(record.stamp);
+                            self.data.apply(record);
+                            return;
+                        }
+                    }
+                }
+                Err(damage) => Problem {
+                    file: path.to_path_buf(),
+                    offset: damage.extent.offset,
+                    len: damage.extent.len,
+                    kind: damage.kind,
+                    id: damage.id,
+                },
+            };
+            self.report.quarantined.push(quarantined);
+        })
+        .await?;
+        if self.report.quarantined.len() == first {
+            return Ok(());
+        }
+        // The walk only keeps the bytes it has not got past, so those of the
+        // quarantined records are copied over from the file.
+        let quarantine = match &mut self.quarantine {
+            Some(quarantine) => quarantine,
+            None => {
+                let mut path = self.store.as_os_str().to_owned();
+                path.push(".quarantine");
+                let path = PathBuf::from(path);
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .await?;
+                self.report.quarantine_file = Some(path);
+                self.quarantine.insert(file)
+            }
+        };
+        for problem in &self.report.quarantined[first..] {
+            file.seek(SeekFrom::Start(problem.offset)).await?;
+            let copied = tokio::io::copy(&mut (&mut file).take(problem.len), quarantine).await?;
+            if copied < problem.len {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Sync the quarantine file, if anything was quarantined, and return the
+    /// recovered documents, the stamp of the last record they reflect and
+    /// the report.
+    pub(super) async fn finish(mut self) -> Result<(RedDbHM, Stamp, SalvageReport)> {
+        if let Some(quarantine) = &mut self.quarantine {
+            quarantine.flush().await?;
+            quarantine.sync_all().await?;
+        }
+        let data = self.data.finish();
+        self.report.documents = data.len() as u64;
+        Ok((data, self.last, self.report))
+    }
+}
@@ -0,0 +1,901 @@
+use async_trait::async_trait;
+use core::fmt::Debug;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::backup::{self, BackupCursor, BackupReport};
+use super::file::{compacted_size, open_append, sync_dir};
+use super::format::{
+    build_header, copy_payload, encode_record_into, last_stamp, read_header, read_records_range,
+    replay_records, write_record, Cutoff, Framing, Record, Replay, Stamp, HEADER_LEN, KEPT_FRAMES,
+};
+use super::group_commit::GroupCommit;
+use super::lock::{LockFile, SwapLock};
+use super::salvage::Salvager;
+use super::verify::Verifier;
+use super::{should_compact, PayloadCheck, SalvageReport, Storage, VerifyReport};
+use crate::config::{AsOf, DbConfig};
+use crate::document::Document;
+use crate::error::{RedDbError, Result};
+use crate::serializer::{FormatId, Serializer};
+use crate::wal::WalOp;
+use crate::RedDbHM;
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, RwLock};
+use uuid::Uuid;
+
+pub(super) const SNAPSHOT_PREFIX: &str = "snapshot-";
+pub(super) const SEGMENT_PREFIX: &str = "wal-";
+
+/// Directory-based storage: an immutable snapshot plus rolling WAL segments.
+///
+/// The database lives in a directory named after [`DbConfig::file_stem`]:
+///
+/// ```text
+/// <stem>/snapshot-<seq><ext>   one Insert per live document, covers segments < seq
+/// <stem>/wal-<seq><ext>        appended records, numbered in write order
+/// <stem>.lock                  held by the writer while the database is open
+/// <stem>.swap.lock             orders checkpoints against read-only loads
+/// ```
+///
+/// Writes append to the highest-numbered segment, which is closed once it
+/// reaches [`DbConfig::segment_size`]. `compact` is a checkpoint: it starts a
+/// new segment, writes a snapshot of the live data that covers every older
+/// segment, then deletes them. Opening loads the latest snapshot and replays
+/// only the segments written after it.
+///
+/// With [`DbConfig::read_only`] set, opening takes no lock on the database,
+/// so it works alongside a writer. The handle loads the files under the swap
+/// lock, which the writer holds while a checkpoint replaces files, and has no
+/// active segment; writes fail with [`RedDbError::ReadOnly`].
+#[derive(Debug)]
+pub struct SegmentedStorage<SE> {
+    dir: PathBuf,
+    /// `None` when opened read-only.
+    _lock: Option<LockFile>,
+    swap: SwapLock,
+    extension: &'static str,
+    compaction_ratio: f64,
+    segment_size: u64,
+    /// Framing of files written from now on.
+    framing: Framing,
+    serializer: SE,
+    /// `None` when opened read-only.
+    active: Mutex<Option<Segment>>,
+    commit: GroupCommit,
+    /// Where the last backup ended, as (segment, offset). Also serializes
+    /// backups.
+    backup: Mutex<Option<BackupCursor<(u64, u64)>>>,
+    /// Where `load` stops replaying, from [`DbConfig::as_of`].
+    as_of: Option<AsOf>,
+    /// Sequence number a reader's `load` ended at.
+    loaded_lsn: AtomicU64,
+    truncated_tail: AtomicU64,
+}
+
+/// The segment currently receiving appends.
+#[derive(Debug)]
+struct Segment {
+    seq: u64,
+    file: File,
+    len: u64,
+    framing: Framing,
+    /// Records appended since the storage was opened, across all segments.
+    written: u64,
+    /// Stamp of the last record written, which the next one follows.
+    last: Stamp,
+    /// Frames of the batch being appended, kept for the next batch.
+    frames: Vec<u8>,
+}
+
+/// Sequence numbers of the snapshots and segments found in the directory.
+#[derive(Debug, Default)]
+pub(super) struct Listing {
+    pub(super) snapshots: Vec<u64>,
+    pub(super) segments: Vec<u64>,
+}
+
+impl Listing {
+    fn latest_snapshot(&self) -> Option<u64> {
+        self.snapshots.iter().copied().max()
+    }
+}
+
+fn parse_seq(name: &str, prefix: &str, extension: &str) -> Option<u64> {
+    name.strip_prefix(prefix)?
+        .strip_suffix(extension)?
+        .parse()
+        .ok()
+}
+
+pub(super) async fn list_dir(dir: &Path, extension: &str) -> Result<Listing> {
+    let mut listing = Listing::default();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else { continue };
+        if let Some(seq) = parse_seq(name, SNAPSHOT_PREFIX, extension) {
+            listing.snapshots.push(seq);
+        } else if let Some(seq) = parse_seq(name, SEGMENT_PREFIX, extension) {
+            listing.segments.push(seq);
+        }
+    }
+    listing.snapshots.sort_unstable();
+    listing.segments.sort_unstable();
+    Ok(listing)
+}
+
+/// Remove snapshots left half-written by an interrupted checkpoint.
+async fn remove_tmp_files(dir: &Path) -> Result<()> {
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.path().extension().is_some_and(|ext| ext == "tmp") {
+            fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn file_path(dir: &Path, prefix: &str, seq: u64, extension: &str) -> PathBuf {
+    dir.join(format!("{prefix}{seq:020}{extension}"))
+}
+
+/// Delete the files snapshot `snapshot_seq` covers. The caller holds the
+/// swap lock.
+async fn remove_covered(dir: &Path, extension: &str, snapshot_seq: u64) -> Result<()> {
+    let listing = list_dir(dir, extension).await?;
+    for seq in listing.segments.into_iter().filter(|&s| s < snapshot_seq) {
+        fs::remove_file(file_path(dir, SEGMENT_PREFIX, seq, extension)).await?;
+    }
+    for seq in listing.snapshots.into_iter().filter(|&s| s < snapshot_seq) {
+        fs::remove_file(file_path(dir, SNAPSHOT_PREFIX, seq, extension)).await?;
+    }
+    Ok(())
+}
+
+/// Open segment `seq` for appending, writing the header if it is new. A new
+/// segment continues after `last`.
+async fn open_segment(
+    dir: &Path,
+    seq: u64,
+    format: FormatId,
+    extension: &str,
+    configured: &Framing,
+    last: Stamp,
+) -> Result<Segment> {
+    let path = file_path(dir, SEGMENT_PREFIX, seq, extension);
+    let mut file = open_append(&path.to_string_lossy()).await?;
+    let len = file.metadata().await?.len();
+    let (framing, last) = if len == 0 {
+        file.write_all(&build_header(format, configured, last.lsn))
+            .await?;
+        file.sync_all().await?;
+        sync_dir(&path).await?;
+        (configured.clone(), last)
+    } else {
+        let (framing, base) = read_header(&mut file, format, configured).await?;
+        (framing, last_stamp(base, &[]))
+    };
+    Ok(Segment {
+        seq,
+        file,
+        len: len.max(HEADER_LEN),
+        framing,
+        written: 0,
+        last,
+        frames: Vec::new(),
+    })
+}
+
+/// The active segment, or [`RedDbError::ReadOnly`] if there is none.
+fn writable(active: &mut Option<Segment>) -> Result<&mut Segment> {
+    active.as_mut().ok_or(RedDbError::ReadOnly)
+}
+
+impl<SE> SegmentedStorage<SE>
+where
+    SE: Serializer + Debug,
+{
+    fn snapshot_path(&self, seq: u64) -> PathBuf {
+        file_path(&self.dir, SNAPSHOT_PREFIX, seq, self.extension)
+    }
+
+    fn segment_path(&self, seq: u64) -> PathBuf {
+        file_path(&self.dir, SEGMENT_PREFIX, seq, self.extension)
+    }
+
+    /// Close the active segment and continue in segment `next`. The closed
+    /// segment is synced first: later fsyncs only cover the active one.
+    async fn roll(&self, active: &mut Segment, next: u64) -> Result<()> {
+        active.file.sync_data().await?;
+        let written = active.written;
+        let frames = std::mem::take(&mut active.frames);
+        let format = self.serializer.format_id();
+        *active = open_segment(
+            &self.dir,
+            next,
+            format,
+            self.extension,
+            &self.framing,
+            active.last,
+        )
+        .await?;
+        active.written = written;
+        active.frames = frames;
+        self.commit.mark_synced(written);
+        Ok(())
+    }
+
+    /// Record count and a handle for syncing the active segment.
+    async fn current_file(&self) -> Result<(u64, File)> {
+        let mut active = self.active.lock().await;
+        let active = writable(&mut active)?;
+        Ok((active.written, active.file.try_clone().await?))
+    }
+
+    /// Open a closed file and read its header, returning the framing it was
+    /// written with and its base LSN.
+    async fn open_closed(&self, path: &Path) -> Result<(File, Framing, u64)> {
+        let mut file = File::open(path).await?;
+        let (framing, base) =
+            read_header(&mut file, self.serializer.format_id(), &self.framing).await?;
+        Ok((file, framing, base))
+    }
+
+    /// Hand every record of a closed file, which must not have a torn tail,
+    /// to `visit`.
+    async fn replay_closed(
+        &self,
+        file: &mut File,
+        framing: &Framing,
+        visit: impl FnMut(Record) -> Result<()>,
+    ) -> Result<()> {
+        let len = file.metadata().await?.len();
+        let valid_len = replay_records(file, framing, visit).await?;
+        if valid_len < len {
+            return Err(RedDbError::CorruptRecord(valid_len));
+        }
+        Ok(())
+    }
+
+    /// Replay a closed file onto `map`, returning the framing it was written
+    /// with and the stamp it ends at.
+    async fn read_closed(&self, path: &Path, map: &mut Replay) -> Result<(Framing, Stamp)> {
+        let (mut file, framing, base) = self.open_closed(path).await?;
+        let mut last = last_stamp(base, &[]);
+        self.replay_closed(&mut file, &framing, |record| {
+            last = record.stamp;
+            map.apply(record);
+            Ok(())
+        })
+        .await?;
+        Ok((framing, last))
+    }
+
+    /// Load without modifying anything, under the swap lock. The last segment
+    /// may still be receiving appends from a writer, so a torn record or a
+    /// header not written yet at its end is skipped rather than repaired.
+    ///
+    /// With [`DbConfig::as_of`] set, replay stops at that point.
+    async fn load_read_only(&self) -> Result<RedDbHM> {
+        let _loading = self.swap.shared().await?;
+        let listing = list_dir(&self.dir, self.extension).await?;
+        let base = listing.latest_snapshot().unwrap_or(0);
+        let snapshot = match base {
+            0 => None,
+            _ => Some(self.open_closed(&self.snapshot_path(base)).await?),
+        };
+        let base_lsn = snapshot.as_ref().map_or(0, |(_, _, lsn)| *lsn);
+        let mut cutoff = self
+            .as_of
+            .map(|point| Cutoff::new(base_lsn, point))
+            .transpose()?;
+        let mut last = last_stamp(base_lsn, &[]);
+        let mut map = Replay::default();
+        let mut visit = |record: Record| -> Result<()> {
+            if let Some(cutoff) = cutoff.as_mut() {
+                if !cutoff.admits(record.stamp)? {
+                    return Ok(());
+                }
+            }
+            last = record.stamp;
+            map.apply(record);
+            Ok(())
+        };
+
+        if let Some((mut file, framing, _)) = snapshot {
+            self.replay_closed(&mut file, &framing, &mut visit).await?;
+        }
+        let segments: Vec<u64> = listing
+            .segments
+            .into_iter()
+            .filter(|&s| s >= base)
+            .collect();
+        if let Some((&newest, closed)) = segments.split_last() {
+            for &seq in closed {
+                let (mut file, framing, _) = self.open_closed(&self.segment_path(seq)).await?;
+                self.replay_closed(&mut file, &framing, &mut visit).await?;
+            }
+            let mut file = File::open(self.segment_path(newest)).await?;
+            let file_len = file.metadata().await?.len();
+            if file_len >= HEADER_LEN {
+                let (framing, _) =
+                    read_header(&mut file, self.serializer.format_id(), &self.framing).await?;
+                let valid_len = replay_records(&mut file, &framing, &mut visit).await?;
+                // The writer may have appended more since `file_len` was read.
+                self.truncated_tail
+                    .store(file_len.saturating_sub(valid_len), Ordering::Relaxed);
+            }
+        }
+
+        if let Some(cutoff) = &cutoff {
+            cutoff.finish()?;
+        }
+        self.loaded_lsn.store(last.lsn, Ordering::Relaxed);
+        Ok(map.finish())
+    }
+
+    /// Open the latest snapshot and the segments after it, in log order,
+    /// each with the length to read of it. A checkpoint may delete files
+    /// while they are being opened, and then the listing starts over; once
+    /// open they stay readable.
+    async fn open_all(&self) -> Result<Vec<(PathBuf, File, u64)>> {
+        'listing: loop {
+            let active = self.active.lock().await;
+            let listing = list_dir(&self.dir, self.extension).await?;
+            let base = listing.latest_snapshot().unwrap_or(0);
+            let snapshot = (base > 0).then(|| self.snapshot_path(base));
+            let segments = listing.segments.iter().filter(|&&s| s >= base);
+            let mut files = Vec::new();
+            for path in snapshot
+                .into_iter()
+                .chain(segments.map(|&s| self.segment_path(s)))
+            {
+                let file = match File::open(&path).await {
+                    Ok(file) => file,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue 'listing,
+                    Err(e) => return Err(e.into()),
+                };
+                // Appends to the active segment past its length may be
+                // under way.
+                let end = match active.as_ref() {
+                    Some(active) if self.segment_path(active.seq) == path => active.len,
+                    _ => file.metadata().await?.len(),
+                };
+                // A reader may find the newest segment before its header.
+                if end >= HEADER_LEN {
+                    files.push((path, file, end));
+                }
+            }
+            return Ok(files);
+        }
+    }
+
+    /// Append a batch, then wait for the fsync the sync mode asks for. Each
+    /// record's payload is written by `write_payload` straight into the
+    /// frames of the batch, which go to the segment in one write.
+    async fn append<P>(
+        &self,
+        records: impl Iterator<Item = (WalOp, Uuid, P)>,
+        write_payload: impl Fn(P, &mut Vec<u8>) -> Result<()>,
+    ) -> Result<()> {
+        let written = {
+            let mut active = self.active.lock().await;
+            let active = writable(&mut active)?;
+            active.frames.clear();
+            let mut last = active.last;
+            let mut count = 0;
+            for (op, id, payload) in records {
+                let stamp = last.next();
+                encode_record_into(&mut active.frames, op, id, stamp, &active.framing, |buf| {
+                    write_payload(payload, buf)
+                })?;
+                last = stamp;
+                count += 1;
+            }
+            active.file.write_all(&active.frames).await?;
+            active.len += active.frames.len() as u64;
+            active.frames.clear();
+            active.frames.shrink_to(KEPT_FRAMES);
+            active.last = last;
+            active.written += count;
+            if active.len >= self.segment_size {
+                let next = active.seq + 1;
+                self.roll(active, next).await?;
+            }
+            active.written
+        };
+        if self.commit.due(written) {
+            self.commit.sync_to(written, || self.current_file()).await?;
+        }
+        Ok(())
+    }
+
+    /// Start a fresh segment and return its sequence number along with the
+    /// stamp of the last record before it; the snapshot written under that
+    /// number covers everything before it.
+    async fn start_checkpoint(&self) -> Result<(u64, Stamp)> {
+        let mut active = self.active.lock().await;
+        let active = writable(&mut active)?;
+        let next = active.seq + 1;
+        self.roll(active, next).await?;
+        Ok((next, active.last))
+    }
+
+    async fn write_snapshot(&self, seq: u64, data: &RedDbHM, base: Stamp) -> Result<()> {
+        let final_path = self.snapshot_path(seq);
+        let tmp_path = final_path.with_extension("tmp");
+        {
+            let framing = &self.framing;
+            let mut tmp = File::create(&tmp_path).await?;
+            tmp.write_all(&build_header(
+                self.serializer.format_id(),
+                framing,
+                base.lsn,
+            ))
+            .await?;
+            for (id, payload) in data {
+                write_record(&mut tmp, WalOp::Insert, *id, payload, base, framing).await?;
+            }
+            tmp.sync_all().await?;
+        }
+        let _swapping = self.swap.exclusive().await?;
+        fs::rename(&tmp_path, &final_path).await?;
+        sync_dir(&final_path).await?;
+        remove_covered(&self.dir, self.extension, seq).await
+    }
+
+    /// Append the records logged since the last backup to it, or return
+    /// `None` if that backup went to another file or was changed since, or a
+    /// segment it needs was removed by a checkpoint.
+    async fn continue_backup(
+        &self,
+        dest: &Path,
+        last: &mut Option<BackupCursor<(u64, u64)>>,
+    ) -> Result<Option<BackupReport>> {
+        let Some(cursor) = last.as_mut().filter(|c| c.continues(dest)) else {
+            return Ok(None);
+        };
+        let (first, offset) = cursor.position;
+        let Some((last_seq, end)) = self.active.lock().await.as_ref().map(|a| (a.seq, a.len))
+        else {
+            return Ok(None);
+        };
+        let mut records = Vec::new();
+        for seq in first..=last_seq {
+            let mut file = match File::open(self.segment_path(seq)).await {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let (framing, _) =
+                read_header(&mut file, self.serializer.format_id(), &self.framing).await?;
+            let start = if seq == first { offset } else { HEADER_LEN };
+            // Segments before the active one are closed and complete.
+            let stop = if seq == last_seq {
+                end
+            } else {
+                file.metadata().await?.len()
+            };
+            let scan = read_records_range(&mut file, start, stop, &framing).await?;
+            if scan.valid_len < stop {
+                return Err(RedDbError::CorruptRecord(scan.valid_len));
+            }
+            records.extend(scan.records);
+        }
+        let Some(len) = backup::append(cursor, &records).await? else {
+            return Ok(None);
+        };
+        cursor.len = len;
+        cursor.position = (last_seq, end);
+        Ok(Some(BackupReport {
+            incremental: true,
+            records: records.len() as u64,
+        }))
+    }
+}
+
+#[async_trait]
+impl<SE> Storage for SegmentedStorage<SE>
+where
+    SE: Serializer + Debug + Sync + Send,
+{
+    async fn new(config: &DbConfig) -> Result<Self> {
+        #[cfg(feature = "paged")]
+        if config.paged.is_some() {
+            return Err(RedDbError::Unsupported("paged mode"));
+        }
+        let serializer = SE::for_config(config)?;
+        let dir = config.file_stem();
+        let format = serializer.format_id();
+        let extension = serializer.extension();
+        let framing = Framing::from_config(config);
+        if config.opens_read_only() {
+            // Fails if the database does not exist.
+            fs::metadata(&dir).await?;
+            return Ok(Self {
+                swap: SwapLock::open(&dir)?,
+                dir,
+                _lock: None,
+                extension,
+                compaction_ratio: config.compaction_ratio,
+                segment_size: config.segment_size,
+                framing,
+                serializer,
+                active: Mutex::new(None),
+                commit: GroupCommit::new(config.sync_mode),
+                backup: Mutex::new(None),
+                as_of: config.as_of,
+                loaded_lsn: AtomicU64::new(0),
+                truncated_tail: AtomicU64::new(0),
+            });
+        }
+        fs::create_dir_all(&dir).await?;
+        let lock = LockFile::acquire(&dir)?;
+        remove_tmp_files(&dir).await?;
+        let listing = list_dir(&dir, extension).await?;
+        // A checkpoint always starts the segment its snapshot is numbered after.
+        let active_seq = listing
+            .segments
+            .last()
+            .copied()
+            .max(listing.latest_snapshot())
+            .unwrap_or(1);
+        // The stamp a new active segment starts from is settled by `load`.
+        let active = open_segment(
+            &dir,
+            active_seq,
+            format,
+            extension,
+            &framing,
+            Stamp::default(),
+        )
+        .await?;
+
+        Ok(Self {
+            swap: SwapLock::open(&dir)?,
+            dir,
+            _lock: Some(lock),
+            extension,
+            compaction_ratio: config.compaction_ratio,
+            segment_size: config.segment_size,
+            framing,
+            serializer,
+            active: Mutex::new(Some(active)),
+            commit: GroupCommit::new(config.sync_mode),
+            backup: Mutex::new(None),
+            as_of: None,
+            loaded_lsn: AtomicU64::new(0),
+            truncated_tail: AtomicU64::new(0),
+        })
+    }
+
+    #[allow(clippy::extra_unused_type_parameters)]
+    async fn load<T>(&self) -> Result<RedDbHM>
+    where
+        for<'de> T: Serialize + Deserialize<'de> + Debug + PartialEq + Send + Sync,
+    {
+        let mut guard = self.active.lock().await;
+        let active = match guard.as_mut() {
+            Some(active) => active,
+            None => {
+                drop(guard);
+                return self.load_read_only().await;
+            }
+        };
+        let listing = list_dir(&self.dir, self.extension).await?;
+        let base = listing.latest_snapshot().unwrap_or(0);
+        let mut map = Replay::default();
+        let mut total_size = 0;
+        // Whether some file was written without encryption.
+        let mut plaintext = false;
+        let mut last = Stamp::default();
+
+        if base > 0 {
+            let path = self.snapshot_path(base);
+            total_size += fs::metadata(&path).await?.len();
+            let (framing, end) = self.read_closed(&path, &mut map).await?;
+            plaintext |= !framing.encrypted();
+            last = last.max(end);
+        }
+
+        for seq in listing.segments.iter().copied().filter(|&s| s >= base) {
+            if seq == active.seq {
+                continue;
+            }
+            let path = self.segment_path(seq);
+            total_size += fs::metadata(&path).await?.len();
+            let (framing, end) = self.read_closed(&path, &mut map).await?;
+            plaintext |= !framing.encrypted();
+            last = last.max(end);
+        }
+
+        // Only the active segment can end in a torn record.
+        let framing = active.framing.clone();
+        plaintext |= !framing.encrypted();
+        let file_len = active.file.metadata().await?.len();
+        let mut active_last = active.last;
+        let valid_len = replay_records(&mut active.file, &framing, |record| {
+            active_last = record.stamp;
+            map.apply(record);
+            Ok(())
+        })
+        .await?;
+        if valid_len < file_len {
+            active.file.set_len(valid_len).await?;
+            active.file.sync_all().await?;
+            self.truncated_tail
+                .store(file_len - valid_len, Ordering::Relaxed);
+        }
+        active.len = valid_len;
+        active.last = last.max(active_last);
+        total_size += valid_len;
+        drop(guard);
+        let map = map.finish();
+
+        // Files older than the snapshot are leftovers of an interrupted checkpoint.
+        if base > 0 {
+            let _swapping = self.swap.exclusive().await?;
+            remove_covered(&self.dir, self.extension, base).await?;
+        }
+
+        // Plaintext files left from before a key was configured are encrypted
+        // by checkpointing right away.
+        let encrypt = plaintext && self.framing.encrypted();
+        if encrypt
+            || should_compact(
+                total_size,
+                compacted_size(&map, &self.framing),
+                self.compaction_ratio,
+            )
+        {
+            self.compact(&map).await?;
+        }
+
+        Ok(map)
+    }
+
+    async fn persist<T>(&self, data: &[Document<T>], op: WalOp) -> Result<()>
+    where
+        for<'de> T: Serialize + Deserialize<'de> + Debug + Sync + Clone,
+    {
+        let records = data.iter().map(|doc| (op, doc.id, &doc.data));
+        self.append(records, |value, buf| {
+            if op == WalOp::Delete {
+                return Ok(());
+            }
+            self.serializer
+                .serialize_into(value, buf)
+                .map_err(|e| RedDbError::Serialize(e.to_string()))
+        })
+        .await
+    }
+
+    async fn compact(&self, data: &RedDbHM) -> Result<()> {
+        let (snapshot_seq, base) = self.start_checkpoint().await?;
+        self.write_snapshot(snapshot_seq, data, base).await
+    }
+
+    async fn compact_online(&self, data: &RwLock<RedDbHM>) -> Result<()> {
+        // Rolled under the read lock, so every record in the segments the
+        // snapshot covers is already reflected in the copied map.
+        let ((snapshot_seq, base), snapshot) = {
+            let data = data.read().await;
+            (self.start_checkpoint().await?, data.clone())
+        };
+        self.write_snapshot(snapshot_seq, &snapshot, base).await
+    }
+
+    fn compacted_size(&self, data: &RedDbHM) -> u64 {
+        // The snapshot plus the empty segment the checkpoint starts.
+        compacted_size(data, &self.framing) + HEADER_LEN
+    }
+
+    async fn file_size(&self) -> Result<u64> {
+        let mut total = 0;
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            total += entry.metadata().await?.len();
+        }
+        Ok(total)
+    }
+
+    async fn persist_raw(&self, records: &[(WalOp, Uuid, Vec<u8>)]) -> Result<()> {
+        let records = records
+            .iter()
+            .map(|(op, id, payload)| (*op, *id, payload.as_slice()));
+        self.append(records, copy_payload).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let written = match self.active.lock().await.as_ref() {
+            Some(active) => active.written,
+            None => return Ok(()),
+        };
+        self.commit.sync_to(written, || self.current_file()).await
+    }
+
+    async fn backup(
+        &self,
+        dest: &Path,
+        data: &RwLock<RedDbHM>,
+        incremental: bool,
+    ) -> Result<BackupReport> {
+        let mut last = self.backup.lock().await;
+        if incremental {
+            if let Some(report) = self.continue_backup(dest, &mut last).await? {
+                return Ok(report);
+            }
+        }
+        // Taken together under the read lock, so every record before the
+        // position is already reflected in the copied map.
+        let (snapshot, base, position) = {
+            let data = data.read().await;
+            let active = self.active.lock().await;
+            let base = active.as_ref().map_or(Stamp::default(), |a| a.last);
+            (data.clone(), base, active.as_ref().map(|a| (a.seq, a.len)))
+        };
+        let format = self.serializer.format_id();
+        let len = backup::write_full(dest, format, &self.framing, &snapshot, base, backup::stored)
+            .await?;
+        // A reader has no active segment to continue a backup from.
+        *last = position.map(|position| BackupCursor {
+            dest: dest.to_path_buf(),
+            len,
+            framing: self.framing.clone(),
+            position,
+        });
+        Ok(BackupReport {
+            incremental: false,
+            records: snapshot.len() as u64,
+        })
+    }
+
+    /// Writes the restored data as a snapshot numbered past every file in
+    /// the directory, then removes the files it replaces.
+    async fn restore(config: &DbConfig, data: &RedDbHM) -> Result<()>
+    where
+        Self: 'static,
+    {
+        if config.opens_read_only() {
+            return Err(RedDbError::ReadOnly);
+        }
+        let serializer = SE::for_config(config)?;
+        let format = serializer.format_id();
+        let extension = serializer.extension();
+        let dir = config.file_stem();
+        fs::create_dir_all(&dir).await?;
+        let _lock = LockFile::acquire(&dir)?;
+        remove_tmp_files(&dir).await?;
+        let listing = list_dir(&dir, extension).await?;
+        let seq = listing
+            .segments
+            .last()
+            .copied()
+            .max(listing.latest_snapshot())
+            .unwrap_or(0)
+            + 1;
+        let path = file_path(&dir, SNAPSHOT_PREFIX, seq, extension);
+        let swap = SwapLock::open(&dir)?;
+        let _swapping = swap.exclusive().await?;
+        // The backup's history is not carried over; sequence numbers restart.
+        let framing = Framing::from_config(config);
+        backup::write_full(
+            &path,
+            format,
+            &framing,
+            data,
+            Stamp::default(),
+            backup::stored,
+        )
+        .await?;
+        remove_covered(&dir, extension, seq).await
+    }
+
+    async fn salvage(config: &DbConfig, check: &PayloadCheck<'_>) -> Result<SalvageReport>
+    where
+        Self: 'static,
+    {
+        if config.opens_read_only() {
+            return Err(RedDbError::ReadOnly);
+        }
+        let serializer = SE::for_config(config)?;
+        let format = serializer.format_id();
+        let extension = serializer.extension();
+        let dir = config.file_stem();
+        let _lock = LockFile::acquire(&dir)?;
+        remove_tmp_files(&dir).await?;
+        let listing = list_dir(&dir, extension).await?;
+        let base = listing.latest_snapshot().unwrap_or(0);
+        let snapshot = (base > 0).then(|| file_path(&dir, SNAPSHOT_PREFIX, base, extension));
+        let segments = listing.segments.iter().filter(|&&s| s >= base);
+        let framing = Framing::from_config(config);
+        let mut salvager = Salvager::new(&dir, &framing, check);
+        for path in snapshot
+            .into_iter()
+            .chain(segments.map(|&s| file_path(&dir, SEGMENT_PREFIX, s, extension)))
+        {
+            let file = File::open(&path).await?;
+            let end = file.metadata().await?.len();
+            // A segment cut short before the end of its header holds
+            // nothing, as on load.
+            if end >= HEADER_LEN {
+                salvager.file(&path, file, format, end).await?;
+            }
+        }
+        let (data, last, report) = salvager.finish().await?;
+        let seq = listing
+            .segments
+            .last()
+            .copied()
+            .max(listing.latest_snapshot())
+            .unwrap_or(0)
+            + 1;
+        let path = file_path(&dir, SNAPSHOT_PREFIX, seq, extension);
+        let swap = SwapLock::open(&dir)?;
+        let _swapping = swap.exclusive().await?;
+        backup::write_full(&path, format, &framing, &data, last, backup::stored).await?;
+        remove_covered(&dir, extension, seq).await?;
+        Ok(report)
+    }
+
+    async fn verify(&self, check: &PayloadCheck<'_>) -> Result<VerifyReport> {
+        let mut verifier = Verifier::new(&self.framing, check);
+        let format = self.serializer.format_id();
+        for (path, file, end) in self.open_all().await? {
+            verifier.file(&path, file, Some(format), end).await?;
+        }
+        Ok(verifier.finish())
+    }
+
+    fn truncated_tail_bytes(&self) -> u64 {
+        self.truncated_tail.load(Ordering::Relaxed)
+    }
+
+    async fn last_lsn(&self) -> u64 {
+        match self.active.lock().await.as_ref() {
+            Some(active) => active.last.lsn,
+            None => self.loaded_lsn.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_seq_reads_numbered_file_names() {
+        assert_eq!(
+            parse_seq("wal-00000000000000000042.ron", SEGMENT_PREFIX, ".ron"),
+            Some(42)
+        );
+        assert_eq!(
+            parse_seq("snapshot-00000000000000000007.ron", SNAPSHOT_PREFIX, ".ron"),
+            Some(7)
+        );
+    }
+
+    #[test]
+    fn parse_seq_rejects_other_files() {
+        assert_eq!(
+            parse_seq("wal-00000000000000000042.json", SEGMENT_PREFIX, ".ron"),
+            None
+        );
+        assert_eq!(
+            parse_seq("snapshot-00000000000000000007.tmp", SNAPSHOT_PREFIX, ".ron"),
+            None
+        );
+        assert_eq!(parse_seq("wal-abc.ron", SEGMENT_PREFIX, ".ron"), None);
+    }
+
+    #[test]
+    fn file_names_sort_in_sequence_order() {
+        let dir = Path::new("db");
+        let low = file_path(dir, SEGMENT_PREFIX, 9, ".ron");
+        let high = file_path(dir, SEGMENT_PREFIX, 10, ".ron");
+        assert!(low < high);
+    }
+}
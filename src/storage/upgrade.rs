@@ -0,0 +1,334 @@
+//! Rewriting version 2 files in the current format, for
+//! [`migrate::upgrade_v2`](crate::migrate::upgrade_v2).
+//!
+//! Version 2 records carry no sequence number or timestamp. Upgraded records
+//! are numbered from 1 in replay order and get timestamp 0, since when they
+//! were written is unknown.
+
+use std::path::Path;
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+
+use super::file::{db_path, sync_dir};
+use super::format::{
+    build_header, copy_payload, encode_record_into, last_stamp, read_header, read_v2_header,
+    replay_records, replay_v2_records, Extent, Framing, Record, Stamp, Visitor, KEPT_FRAMES,
+};
+use super::lock::LockFile;
+use super::segmented::{file_path, list_dir, SEGMENT_PREFIX, SNAPSHOT_PREFIX};
+use crate::config::DbConfig;
+use crate::error::Result;
+use crate::serializer::FormatId;
+
+/// Upgrade the database `config` describes, whichever engine wrote it, and
+/// return the number of records rewritten.
+pub(crate) async fn upgrade_v2(
+    config: &DbConfig,
+    format: FormatId,
+    extension: &str,
+) -> Result<u64> {
+    let configured = Framing::from_config(config);
+    let file = db_path(config, extension);
+    let dir = config.file_stem();
+    if fs::metadata(&dir).await.is_ok_and(|m| m.is_dir()) {
+        let _lock = LockFile::acquire(&dir)?;
+        upgrade_dir(&dir, format, extension, &configured).await
+    } else {
+        let path = Path::new(&file);
+        let _lock = LockFile::acquire(path)?;
+        let mut last = Stamp::default();
+        upgrade_file(path, format, &configured, &mut last, false).await
+    }
+}
+
+/// Upgrade the snapshots and segments of a segmented database in replay
+/// order, so sequence numbers run on from one file to the next.
+async fn upgrade_dir(
+    dir: &Path,
+    format: FormatId,
+    extension: &str,
+    configured: &Framing,
+) -> Result<u64> {
+    let listing = list_dir(dir, extension).await?;
+    // Snapshot `n` follows segment `n - 1` and precedes segment `n`.
+    let mut files: Vec<(u64, bool)> = listing
+        .snapshots
+        .iter()
+        .map(|&seq| (seq, true))
+        .chain(listing.segments.iter().map(|&seq| (seq, false)))
+        .collect();
+    files.sort_by_key(|&(seq, snapshot)| (seq, !snapshot));
+
+    let mut last = Stamp::default();
+    let mut upgraded = 0;
+    for (seq, snapshot) in files {
+        let prefix = if snapshot {
+            SNAPSHOT_PREFIX
+        } else {
+            SEGMENT_PREFIX
+        };
+        let path = file_path(dir, prefix, seq, extension);
+        upgraded += upgrade_file(&path, format, configured, &mut last, snapshot).await?;
+    }
+    Ok(upgraded)
+}
+
+/// Rewrite one file whose records follow `last`, and advance `last` past
+/// them. The records of a snapshot all share the stamp before them. A file
+/// already in the current format is left alone.
+async fn upgrade_file(
+    path: &Path,
+    format: FormatId,
+    configured: &Framing,
+    last: &mut Stamp,
+    snapshot: bool,
+) -> Result<u64> {
+    let mut file = File::open(path).await?;
+    let Some(framing) = read_v2_header(&mut file, format, configured).await? else {
+        let (framing, base) = read_header(&mut file, format, configured).await?;
+        let mut file_last = last_stamp(base, &[]);
+        replay_records(&mut file, &framing, |record| {
+            file_last = record.stamp;
+            Ok(())
+        })
+        .await?;
+        *last = (*last).max(file_last);
+        return Ok(0);
+    };
+
+    // Checksums were optional in version 2; upgraded files always have them.
+    let upgraded = Framing {
+        checksummed: true,
+        ..framing.clone()
+    };
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let mut tmp = File::create(&tmp_name).await?;
+    tmp.write_all(&build_header(format, &upgraded, last.lsn))
+        .await?;
+    let mut rewrite = Rewrite {
+        tmp,
+        framing: upgraded,
+        frames: Vec::new(),
+        base: *last,
+        last,
+        snapshot,
+        records: 0,
+    };
+    replay_v2_records(&mut file, &framing, &mut rewrite).await?;
+    drop(file);
+    let Rewrite { tmp, records, .. } = rewrite;
+    tmp.sync_all().await?;
+    drop(tmp);
+    fs::rename(&tmp_name, path).await?;
+    sync_dir(path).await?;
+    Ok(records)
+}
+
+/// Writes the records of a version 2 file to its upgraded copy a batch at a
+/// time, as they are read.
+struct Rewrite<'a> {
+    tmp: File,
+    framing: Framing,
+    /// Frames of the batch being read.
+    frames: Vec<u8>,
+    /// Stamp before the file's records.
+    base: Stamp,
+    /// Stamp of the last record written.
+    last: &'a mut Stamp,
+    snapshot: bool,
+    /// Records written so far.
+    records: u64,
+}
+
+impl Visitor for &mut Rewrite<'_> {
+    fn visit(&mut self, record: Record, _: Extent) -> Result<()> {
+        let Record {
+            op, id, payload, ..
+        } = record;
+        let stamp = if self.snapshot {
+            self.base
+        } else {
+            Stamp {
+                lsn: self.last.lsn + 1,
+                timestamp: 0,
+            }
+        };
+        encode_record_into(&mut self.frames, op, id, stamp, &self.framing, |buf| {
+            copy_payload(&payload, buf)
+        })?;
+        *self.last = stamp;
+        self.records += 1;
+        Ok(())
+    }
+
+    async fn batch_done(&mut self) -> Result<()> {
+        self.tmp.write_all(&self.frames).await?;
+        self.frames.clear();
+        self.frames.shrink_to(KEPT_FRAMES);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::format::read_records;
+    use super::*;
+    use crate::wal::WalOp;
+    use uuid::Uuid;
+
+    /// A version 2 file: the current header with version 2 and records
+    /// without stamps.
+    fn v2_file(records: &[(WalOp, Uuid, &[u8])]) -> Vec<u8> {
+        let framing = Framing::from_config(&DbConfig::default());
+        let mut buf = build_header(FormatId::Json, &framing, 0).to_vec();
+        buf[8..10].copy_from_slice(&2u16.to_le_bytes());
+        for (op, id, payload) in records {
+            let mut frame = Vec::new();
+            frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            frame.push(match op {
+                WalOp::Insert => 1,
+                WalOp::Update => 2,
+                WalOp::Delete => 3,
+            });
+            frame.extend_from_slice(id.as_bytes());
+            frame.extend_from_slice(payload);
+            let crc = crc32c::crc32c(&frame);
+            frame.extend_from_slice(&crc.to_le_bytes());
+            buf.extend_from_slice(&frame);
+        }
+        buf
+    }
+
+    async fn read(path: &Path) -> (u64, Vec<Record>) {
+        let mut file = File::open(path).await.unwrap();
+        let configured = Framing::from_config(&DbConfig::default());
+        let (framing, base) = read_header(&mut file, FormatId::Json, &configured)
+            .await
+            .unwrap();
+        (
+            base,
+            read_records(&mut file, &framing).await.unwrap().records,
+        )
+    }
+
+    #[tokio::test]
+    async fn file_records_are_numbered_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(dir.path());
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let path = dir.path().join("db.json");
+        std::fs::write(
+            &path,
+            v2_file(&[
+                (WalOp::Insert, a, b"1"),
+                (WalOp::Insert, b, b"2"),
+                (WalOp::Delete, a, b""),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            upgrade_v2(&config, FormatId::Json, ".json").await.unwrap(),
+            3
+        );
+        let (base, records) = read(&path).await;
+        assert_eq!(base, 0);
+        let stamps: Vec<(u64, u64)> = records
+            .iter()
+            .map(|r| (r.stamp.lsn, r.stamp.timestamp))
+            .collect();
+        assert_eq!(stamps, vec![(1, 0), (2, 0), (3, 0)]);
+        assert_eq!(records[1].id, b);
+        assert_eq!(records[1].payload, b"2");
+
+        // Already upgraded.
+        assert_eq!(
+            upgrade_v2(&config, FormatId::Json, ".json").await.unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn segments_continue_from_the_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(dir.path());
+        let db = dir.path().join("db");
+        std::fs::create_dir(&db).unwrap();
+        let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        let write = |prefix, seq, records: &[(WalOp, Uuid, &[u8])]| {
+            std::fs::write(file_path(&db, prefix, seq, ".json"), v2_file(records)).unwrap();
+        };
+        write(
+            SNAPSHOT_PREFIX,
+            2,
+            &[(WalOp::Insert, ids[0], b"a"), (WalOp::Insert, ids[1], b"b")],
+        );
+        write(SEGMENT_PREFIX, 2, &[(WalOp::Insert, ids[2], b"c")]);
+        write(SEGMENT_PREFIX, 3, &[(WalOp::Update, ids[3], b"d")]);
+
+        assert_eq!(
+            upgrade_v2(&config, FormatId::Json, ".json").await.unwrap(),
+            4
+        );
+        let (base, snapshot) = read(&file_path(&db, SNAPSHOT_PREFIX, 2, ".json")).await;
+        assert_eq!(base, 0);
+        assert!(snapshot.iter().all(|r| r.stamp.lsn == 0));
+        let (base, segment) = read(&file_path(&db, SEGMENT_PREFIX, 2, ".json")).await;
+        assert_eq!((base, segment[0].stamp.lsn), (0, 1));
+        let (base, segment) = read(&file_path(&db, SEGMENT_PREFIX, 3, ".json")).await;
+        assert_eq!((base, segment[0].stamp.lsn), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn files_larger_than_a_batch_are_upgraded_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(dir.path());
+        let path = dir.path().join("db.json");
+        let payloads: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i; 1_500_000]).collect();
+        let records: Vec<(WalOp, Uuid, &[u8])> = payloads
+            .iter()
+            .map(|p| (WalOp::Insert, Uuid::new_v4(), p.as_slice()))
+            .collect();
+        std::fs::write(&path, v2_file(&records)).unwrap();
+
+        assert_eq!(
+            upgrade_v2(&config, FormatId::Json, ".json").await.unwrap(),
+            5
+        );
+        let (_, upgraded) = read(&path).await;
+        assert_eq!(upgraded.len(), 5);
+        for (i, (record, (_, id, payload))) in upgraded.iter().zip(&records).enumerate() {
+            assert_eq!((record.id, record.stamp.lsn), (*id, i as u64 + 1));
+            assert_eq!(record.payload, *payload);
+        }
+    }
+
+    #[tokio::test]
+    async fn segments_continue_from_upgraded_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(dir.path());
+        let db = dir.path().join("db");
+        std::fs::create_dir(&db).unwrap();
+        let write = |seq| {
+            let records = [(WalOp::Insert, Uuid::new_v4(), b"a".as_slice())];
+            std::fs::write(
+                file_path(&db, SEGMENT_PREFIX, seq, ".json"),
+                v2_file(&records),
+            )
+            .unwrap();
+        };
+        write(1);
+        write(2);
+        upgrade_v2(&config, FormatId::Json, ".json").await.unwrap();
+        write(3);
+
+        // The segments already upgraded are only read for their last stamp.
+        assert_eq!(
+            upgrade_v2(&config, FormatId::Json, ".json").await.unwrap(),
+            1
+        );
+        let (base, segment) = read(&file_path(&db, SEGMENT_PREFIX, 3, ".json")).await;
+        assert_eq!((base, segment[0].stamp.lsn), (2, 3));
+    }
+}
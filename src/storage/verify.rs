@@ -0,0 +1,167 @@
+//! Verification of file-backed stores: a scan of every record that, unlike
+//! loading, carries on past damage and reports all of it.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use tokio::fs::File;
+use uuid::Uuid;
+
+use super::format::{check, read_any_header, read_header, Framing, HEADER_LEN};
+use crate::error::Result;
+use crate::serializer::FormatId;
+use crate::wal::WalOp;
+
+/// Checks a payload that decoded from its record, returning why it is
+/// unusable if it is. [`RedDb::verify`](crate::RedDb::verify) checks that it
+/// deserializes as the document type.
+pub type PayloadCheck<'a> = dyn Fn(&[u8]) -> std::result::Result<(), String> + Send + Sync + 'a;
+
+/// What is wrong with a record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// The op byte is not one of insert, update and delete.
+    UnknownOp(u8),
+    /// The record does not match its checksum.
+    ChecksumMismatch,
+    /// The record runs past the end of the file. At the very end of the
+    /// log this is a write cut short by a crash, which opening discards.
+    TruncatedFrame,
+    /// The payload fails to decrypt or decompress.
+    UnreadablePayload,
+    /// The payload does not pass the [`PayloadCheck`], with its reason.
+    Undeserializable(String),
+    /// An update or delete of a document that does not exist at that point.
+    UnknownDocument(WalOp),
+}
+
+/// A problem found by verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// The file the record is in.
+    pub file: PathBuf,
+    /// Byte offset of the record in the file.
+    pub offset: u64,
+    /// Bytes affected, up to the next record that can be read.
+    pub len: u64,
+    pub kind: ProblemKind,
+    /// The document the record is about, unless its id cannot be trusted.
+    pub id: Option<Uuid>,
+}
+
+/// The outcome of verifying a database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Records read intact, including those with a problem of their own.
+    pub records: u64,
+    /// Documents alive after replaying every intact record.
+    pub documents: u64,
+    /// Every problem found, in log order.
+    pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+    /// Whether no problem was found.
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Verifies the files of a store one after the other, in log order.
+pub(super) struct Verifier<'a> {
+    check: &'a PayloadCheck<'a>,
+    /// Framing of files written under the store's config, which supplies
+    /// what headers do not record.
+    configured: &'a Framing,
+    live: HashSet<Uuid>,
+    report: VerifyReport,
+}
+
+impl<'a> Verifier<'a> {
+    pub(super) fn new(configured: &'a Framing, check: &'a PayloadCheck<'a>) -> Self {
+        Verifier {
+            check,
+            configured,
+            live: HashSet::new(),
+            report: VerifyReport::default(),
+        }
+    }
+
+    /// Verify `file`, found at `path`, up to byte offset `end`. A header
+    /// that is unreadable, or not in `format` when given, fails verification
+    /// outright with the error opening the store would give.
+    pub(super) async fn file(
+        &mut self,
+        path: &Path,
+        mut file: File,
+        format: Option<FormatId>,
+        end: u64,
+    ) -> Result<()> {
+        let (framing, _) = read_file_header(&mut file, format, self.configured).await?;
+        check(&mut file, HEADER_LEN, end, &framing, |found| {
+            let (record, extent) = match found {
+                Ok(found) => found,
+                Err(damage) => {
+                    self.report.problems.push(Problem {
+                        file: path.to_path_buf(),
+                        offset: damage.extent.offset,
+                        len: damage.extent.len,
+                        kind: damage.kind,
+                        id: damage.id,
+                    });
+                    return;
+                }
+            };
+            self.report.records += 1;
+            let known = match record.op {
+                WalOp::Insert => {
+                    self.live.insert(record.id);
+                    true
+                }
+                WalOp::Update => !self.live.insert(record.id),
+                WalOp::Delete => self.live.remove(&record.id),
+            };
+            let kind = if !known {
+                Some(ProblemKind::UnknownDocument(record.op))
+            } else if record.op == WalOp::Delete {
+                None
+            } else {
+                (self.check)(&record.payload)
+                    .err()
+                    .map(ProblemKind::Undeserializable)
+            };
+            if let Some(kind) = kind {
+                self.report.problems.push(Problem {
+                    file: path.to_path_buf(),
+                    offset: extent.offset,
+                    len: extent.len,
+                    kind,
+                    id: Some(record.id),
+                });
+            }
+        })
+        .await
+    }
+
+    pub(super) fn finish(mut self) -> VerifyReport {
+        self.report.documents = self.live.len() as u64;
+        self.report
+    }
+}
+
+/// Read the header of `file`, which must be in `format` when given, leaving
+/// the file at the records after it. Returns the file's framing and its base
+/// LSN.
+pub(super) async fn read_file_header(
+    file: &mut File,
+    format: Option<FormatId>,
+    configured: &Framing,
+) -> Result<(Framing, u64)> {
+    match format {
+        Some(format) => read_header(file, format, configured).await,
+        None => {
+            let (_, framing, base) = read_any_header(file, configured).await?;
+            Ok((framing, base))
+        }
+    }
+}
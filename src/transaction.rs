@@ -1,3 +1,5 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::fmt::Debug;
 
 use serde::{Deserialize, Serialize};
@@ -22,7 +24,6 @@ pub struct Transaction<'db, SE, ST> {
     ops: Vec<(WalOp, Uuid, Vec<u8>)>,
 }
 
-#[allow(private_bounds)]
 impl<'db, SE, ST: 'static> Transaction<'db, SE, ST>
 where
     SE: Serializer + Debug,
@@ -65,7 +66,8 @@ where
     }
 
     /// Apply all staged operations atomically: update the in-memory store,
-    /// update any registered indexes, then append to the WAL.
+    /// update any registered indexes, then append to the WAL. In paged mode
+    /// the WAL is appended to first.
     pub async fn commit(self) -> Result<()> {
         if self.ops.is_empty() {
             return Ok(());
@@ -75,19 +77,33 @@ where
         let index_changes = {
             let mut data = self.db.write_lock().await?;
 
+            // Payload of each touched document as the ops go, starting from
+            // the live ones. Paged map entries are placeholders, so those
+            // are read back before the records replacing them are written.
+            let mut current: HashMap<Uuid, Vec<u8>> = HashMap::new();
+            for (_, id, _) in &self.ops {
+                if let (Entry::Vacant(slot), Some(raw)) = (current.entry(*id), data.get(id)) {
+                    slot.insert(self.db.payload(id, raw)?.into_owned());
+                }
+            }
+            if self.db.paged {
+                self.db.storage_persist_raw(&self.ops).await?;
+            }
+
             let mut changes = Vec::with_capacity(self.ops.len());
             for (op, id, new_raw) in &self.ops {
                 match op {
                     WalOp::Insert => {
-                        data.insert(*id, new_raw.clone());
+                        data.insert(*id, self.db.entry(new_raw));
+                        current.insert(*id, new_raw.clone());
                         changes.push(IndexChange::Insert {
                             id: *id,
                             raw: new_raw.clone(),
                         });
                     }
                     WalOp::Update => {
-                        let old_raw = data.get(id).cloned().unwrap_or_default();
-                        data.insert(*id, new_raw.clone());
+                        data.insert(*id, self.db.entry(new_raw));
+                        let old_raw = current.insert(*id, new_raw.clone()).unwrap_or_default();
                         changes.push(IndexChange::Update {
                             id: *id,
                             old_raw,
@@ -95,7 +111,8 @@ where
                         });
                     }
                     WalOp::Delete => {
-                        let old_raw = data.remove(id).unwrap_or_default();
+                        data.remove(id);
+                        let old_raw = current.remove(id).unwrap_or_default();
                         changes.push(IndexChange::Delete {
                             id: *id,
                             raw: old_raw,
@@ -122,7 +139,10 @@ where
             }
         }
 
-        // Persist raw ops to WAL.
+        // Persist raw ops to WAL, unless that came first.
+        if self.db.paged {
+            return Ok(());
+        }
         self.db.storage_persist_raw(&self.ops).await
     }
 
@@ -26,7 +26,6 @@ where
     _marker: PhantomData<T>,
 }
 
-#[allow(private_bounds)]
 impl<'db, T, F, SE, ST> UpdateWhereBuilder<'db, T, F, SE, ST>
 where
     F: Fn(&T) -> bool + Send + Sync + 'static,
@@ -78,11 +77,12 @@ where
                         break;
                     }
                 }
-                let value: T = self.db.deserialize_raw(old_raw)?;
+                let old_raw = self.db.payload(id, old_raw)?;
+                let value: T = self.db.deserialize_raw(&old_raw)?;
                 if (self.predicate)(&value) {
                     let new_value = transform(value);
                     let new_raw = self.db.serialize_raw(&new_value)?;
-                    updates.push((*id, old_raw.clone(), new_raw, new_value));
+                    updates.push((*id, old_raw.into_owned(), new_raw, new_value));
                 }
             }
 
@@ -97,7 +97,7 @@ where
             self.db.storage_persist(&docs, WalOp::Update).await?;
             for (id, _, new_raw, _) in &updates {
                 if let Some(entry) = data.get_mut(id) {
-                    *entry = new_raw.clone();
+                    *entry = self.db.entry(new_raw);
                 }
             }
             drop(data);
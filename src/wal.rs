@@ -1,10 +1,13 @@
 use serde::{Deserialize, Serialize};
 
 /// The operation recorded in a WAL entry.
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
-pub(crate) enum WalOp {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalOp {
+    /// A new document; the payload is its serialized data.
     Insert,
+    /// A replacement for an existing document; the payload is the new data.
     Update,
+    /// A removal; the payload is empty.
     Delete,
 }
 
@@ -0,0 +1,220 @@
+#![cfg(feature = "cli")]
+
+use reddb::serializer::Json;
+use reddb::storage::Storage;
+use reddb::{CborDb, DbConfig, FileStorage, JsonDb, MsgPackDb, Uuid, WalOp, YamlDb};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::{Command, Output};
+
+#[derive(Clone, Debug, Serialize, PartialEq, Deserialize)]
+struct User {
+    name: String,
+    age: u32,
+}
+
+fn reddb(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_reddb"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+/// A JSON database in `dir` with one live document, one updated and
+/// then deleted, and so one dead update.
+async fn users(dir: &Path) -> String {
+    let db = JsonDb::open::<User>(DbConfig::new("users").dir(dir))
+        .await
+        .unwrap();
+    let gone = db
+        .insert_one(User {
+            name: "ann".into(),
+            age: 30,
+        })
+        .await
+        .unwrap()
+        .id;
+    db.insert_one(User {
+        name: "bob".into(),
+        age: 40,
+    })
+    .await
+    .unwrap();
+    db.update_one(
+        &gone,
+        User {
+            name: "ann".into(),
+            age: 31,
+        },
+    )
+    .await
+    .unwrap();
+    db.delete_one::<User>(&gone).await.unwrap();
+    dir.join("users.json").to_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn info_reports_records_and_dead_bytes() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = users(tmp.path()).await;
+    let output = reddb(&["info", &path]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("format:      json (version 3)"));
+    assert!(out.contains("records:     4"));
+    assert!(out.contains("documents:   1"));
+    assert!(!out.contains("dead bytes:  0"));
+}
+
+#[tokio::test]
+async fn dump_prints_a_json_line_per_record() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = users(tmp.path()).await;
+    let output = reddb(&["dump", &path]);
+    assert!(output.status.success());
+    let lines: Vec<serde_json::Value> = stdout(&output)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let ops: Vec<&str> = lines.iter().map(|l| l["op"].as_str().unwrap()).collect();
+    assert_eq!(ops, ["insert", "insert", "update", "delete"]);
+    assert_eq!(lines[2]["payload"]["age"], 31);
+    assert_eq!(lines[2]["lsn"], 3);
+    assert!(lines[3].get("payload").is_none());
+
+    let hex = stdout(&reddb(&["dump", "--hex", &path]));
+    let first: serde_json::Value = serde_json::from_str(hex.lines().next().unwrap()).unwrap();
+    assert!(first["payload_hex"].as_str().unwrap().starts_with("7b")); // '{'
+}
+
+#[tokio::test]
+async fn verify_reports_payloads_that_do_not_decode() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = users(tmp.path()).await;
+    assert!(reddb(&["verify", &path]).status.success());
+
+    let storage = FileStorage::<Json>::new(&DbConfig::new("users").dir(tmp.path()))
+        .await
+        .unwrap();
+    storage.load::<User>().await.unwrap();
+    let bad = Uuid::new_v4();
+    storage
+        .persist_raw(&[(WalOp::Insert, bad, b"{not json".to_vec())])
+        .await
+        .unwrap();
+    drop(storage);
+    let output = reddb(&["verify", &path]);
+    assert!(!output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains(&format!("document {bad}: payload does not decode")));
+    assert!(out.contains("2 documents, 1 problems"));
+}
+
+#[tokio::test]
+async fn compact_drops_dead_records() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = users(tmp.path()).await;
+    assert!(reddb(&["compact", &path]).status.success());
+    let out = stdout(&reddb(&["info", &path]));
+    assert!(out.contains("records:     1"));
+    assert!(out.contains("dead bytes:  0"));
+}
+
+#[tokio::test]
+async fn convert_writes_yaml_and_refuses_bin() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = users(tmp.path()).await;
+    let output = reddb(&["convert", &path, "--to", "yaml"]);
+    assert!(output.status.success());
+    let db = YamlDb::open::<User>(DbConfig::new("users").dir(tmp.path()))
+        .await
+        .unwrap();
+    let all = db.find_all::<User>().await.unwrap();
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0].data.name, "bob");
+
+    let output = reddb(&["convert", &path, "--to", "bin"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("migrate::convert"));
+    assert!(!tmp.path().join("users.bin").exists());
+}
+
+#[tokio::test]
+async fn convert_writes_msgpack_that_dumps_and_verifies() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = users(tmp.path()).await;
+    assert!(reddb(&["convert", &path, "--to", "msgpack"])
+        .status
+        .success());
+    let db = MsgPackDb::open::<User>(DbConfig::new("users").dir(tmp.path()))
+        .await
+        .unwrap();
+    let all = db.find_all::<User>().await.unwrap();
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0].data.name, "bob");
+    drop(db);
+
+    let converted = tmp.path().join("users.msgpack");
+    let converted = converted.to_str().unwrap();
+    assert!(stdout(&reddb(&["info", converted])).contains("format:      msgpack (version 3)"));
+    let dump = stdout(&reddb(&["dump", converted]));
+    let line: serde_json::Value = serde_json::from_str(dump.lines().next().unwrap()).unwrap();
+    assert_eq!(line["payload"]["age"], 40);
+    let output = reddb(&["verify", converted]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("1 records, 1 documents, 0 problems"));
+}
+
+#[tokio::test]
+async fn cbor_converts_back_and_forth_but_postcard_needs_the_type() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = users(tmp.path()).await;
+    assert!(reddb(&["convert", &path, "--to", "cbor"]).status.success());
+    let converted = tmp.path().join("users.cbor");
+    let converted = converted.to_str().unwrap();
+    let dump = stdout(&reddb(&["dump", converted]));
+    let line: serde_json::Value = serde_json::from_str(dump.lines().next().unwrap()).unwrap();
+    assert_eq!(line["payload"]["name"], "bob");
+    assert!(reddb(&["verify", converted]).status.success());
+
+    let output = reddb(&["convert", converted, "--to", "msgpack", "--name", "again"]);
+    assert!(output.status.success());
+    let db = CborDb::open::<User>(DbConfig::new("users").dir(tmp.path()))
+        .await
+        .unwrap();
+    assert_eq!(db.find_all::<User>().await.unwrap()[0].data.age, 40);
+    let db = MsgPackDb::open::<User>(DbConfig::new("again").dir(tmp.path()))
+        .await
+        .unwrap();
+    assert_eq!(db.find_all::<User>().await.unwrap()[0].data.age, 40);
+
+    let output = reddb(&["convert", converted, "--to", "postcard"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("migrate::convert"));
+}
+
+#[tokio::test]
+async fn salvage_quarantines_payloads_that_do_not_decode() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = users(tmp.path()).await;
+    let storage = FileStorage::<Json>::new(&DbConfig::new("users").dir(tmp.path()))
+        .await
+        .unwrap();
+    storage.load::<User>().await.unwrap();
+    storage
+        .persist_raw(&[(WalOp::Insert, Uuid::new_v4(), b"{not json".to_vec())])
+        .await
+        .unwrap();
+    drop(storage);
+    let output = reddb(&["salvage", &path]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("payload does not decode"));
+    assert!(out.contains("1 documents"));
+    assert!(tmp.path().join("users.json.quarantine").exists());
+    assert!(reddb(&["verify", &path]).status.success());
+}
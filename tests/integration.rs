@@ -1,6 +1,7 @@
-use reddb::{DbConfig, Document, MemDb, RonDb, WriteOrder};
+use reddb::{AutoCompaction, DbConfig, Document, MemDb, RedDbError, RonDb, SyncMode, WriteOrder};
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::time::Duration;
 
 #[derive(Clone, Debug, Serialize, PartialEq, Deserialize)]
 struct UserRec {
@@ -16,6 +17,7 @@ struct TestStruct {
 /// Remove the file at `path`, ignoring errors (e.g. does not exist).
 fn cleanup(path: &str) {
     let _ = fs::remove_file(path);
+    let _ = fs::remove_file(format!("{path}.lock"));
 }
 
 // ── insert ────────────────────────────────────────────────────────────────────
@@ -546,6 +548,1677 @@ async fn manual_compact_shrinks_file() {
     cleanup(file);
 }
 
+// ── background compaction ─────────────────────────────────────────────────────
+
+/// Poll `stats` until the background task has compacted at least once.
+async fn wait_for_auto_compaction(db: &RonDb) {
+    for _ in 0..200 {
+        if db.stats().await.unwrap().auto_compactions > 0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("background compaction did not run");
+}
+
+#[tokio::test]
+async fn background_compaction_runs_when_ratio_is_reached() {
+    let tmp = tempfile::tempdir().unwrap();
+    let config = DbConfig::new("db")
+        .dir(tmp.path())
+        .auto_compaction(AutoCompaction::new(Duration::from_millis(10)));
+    let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+    let doc = db.insert_one(TestStruct { foo: "0".into() }).await.unwrap();
+    for i in 1..20 {
+        db.update_one(&doc.id, TestStruct { foo: i.to_string() })
+            .await
+            .unwrap();
+    }
+
+    wait_for_auto_compaction(&db).await;
+    db.close().await.unwrap();
+
+    let db = RonDb::open::<TestStruct>(config).await.unwrap();
+    assert_eq!(
+        db.find_one::<TestStruct>(&doc.id).await.unwrap().data.foo,
+        "19"
+    );
+}
+
+#[tokio::test]
+async fn background_compaction_runs_after_max_interval() {
+    let tmp = tempfile::tempdir().unwrap();
+    let settings =
+        AutoCompaction::new(Duration::from_millis(10)).max_interval(Duration::from_millis(50));
+    let config = DbConfig::new("db")
+        .dir(tmp.path())
+        .compaction_ratio(100.0)
+        .auto_compaction(settings);
+    let db = RonDb::open::<TestStruct>(config).await.unwrap();
+    let doc = db.insert_one(TestStruct { foo: "a".into() }).await.unwrap();
+    db.update_one(&doc.id, TestStruct { foo: "b".into() })
+        .await
+        .unwrap();
+
+    let before = db.stats().await.unwrap().file_size_bytes;
+    wait_for_auto_compaction(&db).await;
+    assert!(db.stats().await.unwrap().file_size_bytes < before);
+    db.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn background_compaction_is_off_by_default() {
+    let tmp = tempfile::tempdir().unwrap();
+    let db = RonDb::open::<TestStruct>(DbConfig::new("db").dir(tmp.path()))
+        .await
+        .unwrap();
+    let doc = db.insert_one(TestStruct { foo: "a".into() }).await.unwrap();
+    for _ in 0..10 {
+        db.update_one(&doc.id, TestStruct { foo: "b".into() })
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(db.stats().await.unwrap().auto_compactions, 0);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn writes_during_compaction_are_kept() {
+    let tmp = tempfile::tempdir().unwrap();
+    let config = DbConfig::new("db").dir(tmp.path()).compaction_ratio(100.0);
+    let db = std::sync::Arc::new(RonDb::open::<TestStruct>(config.clone()).await.unwrap());
+    db.insert(
+        (0..500)
+            .map(|i| TestStruct { foo: i.to_string() })
+            .collect(),
+    )
+    .await
+    .unwrap();
+
+    let writer = {
+        let db = std::sync::Arc::clone(&db);
+        tokio::spawn(async move {
+            for i in 0..100 {
+                db.insert_one(TestStruct {
+                    foo: format!("during_{i}"),
+                })
+                .await
+                .unwrap();
+            }
+        })
+    };
+    for _ in 0..5 {
+        db.compact().await.unwrap();
+    }
+    writer.await.unwrap();
+    drop(db);
+
+    let db = RonDb::open::<TestStruct>(config).await.unwrap();
+    assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), 600);
+}
+
+// ── durability modes ──────────────────────────────────────────────────────────
+
+async fn round_trip_with(mode: SyncMode) {
+    let tmp = tempfile::tempdir().unwrap();
+    let config = DbConfig::new("db").dir(tmp.path()).sync_mode(mode);
+    {
+        let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        for i in 0..10 {
+            db.insert_one(TestStruct { foo: i.to_string() })
+                .await
+                .unwrap();
+        }
+        db.close().await.unwrap();
+    }
+    let db = RonDb::open::<TestStruct>(config).await.unwrap();
+    assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), 10);
+}
+
+#[tokio::test]
+async fn every_sync_mode_round_trips() {
+    round_trip_with(SyncMode::Always).await;
+    round_trip_with(SyncMode::EveryN(4)).await;
+    round_trip_with(SyncMode::Interval(Duration::from_millis(5))).await;
+    round_trip_with(SyncMode::Never).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_writers_share_group_commits() {
+    let tmp = tempfile::tempdir().unwrap();
+    let config = DbConfig::new("db").dir(tmp.path());
+    let db = std::sync::Arc::new(RonDb::open::<TestStruct>(config.clone()).await.unwrap());
+    let writers: Vec<_> = (0..8)
+        .map(|w| {
+            let db = std::sync::Arc::clone(&db);
+            tokio::spawn(async move {
+                for i in 0..25 {
+                    db.insert_one(TestStruct {
+                        foo: format!("{w}_{i}"),
+                    })
+                    .await
+                    .unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.await.unwrap();
+    }
+    drop(db);
+
+    let db = RonDb::open::<TestStruct>(config).await.unwrap();
+    assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), 200);
+}
+
+#[tokio::test]
+async fn flush_is_a_no_op_for_mem_db() {
+    let db = MemDb::new::<TestStruct>("_").await.unwrap();
+    db.insert_one(TestStruct { foo: "a".into() }).await.unwrap();
+    db.flush().await.unwrap();
+}
+
+// ── compression ───────────────────────────────────────────────────────────────
+
+#[cfg(any(feature = "lz4_compression", feature = "zstd_compression"))]
+mod compression {
+    use super::*;
+    use reddb::{Compression, JsonDb};
+
+    fn verbose(i: usize) -> UserRec {
+        UserRec {
+            name: format!("user number {i} with a fairly long and repetitive name"),
+            role: "administrator administrator administrator".into(),
+        }
+    }
+
+    async fn file_size_with(compression: Compression) -> u64 {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path()).compression(compression);
+        {
+            let db = JsonDb::open::<UserRec>(config.clone()).await.unwrap();
+            db.insert((0..50).map(verbose).collect()).await.unwrap();
+        }
+        let db = JsonDb::open::<UserRec>(config).await.unwrap();
+        let all = db.find_all::<UserRec>().await.unwrap();
+        assert_eq!(all.len(), 50);
+        assert!(all.iter().any(|d| d.data == verbose(7)));
+        db.stats().await.unwrap().file_size_bytes
+    }
+
+    #[cfg(feature = "lz4_compression")]
+    #[tokio::test]
+    async fn lz4_round_trips_and_shrinks_the_file() {
+        let plain = file_size_with(Compression::None).await;
+        assert!(file_size_with(Compression::Lz4).await < plain);
+    }
+
+    #[cfg(feature = "zstd_compression")]
+    #[tokio::test]
+    async fn zstd_round_trips_and_shrinks_the_file() {
+        let plain = file_size_with(Compression::None).await;
+        assert!(file_size_with(Compression::Zstd(3)).await < plain);
+    }
+
+    #[cfg(feature = "zstd_compression")]
+    #[tokio::test]
+    async fn file_keeps_its_codec_until_compaction_recompresses_it() {
+        let tmp = tempfile::tempdir().unwrap();
+        let zstd = DbConfig::new("db")
+            .dir(tmp.path())
+            .compaction_ratio(100.0)
+            .compression(Compression::Zstd(3));
+        {
+            let db = JsonDb::open::<UserRec>(zstd.clone()).await.unwrap();
+            db.insert((0..20).map(verbose).collect()).await.unwrap();
+        }
+
+        // Reopened without compression: the header still says zstd, so the
+        // existing records decode and new ones are appended with zstd.
+        let plain = zstd.clone().compression(Compression::None);
+        let db = JsonDb::open::<UserRec>(plain.clone()).await.unwrap();
+        db.insert_one(verbose(20)).await.unwrap();
+        assert_eq!(db.find_all::<UserRec>().await.unwrap().len(), 21);
+
+        let compressed = db.stats().await.unwrap().file_size_bytes;
+        db.compact().await.unwrap();
+        assert!(db.stats().await.unwrap().file_size_bytes > compressed);
+        drop(db);
+
+        let db = JsonDb::open::<UserRec>(plain).await.unwrap();
+        assert_eq!(db.find_all::<UserRec>().await.unwrap().len(), 21);
+    }
+}
+
+// ── encryption ────────────────────────────────────────────────────────────────
+
+#[cfg(feature = "encryption")]
+mod encryption {
+    use super::*;
+    use reddb::{JsonDb, RedDb, SegmentedStorage};
+
+    const KEY: [u8; 32] = [0x42; 32];
+
+    fn secret(i: usize) -> UserRec {
+        UserRec {
+            name: format!("classified agent {i}"),
+            role: "spy".into(),
+        }
+    }
+
+    fn contains(path: &std::path::Path, needle: &str) -> bool {
+        fs::read(path)
+            .unwrap()
+            .windows(needle.len())
+            .any(|w| w == needle.as_bytes())
+    }
+
+    #[tokio::test]
+    async fn encrypted_file_round_trips_without_plaintext_on_disk() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path()).encryption_key(KEY);
+        {
+            let db = JsonDb::open::<UserRec>(config.clone()).await.unwrap();
+            db.insert((0..10).map(secret).collect()).await.unwrap();
+        }
+        assert!(!contains(&tmp.path().join("db.json"), "classified"));
+
+        let db = JsonDb::open::<UserRec>(config).await.unwrap();
+        let all = db.find_all::<UserRec>().await.unwrap();
+        assert_eq!(all.len(), 10);
+        assert!(all.iter().any(|d| d.data == secret(3)));
+    }
+
+    #[tokio::test]
+    async fn wrong_or_missing_key_is_reported_as_such() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        {
+            let db = JsonDb::open::<UserRec>(config.clone().encryption_key(KEY))
+                .await
+                .unwrap();
+            db.insert_one(secret(0)).await.unwrap();
+        }
+
+        let wrong = JsonDb::open::<UserRec>(config.clone().encryption_key([0x43; 32])).await;
+        assert!(matches!(wrong, Err(RedDbError::InvalidKey)));
+        let missing = JsonDb::open::<UserRec>(config).await;
+        assert!(matches!(missing, Err(RedDbError::EncryptionKeyRequired)));
+    }
+
+    #[tokio::test]
+    async fn plaintext_file_is_encrypted_when_opened_with_a_key() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        {
+            let db = JsonDb::open::<UserRec>(config.clone()).await.unwrap();
+            db.insert((0..5).map(secret).collect()).await.unwrap();
+        }
+        let path = tmp.path().join("db.json");
+        assert!(contains(&path, "classified"));
+
+        let encrypted = config.clone().encryption_key(KEY);
+        let db = JsonDb::open::<UserRec>(encrypted.clone()).await.unwrap();
+        assert_eq!(db.find_all::<UserRec>().await.unwrap().len(), 5);
+        drop(db);
+        assert!(!contains(&path, "classified"));
+        let missing = JsonDb::open::<UserRec>(config).await;
+        assert!(matches!(missing, Err(RedDbError::EncryptionKeyRequired)));
+    }
+
+    #[tokio::test]
+    async fn rotate_key_re_encrypts_under_the_new_key() {
+        let tmp = tempfile::tempdir().unwrap();
+        let old = DbConfig::new("db").dir(tmp.path()).encryption_key(KEY);
+        let new = old.clone().encryption_key([0x07; 32]);
+        {
+            let db = JsonDb::open::<UserRec>(old.clone()).await.unwrap();
+            db.insert((0..5).map(secret).collect()).await.unwrap();
+            db.rotate_key([0x07; 32]).await.unwrap();
+            // Written after the rotation, with the new key.
+            db.insert_one(secret(5)).await.unwrap();
+        }
+
+        let stale = JsonDb::open::<UserRec>(old).await;
+        assert!(matches!(stale, Err(RedDbError::InvalidKey)));
+        let db = JsonDb::open::<UserRec>(new).await.unwrap();
+        assert_eq!(db.find_all::<UserRec>().await.unwrap().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn segmented_storage_encrypts_but_cannot_rotate() {
+        type Db = RedDb<reddb::serializer::Json, SegmentedStorage<reddb::serializer::Json>>;
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path()).encryption_key(KEY);
+        {
+            let db = Db::open::<UserRec>(config.clone()).await.unwrap();
+            db.insert((0..5).map(secret).collect()).await.unwrap();
+            assert!(matches!(
+                db.rotate_key([0x07; 32]).await,
+                Err(RedDbError::Unsupported(_))
+            ));
+        }
+        for entry in fs::read_dir(tmp.path().join("db")).unwrap() {
+            assert!(!contains(&entry.unwrap().path(), "classified"));
+        }
+        let db = Db::open::<UserRec>(config).await.unwrap();
+        assert_eq!(db.find_all::<UserRec>().await.unwrap().len(), 5);
+    }
+}
+
+// ── file locking ──────────────────────────────────────────────────────────────
+
+mod locking {
+    use super::*;
+    use reddb::{RedDb, SegmentedStorage};
+
+    #[tokio::test]
+    async fn second_open_is_refused_until_the_first_is_dropped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        db.insert_one(TestStruct { foo: "a".into() }).await.unwrap();
+
+        match RonDb::open::<TestStruct>(config.clone()).await {
+            Err(RedDbError::Locked { pid, .. }) => assert_eq!(pid, Some(std::process::id())),
+            other => panic!("expected Locked, got {other:?}"),
+        }
+
+        drop(db);
+        let db = RonDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn segmented_storage_is_locked_too() {
+        type Db = RedDb<reddb::serializer::Ron, SegmentedStorage<reddb::serializer::Ron>>;
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let db = Db::open::<TestStruct>(config.clone()).await.unwrap();
+        assert!(matches!(
+            Db::open::<TestStruct>(config.clone()).await,
+            Err(RedDbError::Locked { .. })
+        ));
+        drop(db);
+        Db::open::<TestStruct>(config).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn background_tasks_do_not_keep_the_lock() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db")
+            .dir(tmp.path())
+            .sync_mode(SyncMode::Interval(Duration::from_secs(60)))
+            .auto_compaction(AutoCompaction::new(Duration::from_secs(60)));
+        let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        drop(db);
+        // Reopened before the dropped tasks get a chance to exit.
+        RonDb::open::<TestStruct>(config).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn close_releases_the_lock() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        db.close().await.unwrap();
+        RonDb::open::<TestStruct>(config).await.unwrap();
+    }
+}
+
+// ── read-only mode ────────────────────────────────────────────────────────────
+
+mod read_only {
+    use super::*;
+    use reddb::{RedDb, SegmentedStorage, Uuid};
+
+    type SegDb = RedDb<reddb::serializer::Ron, SegmentedStorage<reddb::serializer::Ron>>;
+
+    fn doc(i: usize) -> TestStruct {
+        TestStruct {
+            foo: format!("doc {i}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn readers_share_the_file_with_a_writer() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let writer = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        writer.insert((0..3).map(doc).collect()).await.unwrap();
+
+        let ro = config.read_only(true);
+        let first = RonDb::open::<TestStruct>(ro.clone()).await.unwrap();
+        let second = RonDb::open::<TestStruct>(ro).await.unwrap();
+        assert_eq!(first.find_all::<TestStruct>().await.unwrap().len(), 3);
+        assert_eq!(second.find_all::<TestStruct>().await.unwrap().len(), 3);
+
+        // Readers see the data as of opening.
+        writer.insert_one(doc(3)).await.unwrap();
+        assert_eq!(first.find_all::<TestStruct>().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn writes_are_rejected_without_touching_memory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let id = {
+            let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+            db.insert_one(doc(0)).await.unwrap().id
+        };
+
+        let db = RonDb::open::<TestStruct>(config.read_only(true))
+            .await
+            .unwrap();
+        let read_only = |r: Result<_, RedDbError>| matches!(r, Err(RedDbError::ReadOnly));
+        assert!(read_only(db.insert_one(doc(1)).await.map(|_| ())));
+        assert!(read_only(db.update_one(&id, doc(2)).await.map(|_| ())));
+        assert!(read_only(
+            db.delete_one::<TestStruct>(&id).await.map(|_| ())
+        ));
+        let mut tx = db.begin();
+        tx.insert_one(doc(3)).unwrap();
+        assert!(read_only(tx.commit().await));
+        assert!(read_only(db.compact().await));
+        assert_eq!(
+            db.find_all::<TestStruct>().await.unwrap(),
+            vec![Document::new(id, doc(0))]
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_database_is_not_created() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path()).read_only(true);
+        assert!(matches!(
+            RonDb::open::<TestStruct>(config.clone()).await,
+            Err(RedDbError::Io(_))
+        ));
+        assert!(matches!(
+            SegDb::open::<TestStruct>(config).await,
+            Err(RedDbError::Io(_))
+        ));
+        assert!(!tmp.path().join("db.ron").exists());
+        assert!(!tmp.path().join("db").exists());
+    }
+
+    #[tokio::test]
+    async fn load_neither_compacts_nor_repairs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path()).compaction_ratio(100.0);
+        {
+            let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+            let id = db.insert_one(doc(0)).await.unwrap().id;
+            for i in 1..20 {
+                db.update_one(&id, doc(i)).await.unwrap();
+            }
+        }
+        let path = tmp.path().join("db.ron");
+        // A record the writer is still in the middle of appending.
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.extend_from_slice(&[9, 0, 0, 0, 1]);
+        fs::write(&path, &bytes).unwrap();
+
+        let ro = config.compaction_ratio(1.0).read_only(true);
+        let db = RonDb::open::<TestStruct>(ro).await.unwrap();
+        assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), 1);
+        assert_eq!(db.stats().await.unwrap().truncated_tail_bytes, 5);
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+    }
+
+    #[tokio::test]
+    async fn segmented_readers_never_see_a_checkpoint_half_done() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path()).segment_size(512);
+        let writer = SegDb::open::<TestStruct>(config.clone()).await.unwrap();
+        let ids: Vec<Uuid> = writer
+            .insert((0..10).map(doc).collect())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|d| d.id)
+            .collect();
+
+        let checkpoints = async {
+            for round in 0..20 {
+                for id in &ids {
+                    writer.update_one(id, doc(round)).await.unwrap();
+                }
+                writer.compact().await.unwrap();
+            }
+        };
+        let ro = config.read_only(true);
+        let readers = async {
+            for _ in 0..20 {
+                let reader = SegDb::open::<TestStruct>(ro.clone()).await.unwrap();
+                assert_eq!(reader.find_all::<TestStruct>().await.unwrap().len(), 10);
+                assert!(matches!(
+                    reader.insert_one(doc(0)).await,
+                    Err(RedDbError::ReadOnly)
+                ));
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::join!(checkpoints, readers);
+    }
+}
+
+mod backup {
+    use super::*;
+    use reddb::{JsonDb, RedDb, SegmentedStorage};
+    use std::sync::Arc;
+
+    type SegDb = RedDb<reddb::serializer::Ron, SegmentedStorage<reddb::serializer::Ron>>;
+
+    fn doc(i: usize) -> TestStruct {
+        TestStruct {
+            foo: format!("doc {i}"),
+        }
+    }
+
+    async fn sorted(db: &RonDb) -> Vec<String> {
+        let mut docs: Vec<String> = db
+            .find_all::<TestStruct>()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|d| d.data.foo)
+            .collect();
+        docs.sort();
+        docs
+    }
+
+    #[tokio::test]
+    async fn restore_brings_back_the_backed_up_state() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let dest = tmp.path().join("db.backup");
+        let expected = {
+            let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+            db.insert((0..5).map(doc).collect()).await.unwrap();
+            let report = db.backup_to(&dest).await.unwrap();
+            assert!(!report.incremental);
+            assert_eq!(report.records, 5);
+            let expected = sorted(&db).await;
+            db.insert_one(doc(99)).await.unwrap();
+            expected
+        };
+
+        RonDb::restore_from::<TestStruct>(&dest, config.clone())
+            .await
+            .unwrap();
+        let db = RonDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(sorted(&db).await, expected);
+    }
+
+    #[tokio::test]
+    async fn incremental_backup_appends_only_new_records() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dest = tmp.path().join("db.backup");
+        let db = RonDb::open::<TestStruct>(DbConfig::new("db").dir(tmp.path()))
+            .await
+            .unwrap();
+        let docs = db.insert((0..5).map(doc).collect()).await.unwrap();
+        db.backup_to(&dest).await.unwrap();
+        let full_len = fs::metadata(&dest).unwrap().len();
+
+        db.insert_one(doc(5)).await.unwrap();
+        db.update_one(&docs[0].id, doc(50)).await.unwrap();
+        db.delete_one::<TestStruct>(&docs[1].id).await.unwrap();
+        let report = db.backup_incremental_to(&dest).await.unwrap();
+        assert!(report.incremental);
+        assert_eq!(report.records, 3);
+        assert!(fs::metadata(&dest).unwrap().len() > full_len);
+
+        let restored = DbConfig::new("restored").dir(tmp.path());
+        RonDb::restore_from::<TestStruct>(&dest, restored.clone())
+            .await
+            .unwrap();
+        let copy = RonDb::open::<TestStruct>(restored).await.unwrap();
+        assert_eq!(sorted(&copy).await, sorted(&db).await);
+    }
+
+    #[tokio::test]
+    async fn incremental_backup_falls_back_to_full() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dest = tmp.path().join("db.backup");
+        let other = tmp.path().join("other.backup");
+        let db = RonDb::open::<TestStruct>(DbConfig::new("db").dir(tmp.path()))
+            .await
+            .unwrap();
+        db.insert((0..3).map(doc).collect()).await.unwrap();
+
+        // Nothing to continue yet.
+        assert!(!db.backup_incremental_to(&dest).await.unwrap().incremental);
+        assert!(db.backup_incremental_to(&dest).await.unwrap().incremental);
+
+        // Compaction drops the records the backup would need.
+        db.insert_one(doc(3)).await.unwrap();
+        db.compact().await.unwrap();
+        let report = db.backup_incremental_to(&dest).await.unwrap();
+        assert!(!report.incremental);
+        assert_eq!(report.records, 4);
+
+        // The file was changed behind the handle's back.
+        let mut bytes = fs::read(&dest).unwrap();
+        bytes.push(0);
+        fs::write(&dest, bytes).unwrap();
+        assert!(!db.backup_incremental_to(&dest).await.unwrap().incremental);
+
+        // Only the last destination is continued.
+        db.backup_to(&other).await.unwrap();
+        assert!(!db.backup_incremental_to(&dest).await.unwrap().incremental);
+    }
+
+    #[tokio::test]
+    async fn backup_taken_during_writes_is_consistent() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dest = tmp.path().join("db.backup");
+        let db = Arc::new(
+            RonDb::open::<TestStruct>(
+                DbConfig::new("db")
+                    .dir(tmp.path())
+                    .write_order(WriteOrder::FileFirst),
+            )
+            .await
+            .unwrap(),
+        );
+        let writer = {
+            let db = Arc::clone(&db);
+            tokio::spawn(async move {
+                for i in 0..200 {
+                    db.insert_one(doc(i)).await.unwrap();
+                }
+            })
+        };
+        let mut restored = 0;
+        while !writer.is_finished() {
+            db.backup_incremental_to(&dest).await.unwrap();
+            let config = DbConfig::new(format!("r{restored}")).dir(tmp.path());
+            RonDb::restore_from::<TestStruct>(&dest, config.clone())
+                .await
+                .unwrap();
+            let copy = RonDb::open::<TestStruct>(config).await.unwrap();
+            let count = copy.find_all::<TestStruct>().await.unwrap().len();
+            // Inserts are in order, so a consistent copy holds a prefix.
+            let expected: Vec<String> = (0..count).map(|i| doc(i).foo).collect();
+            let mut got = sorted(&copy).await;
+            got.sort_by_key(|foo| foo[4..].parse::<usize>().unwrap());
+            assert_eq!(got, expected);
+            restored += 1;
+        }
+        writer.await.unwrap();
+        db.backup_incremental_to(&dest).await.unwrap();
+        let config = DbConfig::new("final").dir(tmp.path());
+        RonDb::restore_from::<TestStruct>(&dest, config.clone())
+            .await
+            .unwrap();
+        let copy = RonDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(sorted(&copy).await, sorted(&db).await);
+    }
+
+    #[tokio::test]
+    async fn invalid_backups_leave_the_live_file_alone() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let dest = tmp.path().join("db.backup");
+        {
+            let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+            db.insert((0..3).map(doc).collect()).await.unwrap();
+            db.backup_to(&dest).await.unwrap();
+        }
+        let live = fs::read(tmp.path().join("db.ron")).unwrap();
+
+        // Documents of another type.
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        struct Other {
+            bar: u32,
+        }
+        assert!(matches!(
+            RonDb::restore_from::<Other>(&dest, config.clone()).await,
+            Err(RedDbError::Deserialize(_))
+        ));
+
+        // Another serializer.
+        assert!(
+            JsonDb::restore_from::<TestStruct>(&dest, DbConfig::new("db").dir(tmp.path()))
+                .await
+                .is_err()
+        );
+
+        // A damaged record followed by intact ones.
+        let mut bytes = fs::read(&dest).unwrap();
+        bytes[55] ^= 0xff;
+        fs::write(&dest, bytes).unwrap();
+        assert!(matches!(
+            RonDb::restore_from::<TestStruct>(&dest, config).await,
+            Err(RedDbError::CorruptRecord(_))
+        ));
+
+        assert_eq!(fs::read(tmp.path().join("db.ron")).unwrap(), live);
+    }
+
+    #[tokio::test]
+    async fn restore_is_refused_while_the_database_is_open() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let dest = tmp.path().join("db.backup");
+        let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        db.insert_one(doc(0)).await.unwrap();
+        db.backup_to(&dest).await.unwrap();
+        assert!(matches!(
+            RonDb::restore_from::<TestStruct>(&dest, config.clone()).await,
+            Err(RedDbError::Locked { .. })
+        ));
+        assert!(matches!(
+            RonDb::restore_from::<TestStruct>(&dest, config.read_only(true)).await,
+            Err(RedDbError::ReadOnly)
+        ));
+    }
+
+    #[tokio::test]
+    async fn mem_db_does_not_back_up() {
+        let db = MemDb::new::<TestStruct>("_").await.unwrap();
+        assert!(matches!(
+            db.backup_to("unused.backup").await,
+            Err(RedDbError::Unsupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn segmented_backup_follows_segments_and_checkpoints() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path()).segment_size(256);
+        let dest = tmp.path().join("db.backup");
+        let db = SegDb::open::<TestStruct>(config.clone()).await.unwrap();
+        db.insert((0..5).map(doc).collect()).await.unwrap();
+        db.backup_to(&dest).await.unwrap();
+
+        // Spans several segment rollovers.
+        for i in 5..30 {
+            db.insert_one(doc(i)).await.unwrap();
+        }
+        let report = db.backup_incremental_to(&dest).await.unwrap();
+        assert!(report.incremental);
+        assert_eq!(report.records, 25);
+
+        // A checkpoint deletes the segments the backup continues from.
+        db.insert_one(doc(30)).await.unwrap();
+        db.compact().await.unwrap();
+        assert!(!db.backup_incremental_to(&dest).await.unwrap().incremental);
+        let expected = db.find_all::<TestStruct>().await.unwrap().len();
+        drop(db);
+
+        let restored = DbConfig::new("restored").dir(tmp.path());
+        SegDb::restore_from::<TestStruct>(&dest, restored.clone())
+            .await
+            .unwrap();
+        let copy = SegDb::open::<TestStruct>(restored).await.unwrap();
+        assert_eq!(copy.find_all::<TestStruct>().await.unwrap().len(), expected);
+
+        // Restoring over an existing directory replaces its files.
+        {
+            let db = SegDb::open::<TestStruct>(config.clone()).await.unwrap();
+            db.insert((100..110).map(doc).collect()).await.unwrap();
+        }
+        SegDb::restore_from::<TestStruct>(&dest, config.clone())
+            .await
+            .unwrap();
+        let db = SegDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), expected);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[tokio::test]
+    async fn encrypted_backup_needs_the_key_to_restore() {
+        let tmp = tempfile::tempdir().unwrap();
+        let key = [0x42; 32];
+        let config = DbConfig::new("db").dir(tmp.path()).encryption_key(key);
+        let dest = tmp.path().join("db.backup");
+        {
+            let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+            db.insert_one(doc(0)).await.unwrap();
+            db.backup_to(&dest).await.unwrap();
+        }
+        let plain = DbConfig::new("plain").dir(tmp.path());
+        assert!(matches!(
+            RonDb::restore_from::<TestStruct>(&dest, plain).await,
+            Err(RedDbError::EncryptionKeyRequired)
+        ));
+        RonDb::restore_from::<TestStruct>(&dest, config.clone())
+            .await
+            .unwrap();
+        let db = RonDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(sorted(&db).await, vec![doc(0).foo]);
+    }
+}
+
+mod point_in_time {
+    use super::*;
+    use reddb::{AsOf, RedDb, SegmentedStorage};
+    use std::time::SystemTime;
+
+    type SegDb = RedDb<reddb::serializer::Ron, SegmentedStorage<reddb::serializer::Ron>>;
+
+    fn doc(name: &str) -> TestStruct {
+        TestStruct {
+            foo: name.to_string(),
+        }
+    }
+
+    async fn names<SE, ST>(db: &RedDb<SE, ST>) -> Vec<String>
+    where
+        SE: reddb::serializer::Serializer + std::fmt::Debug,
+        ST: reddb::storage::Storage + std::fmt::Debug + Send + Sync + 'static,
+    {
+        let mut names: Vec<String> = db
+            .find_all::<TestStruct>()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|d| d.data.foo)
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn open_as_of_replays_up_to_the_sequence_number() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        let a = db.insert_one(doc("a")).await.unwrap();
+        db.insert_one(doc("b")).await.unwrap();
+        db.delete_one::<TestStruct>(&a.id).await.unwrap();
+        db.insert_one(doc("c")).await.unwrap();
+        assert_eq!(db.stats().await.unwrap().last_lsn, 4);
+        let path = tmp.path().join("db.ron");
+        let len = fs::metadata(&path).unwrap().len();
+
+        for (lsn, expected) in [
+            (0, vec![]),
+            (2, vec!["a", "b"]),
+            (3, vec!["b"]),
+            (9, vec!["b", "c"]),
+        ] {
+            let past = RonDb::open_as_of::<TestStruct>(config.clone(), AsOf::Lsn(lsn))
+                .await
+                .unwrap();
+            assert_eq!(names(&past).await, expected);
+            assert_eq!(
+                past.stats().await.unwrap().last_lsn,
+                lsn.min(4),
+                "as of {lsn}"
+            );
+        }
+
+        let past = RonDb::open_as_of::<TestStruct>(config, AsOf::Lsn(2))
+            .await
+            .unwrap();
+        assert!(matches!(
+            past.insert_one(doc("d")).await,
+            Err(RedDbError::ReadOnly)
+        ));
+        assert!(matches!(past.compact().await, Err(RedDbError::ReadOnly)));
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        // The writer is unaffected and keeps numbering from where it was.
+        db.insert_one(doc("d")).await.unwrap();
+        assert_eq!(db.stats().await.unwrap().last_lsn, 5);
+    }
+
+    #[tokio::test]
+    async fn open_as_of_a_time_stops_at_records_written_later() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        db.insert_one(doc("before")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let point = SystemTime::now();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        db.delete_where::<TestStruct, _>(|_| true).await.unwrap();
+        db.insert_one(doc("after")).await.unwrap();
+
+        let past = RonDb::open_as_of::<TestStruct>(config.clone(), AsOf::Time(point))
+            .await
+            .unwrap();
+        assert_eq!(names(&past).await, vec!["before"]);
+        let now = RonDb::open_as_of::<TestStruct>(config, AsOf::Time(SystemTime::now()))
+            .await
+            .unwrap();
+        assert_eq!(names(&now).await, vec!["after"]);
+    }
+
+    #[tokio::test]
+    async fn history_before_a_compaction_is_unavailable() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        db.insert((0..3).map(|i| doc(&i.to_string())).collect())
+            .await
+            .unwrap();
+        db.compact().await.unwrap();
+        db.insert_one(doc("3")).await.unwrap();
+
+        assert!(matches!(
+            RonDb::open_as_of::<TestStruct>(config.clone(), AsOf::Lsn(2)).await,
+            Err(RedDbError::HistoryUnavailable(3))
+        ));
+        let past = RonDb::open_as_of::<TestStruct>(config, AsOf::Lsn(3))
+            .await
+            .unwrap();
+        assert_eq!(names(&past).await, vec!["0", "1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn export_writes_the_past_state_to_a_new_database() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        {
+            let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+            db.insert_one(doc("good")).await.unwrap();
+            db.update_where::<TestStruct, _>(|_| true)
+                .exec(|_| doc("bad"))
+                .await
+                .unwrap();
+        }
+        let original = fs::read(tmp.path().join("db.ron")).unwrap();
+
+        let past = RonDb::open_as_of::<TestStruct>(config.clone(), AsOf::Lsn(1))
+            .await
+            .unwrap();
+        assert!(matches!(
+            past.export_to(config.clone()).await,
+            Err(RedDbError::Io(_))
+        ));
+        let recovered = DbConfig::new("recovered").dir(tmp.path());
+        past.export_to(recovered.clone()).await.unwrap();
+        assert_eq!(fs::read(tmp.path().join("db.ron")).unwrap(), original);
+
+        let db = RonDb::open::<TestStruct>(recovered).await.unwrap();
+        assert_eq!(names(&db).await, vec!["good"]);
+        assert_eq!(db.stats().await.unwrap().last_lsn, 0);
+        db.insert_one(doc("more")).await.unwrap();
+        assert_eq!(db.stats().await.unwrap().last_lsn, 1);
+    }
+
+    #[tokio::test]
+    async fn segmented_database_opens_as_of_a_point_across_segments() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path()).segment_size(64);
+        let db = SegDb::open::<TestStruct>(config.clone()).await.unwrap();
+        db.insert((0..2).map(|i| doc(&i.to_string())).collect())
+            .await
+            .unwrap();
+        db.compact().await.unwrap();
+        for i in 2..6 {
+            db.insert_one(doc(&i.to_string())).await.unwrap();
+        }
+        assert_eq!(db.stats().await.unwrap().last_lsn, 6);
+
+        let past = SegDb::open_as_of::<TestStruct>(config.clone(), AsOf::Lsn(4))
+            .await
+            .unwrap();
+        assert_eq!(names(&past).await, vec!["0", "1", "2", "3"]);
+        assert_eq!(past.stats().await.unwrap().last_lsn, 4);
+        assert!(matches!(
+            SegDb::open_as_of::<TestStruct>(config.clone(), AsOf::Lsn(1)).await,
+            Err(RedDbError::HistoryUnavailable(2))
+        ));
+
+        let recovered = DbConfig::new("recovered").dir(tmp.path());
+        past.export_to(recovered.clone()).await.unwrap();
+        drop(db);
+        let db = SegDb::open::<TestStruct>(recovered).await.unwrap();
+        assert_eq!(names(&db).await, vec!["0", "1", "2", "3"]);
+    }
+}
+
+#[cfg(feature = "paged")]
+mod paged {
+    use super::*;
+    use reddb::{MemStorage, Paging, RedDb, SegmentedStorage};
+
+    type SegDb = RedDb<reddb::serializer::Ron, SegmentedStorage<reddb::serializer::Ron>>;
+
+    fn user(i: usize) -> UserRec {
+        UserRec {
+            name: format!("user {i}"),
+            role: if i.is_multiple_of(3) {
+                "admin"
+            } else {
+                "guest"
+            }
+            .into(),
+        }
+    }
+
+    /// A cache too small for more than a couple of payloads, so most reads
+    /// go to the file.
+    fn paged(config: DbConfig) -> DbConfig {
+        config.paged(Paging::new(64))
+    }
+
+    async fn names(db: &RonDb) -> Vec<String> {
+        let mut names: Vec<String> = db
+            .find_all::<UserRec>()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|d| d.data.name)
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn queries_and_indexes_work_on_paged_payloads() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = paged(DbConfig::new("db").dir(tmp.path()));
+        let db = RonDb::open::<UserRec>(config).await.unwrap();
+        let docs = db.insert((0..30).map(user).collect()).await.unwrap();
+        db.add_index::<UserRec, _>("role", |u| u.role.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            db.using_index::<UserRec>("role", "admin")
+                .await
+                .unwrap()
+                .len(),
+            10
+        );
+        let admins = db
+            .query::<UserRec>()
+            .filter(|u| u.role == "admin")
+            .count()
+            .await
+            .unwrap();
+        assert_eq!(admins, 10);
+        assert_eq!(db.find(&user(4)).await.unwrap().len(), 1);
+        assert_eq!(
+            db.find_one::<UserRec>(&docs[7].id).await.unwrap().data,
+            user(7)
+        );
+
+        db.update_one(&docs[1].id, user(0)).await.unwrap();
+        let promoted = db
+            .update_where::<UserRec, _>(|u| u.name == "user 2")
+            .exec(|u| UserRec {
+                role: "admin".into(),
+                ..u
+            })
+            .await
+            .unwrap();
+        assert_eq!(promoted, 1);
+        db.delete_one::<UserRec>(&docs[3].id).await.unwrap();
+        assert_eq!(db.delete(&user(6)).await.unwrap(), 1);
+
+        let mut tx = db.begin();
+        let added = tx.insert_one(user(100)).unwrap();
+        tx.update_one(&added.id, user(101)).unwrap();
+        tx.delete_one(&docs[9].id);
+        tx.commit().await.unwrap();
+
+        // 10 admins, minus 3, 6 and 9, plus 1 (as user 0) and 2.
+        assert_eq!(
+            db.using_index::<UserRec>("role", "admin")
+                .await
+                .unwrap()
+                .len(),
+            9
+        );
+        assert_eq!(
+            db.get::<UserRec>(&added.id).await.unwrap().unwrap().data,
+            user(101)
+        );
+        assert_eq!(db.find(&user(0)).await.unwrap().len(), 2);
+        assert_eq!(db.find_all::<UserRec>().await.unwrap().len(), 28);
+    }
+
+    #[tokio::test]
+    async fn paged_database_survives_compaction_and_reopen() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let expected = {
+            let db = RonDb::open::<UserRec>(paged(config.clone())).await.unwrap();
+            let docs = db.insert((0..20).map(user).collect()).await.unwrap();
+            for doc in &docs[..10] {
+                db.update_one(&doc.id, user(doc.data.name.len() * 100))
+                    .await
+                    .unwrap();
+            }
+            db.compact().await.unwrap();
+            // Records appended after the compaction land in the new file.
+            db.delete_one::<UserRec>(&docs[15].id).await.unwrap();
+            db.insert_one(user(50)).await.unwrap();
+            names(&db).await
+        };
+
+        let db = RonDb::open::<UserRec>(paged(config.clone())).await.unwrap();
+        assert_eq!(names(&db).await, expected);
+        drop(db);
+        // The file is the same as without paging.
+        let db = RonDb::open::<UserRec>(config).await.unwrap();
+        assert_eq!(names(&db).await, expected);
+    }
+
+    #[tokio::test]
+    async fn backup_and_export_read_the_payloads_back() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = RonDb::open::<UserRec>(paged(DbConfig::new("db").dir(tmp.path())))
+            .await
+            .unwrap();
+        db.insert((0..10).map(user).collect()).await.unwrap();
+        let expected = names(&db).await;
+
+        let dest = tmp.path().join("db.backup");
+        db.backup_to(&dest).await.unwrap();
+        let restored = DbConfig::new("restored").dir(tmp.path());
+        RonDb::restore_from::<UserRec>(&dest, restored.clone())
+            .await
+            .unwrap();
+        let copy = RonDb::open::<UserRec>(restored).await.unwrap();
+        assert_eq!(names(&copy).await, expected);
+
+        let exported = DbConfig::new("exported").dir(tmp.path());
+        db.export_to(exported.clone()).await.unwrap();
+        let copy = RonDb::open::<UserRec>(exported).await.unwrap();
+        assert_eq!(names(&copy).await, expected);
+    }
+
+    #[tokio::test]
+    async fn other_engines_refuse_paged_mode() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = paged(DbConfig::new("db").dir(tmp.path()));
+        let err = SegDb::open::<UserRec>(config.clone()).await.unwrap_err();
+        assert!(matches!(err, RedDbError::Unsupported("paged mode")));
+        let err = RedDb::<reddb::serializer::Ron, MemStorage>::open::<UserRec>(config)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RedDbError::Unsupported("paged mode")));
+    }
+}
+
+#[cfg(feature = "migrate")]
+mod format_upgrade {
+    use super::*;
+    use reddb::serializer::Ron;
+    use reddb::Uuid;
+
+    /// A version 2 file without checksums: a 32-byte header, then
+    /// `[u32 len][op][uuid][payload]` per record.
+    fn v2_file(records: &[(Uuid, &str)]) -> Vec<u8> {
+        let mut buf = b"REDDB\x00\x02\x00".to_vec();
+        buf.extend_from_slice(&2u16.to_le_bytes());
+        buf.push(1); // RON
+        buf.resize(32, 0);
+        for (id, payload) in records {
+            buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            buf.push(0x01);
+            buf.extend_from_slice(id.as_bytes());
+            buf.extend_from_slice(payload.as_bytes());
+        }
+        buf
+    }
+
+    #[tokio::test]
+    async fn version_2_file_opens_after_upgrade() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        fs::write(
+            tmp.path().join("db.ron"),
+            v2_file(&[(a, r#"(foo:"a")"#), (b, r#"(foo:"b")"#)]),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            RonDb::open::<TestStruct>(config.clone()).await,
+            Err(RedDbError::UnsupportedVersion(2))
+        ));
+        assert_eq!(reddb::migrate::upgrade_v2::<Ron>(&config).await.unwrap(), 2);
+
+        let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        assert_eq!(db.find_one::<TestStruct>(&b).await.unwrap().data.foo, "b");
+        db.insert_one(TestStruct { foo: "c".into() }).await.unwrap();
+        assert!(matches!(
+            reddb::migrate::upgrade_v2::<Ron>(&config).await,
+            Err(RedDbError::Locked { .. })
+        ));
+        drop(db);
+        assert_eq!(reddb::migrate::upgrade_v2::<Ron>(&config).await.unwrap(), 0);
+        let db = RonDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), 3);
+    }
+}
+
+// ── crash recovery ────────────────────────────────────────────────────────────
+
+#[tokio::test]
+async fn torn_trailing_record_is_truncated_on_open() {
+    let file = ".it_torn_tail.ron";
+    cleanup(file);
+
+    let id = {
+        let db = RonDb::new::<TestStruct>(".it_torn_tail").await.unwrap();
+        db.insert_one(TestStruct {
+            foo: "intact".into(),
+        })
+        .await
+        .unwrap()
+        .id
+    };
+    let intact_len = fs::metadata(file).unwrap().len();
+
+    // Simulate a crash halfway through appending a record.
+    let mut bytes = fs::read(file).unwrap();
+    bytes.extend_from_slice(&[40, 0, 0, 0, 0x01, 0xAB, 0xCD]);
+    fs::write(file, &bytes).unwrap();
+
+    let db = RonDb::new::<TestStruct>(".it_torn_tail").await.unwrap();
+    let stats = db.stats().await.unwrap();
+    assert_eq!(stats.truncated_tail_bytes, 7);
+    assert_eq!(stats.file_size_bytes, intact_len);
+    assert_eq!(
+        db.find_one::<TestStruct>(&id).await.unwrap().data.foo,
+        "intact"
+    );
+
+    // Appends continue cleanly after the truncated tail.
+    let next = db
+        .insert_one(TestStruct {
+            foo: "after".into(),
+        })
+        .await
+        .unwrap();
+    drop(db);
+    let db = RonDb::new::<TestStruct>(".it_torn_tail").await.unwrap();
+    assert_eq!(db.stats().await.unwrap().truncated_tail_bytes, 0);
+    assert!(db.get::<TestStruct>(&next.id).await.unwrap().is_some());
+
+    cleanup(file);
+}
+
+#[tokio::test]
+async fn corruption_in_the_middle_reports_offset() {
+    let file = ".it_corrupt_middle.ron";
+    cleanup(file);
+
+    {
+        let db = RonDb::new::<TestStruct>(".it_corrupt_middle")
+            .await
+            .unwrap();
+        db.insert_one(TestStruct { foo: "one".into() })
+            .await
+            .unwrap();
+        db.insert_one(TestStruct { foo: "two".into() })
+            .await
+            .unwrap();
+    }
+
+    // Flip a payload byte of the first record (header is 32 bytes, the
+    // record's fixed prefix another 21).
+    let mut bytes = fs::read(file).unwrap();
+    bytes[32 + 21] ^= 0xFF;
+    fs::write(file, &bytes).unwrap();
+
+    let err = RonDb::new::<TestStruct>(".it_corrupt_middle")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RedDbError::CorruptRecord(32)), "{err:?}");
+
+    cleanup(file);
+}
+
+#[tokio::test]
+async fn leftover_compaction_copy_is_removed_on_open() {
+    let tmp = tempfile::tempdir().unwrap();
+    let config = DbConfig::new("db").dir(tmp.path());
+    let id = {
+        let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        db.insert_one(TestStruct { foo: "kept".into() })
+            .await
+            .unwrap()
+            .id
+    };
+    // Simulate a crash halfway through writing the compacted copy.
+    let leftover = tmp.path().join("db.ron.tmp");
+    fs::write(&leftover, b"REDDB\0half-written").unwrap();
+
+    let db = RonDb::open::<TestStruct>(config).await.unwrap();
+    assert!(!leftover.exists());
+    assert_eq!(
+        db.find_one::<TestStruct>(&id).await.unwrap().data.foo,
+        "kept"
+    );
+    db.compact().await.unwrap();
+    assert!(!leftover.exists());
+}
+
+// ── storage backends ──────────────────────────────────────────────────────────
+
+#[cfg(feature = "conformance")]
+mod storage_conformance {
+    use super::*;
+    use reddb::serializer::{Ron, Serializer};
+    use reddb::storage::conformance::{self, Durability};
+    use reddb::storage::Storage;
+    use reddb::{FileStorage, MemStorage, RedDb, RedDbHM, SegmentedStorage, Uuid, WalOp};
+    use std::collections::HashMap;
+    use std::sync::{LazyLock, Mutex};
+
+    type Log = Vec<(WalOp, Uuid, Vec<u8>)>;
+
+    static LOGS: LazyLock<Mutex<HashMap<String, Log>>> = LazyLock::new(Default::default);
+
+    /// A backend defined outside the crate: one append-only record log per
+    /// database name, kept in a process-wide map so it survives reopening.
+    #[derive(Debug)]
+    struct SharedLog {
+        key: String,
+    }
+
+    impl SharedLog {
+        fn append(&self, records: impl IntoIterator<Item = (WalOp, Uuid, Vec<u8>)>) {
+            let mut logs = LOGS.lock().unwrap();
+            logs.entry(self.key.clone()).or_default().extend(records);
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Storage for SharedLog {
+        async fn new(config: &DbConfig) -> Result<Self, RedDbError> {
+            Ok(SharedLog {
+                key: config.file_stem().to_string_lossy().into_owned(),
+            })
+        }
+
+        #[allow(clippy::extra_unused_type_parameters)]
+        async fn load<T>(&self) -> Result<RedDbHM, RedDbError>
+        where
+            for<'de> T: Serialize + Deserialize<'de> + std::fmt::Debug + PartialEq + Send + Sync,
+        {
+            let logs = LOGS.lock().unwrap();
+            let mut map = RedDbHM::new();
+            for (op, id, payload) in logs.get(&self.key).into_iter().flatten() {
+                match op {
+                    WalOp::Delete => map.remove(id),
+                    _ => map.insert(*id, payload.clone()),
+                };
+            }
+            Ok(map)
+        }
+
+        async fn persist<T>(&self, records: &[Document<T>], op: WalOp) -> Result<(), RedDbError>
+        where
+            for<'de> T: Serialize + Deserialize<'de> + std::fmt::Debug + Send + Sync + Clone,
+        {
+            let encoded = records
+                .iter()
+                .map(|doc| {
+                    let payload = match op {
+                        WalOp::Delete => Vec::new(),
+                        _ => Ron
+                            .serialize(&doc.data)
+                            .map_err(|e| RedDbError::Serialize(e.to_string()))?,
+                    };
+                    Ok((op, doc.id, payload))
+                })
+                .collect::<Result<Vec<_>, RedDbError>>()?;
+            self.append(encoded);
+            Ok(())
+        }
+
+        async fn compact(&self, data: &RedDbHM) -> Result<(), RedDbError> {
+            let log = data
+                .iter()
+                .map(|(id, payload)| (WalOp::Insert, *id, payload.clone()))
+                .collect();
+            LOGS.lock().unwrap().insert(self.key.clone(), log);
+            Ok(())
+        }
+
+        async fn file_size(&self) -> Result<u64, RedDbError> {
+            let logs = LOGS.lock().unwrap();
+            let log = logs.get(&self.key).map(Vec::as_slice).unwrap_or_default();
+            Ok(log.iter().map(|(_, _, p)| 17 + p.len() as u64).sum())
+        }
+
+        async fn persist_raw(&self, records: &[(WalOp, Uuid, Vec<u8>)]) -> Result<(), RedDbError> {
+            self.append(records.iter().cloned());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn file_storage_conforms() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("conformance").dir(dir.path());
+        conformance::run::<FileStorage<Ron>>(&config, Durability::Persistent).await;
+    }
+
+    #[cfg(feature = "zstd_compression")]
+    #[tokio::test]
+    async fn compressed_file_storage_conforms() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("conformance")
+            .dir(dir.path())
+            .compression(reddb::Compression::Zstd(3));
+        conformance::run::<FileStorage<Ron>>(&config, Durability::Persistent).await;
+        conformance::run::<SegmentedStorage<Ron>>(&config, Durability::Persistent).await;
+    }
+
+    #[cfg(feature = "encryption")]
+    #[tokio::test]
+    async fn encrypted_file_storage_conforms() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("conformance")
+            .dir(dir.path())
+            .encryption_key([0x42; 32]);
+        conformance::run::<FileStorage<Ron>>(&config, Durability::Persistent).await;
+        conformance::run::<SegmentedStorage<Ron>>(&config, Durability::Persistent).await;
+    }
+
+    #[tokio::test]
+    async fn segmented_storage_conforms() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("conformance").dir(dir.path());
+        conformance::run::<SegmentedStorage<Ron>>(&config, Durability::Persistent).await;
+    }
+
+    #[tokio::test]
+    async fn mem_storage_conforms() {
+        let config = DbConfig::new("conformance");
+        conformance::run::<MemStorage>(&config, Durability::Volatile).await;
+    }
+
+    #[tokio::test]
+    async fn external_storage_conforms() {
+        let config = DbConfig::new("external_conformance");
+        conformance::run::<SharedLog>(&config, Durability::Persistent).await;
+    }
+
+    #[tokio::test]
+    async fn red_db_runs_on_external_storage() {
+        type LogDb = RedDb<Ron, SharedLog>;
+
+        let id = {
+            let db = LogDb::new::<TestStruct>("external_db").await.unwrap();
+            let doc = db
+                .insert_one(TestStruct {
+                    foo: "stored".into(),
+                })
+                .await
+                .unwrap();
+            db.update_one(
+                &doc.id,
+                TestStruct {
+                    foo: "replaced".into(),
+                },
+            )
+            .await
+            .unwrap();
+            doc.id
+        };
+
+        let db = LogDb::new::<TestStruct>("external_db").await.unwrap();
+        let replaced = TestStruct {
+            foo: "replaced".into(),
+        };
+        assert_eq!(db.find(&replaced).await.unwrap().len(), 1);
+        assert_eq!(
+            db.find_one::<TestStruct>(&id).await.unwrap().data.foo,
+            "replaced"
+        );
+    }
+}
+
+// ── segmented storage ───────────────────────────────────────────────────────
+
+mod segmented {
+    use super::*;
+    use reddb::serializer::Ron;
+    use reddb::{RedDb, SegmentedStorage};
+    use std::path::Path;
+
+    type SegmentedDb = RedDb<Ron, SegmentedStorage<Ron>>;
+
+    fn files_with_prefix(dir: &Path, prefix: &str) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn writes_roll_over_into_new_segments() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db")
+            .dir(tmp.path())
+            .segment_size(128)
+            .compaction_ratio(100.0);
+        {
+            let db = SegmentedDb::open::<TestStruct>(config.clone())
+                .await
+                .unwrap();
+            for i in 0..10 {
+                db.insert_one(TestStruct {
+                    foo: format!("doc_{i}"),
+                })
+                .await
+                .unwrap();
+            }
+        }
+        let db_dir = tmp.path().join("db");
+        assert!(files_with_prefix(&db_dir, "wal-").len() > 1);
+
+        let db = SegmentedDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), 10);
+    }
+
+    #[tokio::test]
+    async fn checkpoint_replaces_covered_segments_with_a_snapshot() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db")
+            .dir(tmp.path())
+            .segment_size(128)
+            .compaction_ratio(100.0);
+        let db_dir = tmp.path().join("db");
+        let kept = {
+            let db = SegmentedDb::open::<TestStruct>(config.clone())
+                .await
+                .unwrap();
+            let docs = db
+                .insert(
+                    (0..6)
+                        .map(|i| TestStruct {
+                            foo: format!("{i}"),
+                        })
+                        .collect(),
+                )
+                .await
+                .unwrap();
+            for doc in &docs[..5] {
+                db.delete_one::<TestStruct>(&doc.id).await.unwrap();
+            }
+            db.compact().await.unwrap();
+
+            assert_eq!(files_with_prefix(&db_dir, "snapshot-").len(), 1);
+            assert_eq!(files_with_prefix(&db_dir, "wal-").len(), 1);
+
+            db.insert_one(TestStruct {
+                foo: "after".into(),
+            })
+            .await
+            .unwrap();
+            docs[5].id
+        };
+
+        let db = SegmentedDb::open::<TestStruct>(config).await.unwrap();
+        let all = db.find_all::<TestStruct>().await.unwrap();
+        assert_eq!(all.len(), 2);
+        assert!(db.find_one::<TestStruct>(&kept).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn flush_covers_records_in_rolled_segments() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db")
+            .dir(tmp.path())
+            .segment_size(128)
+            .sync_mode(SyncMode::Never)
+            .compaction_ratio(100.0);
+        {
+            let db = SegmentedDb::open::<TestStruct>(config.clone())
+                .await
+                .unwrap();
+            for i in 0..10 {
+                db.insert_one(TestStruct { foo: i.to_string() })
+                    .await
+                    .unwrap();
+            }
+            db.flush().await.unwrap();
+        }
+        let db = SegmentedDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), 10);
+    }
+
+    #[tokio::test]
+    async fn leftovers_of_an_interrupted_checkpoint_are_removed_on_open() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path()).compaction_ratio(100.0);
+        let db_dir = tmp.path().join("db");
+        {
+            let db = SegmentedDb::open::<TestStruct>(config.clone())
+                .await
+                .unwrap();
+            db.insert_one(TestStruct { foo: "a".into() }).await.unwrap();
+            db.compact().await.unwrap();
+        }
+        // A snapshot that was never renamed into place, and a segment the
+        // completed snapshot already covers.
+        fs::write(db_dir.join("snapshot-00000000000000000009.tmp"), b"partial").unwrap();
+        fs::write(
+            db_dir.join("wal-00000000000000000001.ron"),
+            fs::read(db_dir.join("wal-00000000000000000002.ron")).unwrap(),
+        )
+        .unwrap();
+
+        let db = SegmentedDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), 1);
+        assert!(files_with_prefix(&db_dir, "snapshot-")
+            .iter()
+            .all(|n| !n.ends_with(".tmp")));
+        assert_eq!(
+            files_with_prefix(&db_dir, "wal-"),
+            vec!["wal-00000000000000000002.ron".to_string()]
+        );
+    }
+}
+
 // ── storage stats ─────────────────────────────────────────────────────────────
 
 #[tokio::test]
@@ -771,6 +2444,27 @@ async fn transaction_delete_survives_reopen() {
     cleanup(file);
 }
 
+#[tokio::test]
+async fn transaction_deletes_keep_the_order_of_the_rest() {
+    let db = MemDb::new::<TestStruct>("unused").await.unwrap();
+    let mut ids = Vec::new();
+    for foo in ["a", "b", "c", "d"] {
+        let doc = db.insert_one(TestStruct { foo: foo.into() }).await.unwrap();
+        ids.push(doc.id);
+    }
+
+    let mut tx = db.begin();
+    tx.delete_one(&ids[0]);
+    tx.delete_one(&ids[2]);
+    tx.update_one(&ids[0], TestStruct { foo: "a2".into() })
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    let all = db.find_all::<TestStruct>().await.unwrap();
+    let foos: Vec<&str> = all.iter().map(|d| d.data.foo.as_str()).collect();
+    assert_eq!(foos, ["b", "d", "a2"]);
+}
+
 // ── Cross-serializer persistence round-trip ───────────────────────────────────
 //
 // Each serializer gets the same four tests: insert / update / delete / compact,
@@ -873,6 +2567,9 @@ serializer_round_trip_tests!(bin, BinDb, "bin", "bin_ser");
 serializer_round_trip_tests!(json, JsonDb, "json", "json_ser");
 serializer_round_trip_tests!(ron, RonDb, "ron", "ron_ser");
 serializer_round_trip_tests!(yaml, YamlDb, "yaml", "yaml_ser");
+serializer_round_trip_tests!(msgpack, MsgPackDb, "msgpack", "msgpack_ser");
+serializer_round_trip_tests!(cbor, CborDb, "cbor", "cbor_ser");
+serializer_round_trip_tests!(postcard, PostcardDb, "postcard", "postcard_ser");
 
 // ── HashIndex ─────────────────────────────────────────────────────────────────
 
@@ -964,3 +2661,788 @@ async fn hash_index_stays_consistent_after_mutations() {
     let admins_after = db.using_index::<UserRec>("by_role", "admin").await.unwrap();
     assert_eq!(admins_after.len(), 2);
 }
+
+#[cfg(feature = "migrate")]
+mod conversion {
+    use super::*;
+    use reddb::migrate::convert;
+    use reddb::serializer::{Bin, Json};
+    use reddb::{BinDb, JsonDb};
+
+    #[tokio::test]
+    async fn json_database_converts_to_bin_keeping_ids() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let json = JsonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        let kept = json
+            .insert_one(TestStruct { foo: "kept".into() })
+            .await
+            .unwrap()
+            .id;
+        let gone = json
+            .insert_one(TestStruct { foo: "gone".into() })
+            .await
+            .unwrap()
+            .id;
+        json.delete_one::<TestStruct>(&gone).await.unwrap();
+
+        // The source may stay open: it is only read.
+        let count = convert::<TestStruct, Json, Bin>(&config, &config)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        assert!(tmp.path().join("db.bin").exists());
+
+        let bin = BinDb::open::<TestStruct>(config.clone()).await.unwrap();
+        assert_eq!(
+            bin.find_one::<TestStruct>(&kept).await.unwrap().data.foo,
+            "kept"
+        );
+        assert!(bin.find_one::<TestStruct>(&gone).await.is_err());
+        assert!(matches!(
+            convert::<TestStruct, Json, Bin>(&config, &config).await,
+            Err(RedDbError::Locked { .. })
+        ));
+        drop(bin);
+
+        let back = DbConfig::new("back").dir(tmp.path());
+        convert::<TestStruct, Bin, Json>(&config, &back)
+            .await
+            .unwrap();
+        let json = JsonDb::open::<TestStruct>(back).await.unwrap();
+        assert_eq!(
+            json.find_one::<TestStruct>(&kept).await.unwrap().data.foo,
+            "kept"
+        );
+    }
+
+    #[cfg(feature = "msgpack_ser")]
+    #[tokio::test]
+    async fn json_database_moves_to_msgpack() {
+        use reddb::serializer::MsgPack;
+        use reddb::MsgPackDb;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let json = JsonDb::open::<UserRec>(config.clone()).await.unwrap();
+        let users = vec![
+            UserRec {
+                name: "ann".into(),
+                role: "admin".into(),
+            },
+            UserRec {
+                name: "bob".into(),
+                role: "user".into(),
+            },
+        ];
+        let ids: Vec<_> = json
+            .insert(users.clone())
+            .await
+            .unwrap()
+            .iter()
+            .map(|d| d.id)
+            .collect();
+        drop(json);
+
+        let count = convert::<UserRec, Json, MsgPack>(&config, &config)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+        let json_len = fs::metadata(tmp.path().join("db.json")).unwrap().len();
+        let msgpack_len = fs::metadata(tmp.path().join("db.msgpack")).unwrap().len();
+        assert!(msgpack_len < json_len);
+
+        let db = MsgPackDb::open::<UserRec>(config).await.unwrap();
+        let all = db.find_all::<UserRec>().await.unwrap();
+        assert!(all.iter().map(|d| d.id).eq(ids));
+        assert!(all.iter().map(|d| &d.data).eq(users.iter()));
+    }
+
+    #[tokio::test]
+    async fn converting_onto_itself_is_refused() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let db = JsonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        db.insert_one(TestStruct { foo: "a".into() }).await.unwrap();
+        drop(db);
+        assert!(matches!(
+            convert::<TestStruct, Json, Json>(&config, &config).await,
+            Err(RedDbError::Io(_))
+        ));
+        let db = JsonDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), 1);
+    }
+}
+
+mod verify {
+    use super::*;
+    use reddb::serializer::Ron;
+    use reddb::storage::{inspect, Storage};
+    use reddb::{FileStorage, ProblemKind, RedDb, SegmentedStorage, Uuid, WalOp};
+
+    #[derive(Clone, Debug, Serialize, PartialEq, Deserialize)]
+    struct Other {
+        bar: u32,
+    }
+
+    /// Three documents, `(foo:"a")` to `(foo:"c")`, each in a 50-byte record.
+    async fn three(config: &DbConfig) -> Vec<Uuid> {
+        let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        let mut ids = Vec::new();
+        for name in ["a", "b", "c"] {
+            ids.push(
+                db.insert_one(TestStruct { foo: name.into() })
+                    .await
+                    .unwrap()
+                    .id,
+            );
+        }
+        ids
+    }
+
+    #[tokio::test]
+    async fn clean_database_verifies_clean() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        three(&config).await;
+        let db = RonDb::open::<TestStruct>(config).await.unwrap();
+        let report = db.verify::<TestStruct>().await.unwrap();
+        assert!(report.is_clean());
+        assert_eq!((report.records, report.documents), (3, 3));
+    }
+
+    #[tokio::test]
+    async fn payloads_of_another_type_are_reported() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let ids = three(&config).await;
+        let db = RonDb::open::<TestStruct>(config).await.unwrap();
+        let report = db.verify::<Other>().await.unwrap();
+        assert_eq!(report.problems.len(), 3);
+        assert_eq!(report.problems[1].id, Some(ids[1]));
+        assert_eq!(report.problems[1].offset, 32 + 50);
+        assert!(matches!(
+            report.problems[1].kind,
+            ProblemKind::Undeserializable(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn damage_is_reported_and_skipped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let ids = three(&config).await;
+        let path = tmp.path().join("db.ron");
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[32 + 50 + 45] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        // Opening stops at the damage; verification reads past it.
+        assert!(matches!(
+            RonDb::open::<TestStruct>(config.clone()).await,
+            Err(RedDbError::CorruptRecord(82))
+        ));
+        let report = inspect::verify_file(&path, &config).await.unwrap();
+        assert_eq!((report.records, report.documents), (2, 2));
+        assert_eq!(report.problems.len(), 1);
+        let problem = &report.problems[0];
+        assert_eq!((problem.offset, problem.len), (82, 50));
+        assert_eq!(problem.kind, ProblemKind::ChecksumMismatch);
+        assert_eq!(problem.file, path);
+        assert!(!report.problems.iter().any(|p| p.id == Some(ids[2])));
+    }
+
+    #[tokio::test]
+    async fn updates_and_deletes_of_unknown_documents_are_reported() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        three(&config).await;
+        let stranger = Uuid::new_v4();
+        {
+            let storage = FileStorage::<Ron>::new(&config).await.unwrap();
+            storage.load::<TestStruct>().await.unwrap();
+            storage
+                .persist_raw(&[
+                    (WalOp::Update, stranger, br#"(foo:"x")"#.to_vec()),
+                    (WalOp::Delete, Uuid::new_v4(), Vec::new()),
+                ])
+                .await
+                .unwrap();
+        }
+        let db = RonDb::open::<TestStruct>(config).await.unwrap();
+        let report = db.verify::<TestStruct>().await.unwrap();
+        let kinds: Vec<_> = report.problems.iter().map(|p| &p.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                &ProblemKind::UnknownDocument(WalOp::Update),
+                &ProblemKind::UnknownDocument(WalOp::Delete)
+            ]
+        );
+        assert_eq!(report.problems[0].id, Some(stranger));
+    }
+
+    #[tokio::test]
+    async fn segmented_storage_verifies_every_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path()).segment_size(64);
+        let db = RedDb::<Ron, SegmentedStorage<Ron>>::open::<TestStruct>(config)
+            .await
+            .unwrap();
+        for name in ["a", "b", "c"] {
+            db.insert_one(TestStruct { foo: name.into() })
+                .await
+                .unwrap();
+        }
+        db.compact().await.unwrap();
+        db.insert_one(TestStruct { foo: "d".into() }).await.unwrap();
+        let report = db.verify::<Other>().await.unwrap();
+        assert_eq!((report.records, report.documents), (4, 4));
+        let files: std::collections::HashSet<_> =
+            report.problems.iter().map(|p| p.file.clone()).collect();
+        assert_eq!((report.problems.len(), files.len()), (4, 2));
+    }
+
+    #[tokio::test]
+    async fn header_errors_say_what_is_wrong() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let path = tmp.path().join("db.ron");
+        fs::write(&path, b"REDDB").unwrap();
+        assert!(matches!(
+            RonDb::open::<TestStruct>(config.clone()).await,
+            Err(RedDbError::TruncatedHeader)
+        ));
+        fs::write(&path, [0u8; 40]).unwrap();
+        assert!(matches!(
+            inspect::verify_file(&path, &config).await,
+            Err(RedDbError::NotADatabase)
+        ));
+
+        fs::remove_file(&path).unwrap();
+        three(&config).await;
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[10] = 0; // JSON
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            RonDb::open::<TestStruct>(config.clone()).await,
+            Err(RedDbError::FormatMismatch {
+                found: reddb::serializer::FormatId::Json,
+                ..
+            })
+        ));
+        bytes[10] = 42;
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            inspect::verify_file(&path, &config).await,
+            Err(RedDbError::UnknownFormat(42))
+        ));
+    }
+}
+
+mod salvage {
+    use super::*;
+    use reddb::serializer::Ron;
+    use reddb::storage::Storage;
+    use reddb::{FileStorage, ProblemKind, RedDb, SegmentedStorage, Uuid, WalOp};
+    use std::fmt::Debug;
+
+    fn foo(s: &str) -> TestStruct {
+        TestStruct { foo: s.into() }
+    }
+
+    /// Inserts `(foo:"a")` to `(foo:"c")`, each in a 50-byte record, then
+    /// updates the second.
+    async fn written<ST>(db: &RedDb<Ron, ST>) -> Vec<Uuid>
+    where
+        ST: Storage + Debug + Send + Sync + 'static,
+    {
+        let mut ids = Vec::new();
+        for name in ["a", "b", "c"] {
+            ids.push(db.insert_one(foo(name)).await.unwrap().id);
+        }
+        db.update_one(&ids[1], foo("b2")).await.unwrap();
+        ids
+    }
+
+    #[tokio::test]
+    async fn damaged_record_is_quarantined_and_the_rest_recovered() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let ids = written(&RonDb::open::<TestStruct>(config.clone()).await.unwrap()).await;
+        let path = tmp.path().join("db.ron");
+        let mut bytes = fs::read(&path).unwrap();
+        let damaged = bytes[82..132].to_vec();
+        bytes[82 + 45] ^= 0xff; // payload of the insert of "b"
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            RonDb::open::<TestStruct>(config.clone()).await,
+            Err(RedDbError::CorruptRecord(82))
+        ));
+
+        let report = RonDb::salvage::<TestStruct>(config.clone()).await.unwrap();
+        assert_eq!((report.records, report.documents), (3, 3));
+        assert_eq!(report.quarantined.len(), 1);
+        let problem = &report.quarantined[0];
+        assert_eq!((problem.offset, problem.len), (82, 50));
+        assert_eq!(problem.kind, ProblemKind::ChecksumMismatch);
+        let quarantine = tmp.path().join("db.ron.quarantine");
+        assert_eq!(report.quarantine_file.as_ref(), Some(&quarantine));
+        let mut expected = damaged;
+        expected[45] ^= 0xff;
+        assert_eq!(fs::read(&quarantine).unwrap(), expected);
+
+        // The update of "b" outlived its insert and is kept.
+        let db = RonDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(
+            db.find_one::<TestStruct>(&ids[1]).await.unwrap().data,
+            foo("b2")
+        );
+        assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), 3);
+        let stats = db.stats().await.unwrap();
+        assert_eq!(stats.last_lsn, 4);
+        assert!(db.verify::<TestStruct>().await.unwrap().is_clean());
+    }
+
+    #[tokio::test]
+    async fn payloads_of_another_type_are_quarantined() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        written(&RonDb::open::<TestStruct>(config.clone()).await.unwrap()).await;
+        let stranger = Uuid::new_v4();
+        {
+            let storage = FileStorage::<Ron>::new(&config).await.unwrap();
+            storage.load::<TestStruct>().await.unwrap();
+            storage
+                .persist_raw(&[(WalOp::Insert, stranger, b"(bar:1)".to_vec())])
+                .await
+                .unwrap();
+        }
+        let (db, report) = RonDb::open_salvaged::<TestStruct>(config).await.unwrap();
+        assert_eq!(report.quarantined.len(), 1);
+        assert_eq!(report.quarantined[0].id, Some(stranger));
+        assert!(matches!(
+            report.quarantined[0].kind,
+            ProblemKind::Undeserializable(_)
+        ));
+        assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), 3);
+        assert!(db.get::<TestStruct>(&stranger).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn clean_database_is_compacted_without_quarantine() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        written(&RonDb::open::<TestStruct>(config.clone()).await.unwrap()).await;
+        let report = RonDb::salvage::<TestStruct>(config.clone()).await.unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.quarantine_file, None);
+        assert!(!tmp.path().join("db.ron.quarantine").exists());
+        let db = RonDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(db.stats().await.unwrap().file_size_bytes, 32 + 3 * 50 + 1);
+    }
+
+    #[tokio::test]
+    async fn open_database_is_not_salvaged() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let _db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        assert!(matches!(
+            RonDb::salvage::<TestStruct>(config).await,
+            Err(RedDbError::Locked { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn segmented_storage_is_salvaged_into_a_snapshot() {
+        type SegDb = RedDb<Ron, SegmentedStorage<Ron>>;
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let ids = written(&SegDb::open::<TestStruct>(config.clone()).await.unwrap()).await;
+        let dir = tmp.path().join("db");
+        let segment = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| p.file_name().unwrap().to_str().unwrap().starts_with("wal-"))
+            .unwrap();
+        let mut bytes = fs::read(&segment).unwrap();
+        bytes[32 + 45] ^= 0xff; // payload of the insert of "a"
+        fs::write(&segment, &bytes).unwrap();
+
+        let report = SegDb::salvage::<TestStruct>(config.clone()).await.unwrap();
+        assert_eq!(report.quarantined.len(), 1);
+        assert_eq!(report.quarantined[0].file, segment);
+        assert_eq!(
+            report.quarantine_file,
+            Some(tmp.path().join("db.quarantine"))
+        );
+        assert!(!segment.exists());
+        let db = SegDb::open::<TestStruct>(config).await.unwrap();
+        assert!(db.get::<TestStruct>(&ids[0]).await.unwrap().is_none());
+        assert_eq!(
+            db.find_one::<TestStruct>(&ids[1]).await.unwrap().data,
+            foo("b2")
+        );
+        assert_eq!(db.find_all::<TestStruct>().await.unwrap().len(), 2);
+    }
+}
+
+mod ordering {
+    use super::*;
+    use reddb::serializer::Ron;
+    use reddb::storage::inspect;
+    use reddb::{RedDb, SegmentedStorage, Uuid, WalOp};
+
+    fn foos(docs: &[Document<TestStruct>]) -> Vec<&str> {
+        docs.iter().map(|d| d.data.foo.as_str()).collect()
+    }
+
+    /// Inserts "a" to "e", updates "b", then deletes "c" and "d".
+    async fn churn<ST>(db: &RedDb<Ron, ST>) -> Vec<Uuid>
+    where
+        ST: reddb::storage::Storage + std::fmt::Debug + Send + Sync + 'static,
+    {
+        let names = ["a", "b", "c", "d", "e"];
+        let docs = db
+            .insert(
+                names
+                    .iter()
+                    .map(|n| TestStruct { foo: n.to_string() })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+        let ids: Vec<Uuid> = docs.iter().map(|d| d.id).collect();
+        db.update_one(&ids[1], TestStruct { foo: "B".into() })
+            .await
+            .unwrap();
+        db.delete_one::<TestStruct>(&ids[2]).await.unwrap();
+        db.delete_where::<TestStruct, _>(|t| t.foo == "d")
+            .await
+            .unwrap();
+        db.insert_one(TestStruct { foo: "f".into() }).await.unwrap();
+        ids
+    }
+
+    #[tokio::test]
+    async fn documents_stay_in_insertion_order() {
+        let db = MemDb::open::<TestStruct>(DbConfig::new("ordering"))
+            .await
+            .unwrap();
+        let values: Vec<TestStruct> = (0..100)
+            .map(|i| TestStruct {
+                foo: format!("{i:03}"),
+            })
+            .collect();
+        let ids: Vec<Uuid> = db
+            .insert(values.clone())
+            .await
+            .unwrap()
+            .iter()
+            .map(|d| d.id)
+            .collect();
+        db.update_one(&ids[10], TestStruct { foo: "ten".into() })
+            .await
+            .unwrap();
+        db.delete_where::<TestStruct, _>(|t| t.foo.ends_with('5'))
+            .await
+            .unwrap();
+        let mut expected = values;
+        expected[10].foo = "ten".into();
+        expected.retain(|t| !t.foo.ends_with('5'));
+
+        let all = db.find_all::<TestStruct>().await.unwrap();
+        assert!(all.iter().map(|d| &d.data).eq(expected.iter()));
+        let queried = db.query::<TestStruct>().all().await.unwrap();
+        assert!(queried.iter().map(|d| d.id).eq(all.iter().map(|d| d.id)));
+    }
+
+    #[tokio::test]
+    async fn order_survives_reopen_and_compaction() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        {
+            let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+            churn(&db).await;
+            let all = db.find_all::<TestStruct>().await.unwrap();
+            assert_eq!(foos(&all), ["a", "B", "e", "f"]);
+        }
+        let db = RonDb::open::<TestStruct>(config.clone()).await.unwrap();
+        assert_eq!(
+            foos(&db.find_all::<TestStruct>().await.unwrap()),
+            ["a", "B", "e", "f"]
+        );
+
+        db.compact().await.unwrap();
+        let mut written = Vec::new();
+        inspect::scan(tmp.path().join("db.ron"), &config, |r| {
+            assert_eq!(r.op, WalOp::Insert);
+            written.push(String::from_utf8(r.payload).unwrap());
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            written,
+            [
+                r#"(foo:"a")"#,
+                r#"(foo:"B")"#,
+                r#"(foo:"e")"#,
+                r#"(foo:"f")"#
+            ]
+        );
+        drop(db);
+        let db = RonDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(
+            foos(&db.find_all::<TestStruct>().await.unwrap()),
+            ["a", "B", "e", "f"]
+        );
+    }
+
+    #[tokio::test]
+    async fn segmented_storage_keeps_the_order() {
+        type SegDb = RedDb<Ron, SegmentedStorage<Ron>>;
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path()).segment_size(64);
+        {
+            let db = SegDb::open::<TestStruct>(config.clone()).await.unwrap();
+            churn(&db).await;
+        }
+        let db = SegDb::open::<TestStruct>(config.clone()).await.unwrap();
+        assert_eq!(
+            foos(&db.find_all::<TestStruct>().await.unwrap()),
+            ["a", "B", "e", "f"]
+        );
+        db.compact().await.unwrap();
+        drop(db);
+        let db = SegDb::open::<TestStruct>(config).await.unwrap();
+        assert_eq!(
+            foos(&db.find_all::<TestStruct>().await.unwrap()),
+            ["a", "B", "e", "f"]
+        );
+    }
+}
+
+#[cfg(feature = "json_ser")]
+mod custom_format {
+    use super::*;
+    use reddb::serializer::{FormatId, Json, Serializer};
+    use reddb::storage::inspect;
+    use reddb::{FileStorage, RedDb, SegmentedStorage};
+
+    /// A third-party serializer: JSON under its own format tag and extension.
+    #[derive(Debug, Default)]
+    struct Tagged<const TAG: u8>;
+
+    impl<const TAG: u8> Serializer for Tagged<TAG> {
+        fn format_id(&self) -> FormatId {
+            FormatId::Custom(TAG)
+        }
+
+        fn extension(&self) -> &'static str {
+            ".tagged"
+        }
+
+        fn serialize<T>(&self, val: &T) -> anyhow::Result<Vec<u8>>
+        where
+            T: Serialize + ?Sized,
+        {
+            Json.serialize(val)
+        }
+
+        fn deserialize<'de, T>(&self, val: &'de [u8]) -> anyhow::Result<T>
+        where
+            T: Deserialize<'de>,
+        {
+            Json.deserialize(val)
+        }
+    }
+
+    type TaggedDb<const TAG: u8> = RedDb<Tagged<TAG>, FileStorage<Tagged<TAG>>>;
+
+    #[tokio::test]
+    async fn custom_serializer_persists_under_its_own_extension() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        let id = {
+            let db = TaggedDb::<7>::open::<TestStruct>(config.clone())
+                .await
+                .unwrap();
+            db.insert_one(TestStruct { foo: "mine".into() })
+                .await
+                .unwrap()
+                .id
+        };
+        let path = tmp.path().join("db.tagged");
+        assert_eq!(inspect::format(&path).await.unwrap(), FormatId::Custom(7));
+        assert!(inspect::verify_file(&path, &config)
+            .await
+            .unwrap()
+            .is_clean());
+
+        let db = TaggedDb::<7>::open::<TestStruct>(config.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            db.find_one::<TestStruct>(&id).await.unwrap().data.foo,
+            "mine"
+        );
+        drop(db);
+
+        // Same extension, different format: the header tells them apart.
+        let err = TaggedDb::<8>::open::<TestStruct>(config).await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                RedDbError::FormatMismatch {
+                    expected: FormatId::Custom(8),
+                    found: FormatId::Custom(7),
+                }
+            ),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn any_db_does_not_open_custom_formats() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        TaggedDb::<7>::open::<TestStruct>(config).await.unwrap();
+        let err = reddb::AnyDb::open_path::<TestStruct>(
+            tmp.path().join("db.tagged"),
+            DbConfig::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(err, RedDbError::FormatNotEnabled(FormatId::Custom(7))),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn segmented_storage_uses_the_custom_extension() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        {
+            let db =
+                RedDb::<Tagged<7>, SegmentedStorage<Tagged<7>>>::open::<TestStruct>(config.clone())
+                    .await
+                    .unwrap();
+            db.insert_one(TestStruct { foo: "seg".into() })
+                .await
+                .unwrap();
+            db.compact().await.unwrap();
+        }
+        let names: Vec<String> = fs::read_dir(tmp.path().join("db"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| !name.ends_with(".lock"))
+            .collect();
+        assert!(!names.is_empty());
+        assert!(
+            names.iter().all(|name| name.ends_with(".tagged")),
+            "{names:?}"
+        );
+
+        let db = RedDb::<Tagged<7>, SegmentedStorage<Tagged<7>>>::open::<TestStruct>(config)
+            .await
+            .unwrap();
+        let all = db.find_all::<TestStruct>().await.unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].data.foo, "seg");
+    }
+}
+
+#[cfg(all(feature = "json_ser", feature = "bin_ser", feature = "yaml_ser"))]
+mod any_db {
+    use super::*;
+    use reddb::{AnyDb, BinDb, JsonDb};
+
+    #[tokio::test]
+    async fn opens_files_of_any_format_by_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let json = DbConfig::new("users").dir(tmp.path());
+        let bin = DbConfig::new("users").dir(tmp.path());
+        let json_id = {
+            let db = JsonDb::open::<TestStruct>(json.clone()).await.unwrap();
+            db.insert_one(TestStruct { foo: "json".into() })
+                .await
+                .unwrap()
+                .id
+        };
+        {
+            let db = BinDb::open::<TestStruct>(bin.clone()).await.unwrap();
+            db.insert_one(TestStruct { foo: "bin".into() })
+                .await
+                .unwrap();
+        }
+
+        let db = AnyDb::open_path::<TestStruct>(tmp.path().join("users.json"), DbConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            db.find_one::<TestStruct>(&json_id).await.unwrap().data.foo,
+            "json"
+        );
+        let added = db
+            .insert_one(TestStruct {
+                foo: "added".into(),
+            })
+            .await
+            .unwrap()
+            .id;
+        drop(db);
+        let db = JsonDb::open::<TestStruct>(json).await.unwrap();
+        assert_eq!(
+            db.find_one::<TestStruct>(&added).await.unwrap().data.foo,
+            "added"
+        );
+
+        let db = AnyDb::open_path::<TestStruct>(tmp.path().join("users.bin"), DbConfig::default())
+            .await
+            .unwrap();
+        db.add_index::<TestStruct, _>("foo", |t| t.foo.clone())
+            .await
+            .unwrap();
+        let found = db.using_index::<TestStruct>("foo", "bin").await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(db.verify::<TestStruct>().await.unwrap().is_clean());
+    }
+
+    #[tokio::test]
+    async fn path_must_exist_and_match_its_format() {
+        let tmp = tempfile::tempdir().unwrap();
+        let missing =
+            AnyDb::open_path::<TestStruct>(tmp.path().join("none.json"), DbConfig::default())
+                .await
+                .unwrap_err();
+        assert!(matches!(missing, RedDbError::Io(_)), "{missing:?}");
+        assert!(!tmp.path().join("none.json").exists());
+
+        let config = DbConfig::new("db").dir(tmp.path());
+        RonDb::open::<TestStruct>(config).await.unwrap();
+        let renamed = tmp.path().join("db.json");
+        fs::copy(tmp.path().join("db.ron"), &renamed).unwrap();
+        let err = AnyDb::open_path::<TestStruct>(&renamed, DbConfig::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("must end in .ron"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn open_without_a_path_needs_a_format() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = DbConfig::new("db").dir(tmp.path());
+        assert!(matches!(
+            AnyDb::open::<TestStruct>(config.clone()).await,
+            Err(RedDbError::Io(_))
+        ));
+        // With the format named, it opens and creates like a typed alias.
+        let db = AnyDb::open::<TestStruct>(config.format(reddb::serializer::FormatId::Yaml))
+            .await
+            .unwrap();
+        db.insert_one(TestStruct { foo: "y".into() }).await.unwrap();
+        assert!(tmp.path().join("db.yaml").exists());
+    }
+}